@@ -0,0 +1,130 @@
+//! Throughput of the channel registry under concurrent bulk operations, as
+//! done by the frontend when a whole substation is loaded.
+
+use std::sync::Arc;
+
+use channel_lib::utils::channels::{
+    source::Source,
+    state::{ChannelSpec, Channels},
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tauri::ipc::Channel;
+use tokio::runtime::Runtime;
+
+const SIZES: [usize; 2] = [100, 500];
+
+fn ids(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|i| format!("S{}.P{}", i / 50, i % 50))
+        .collect()
+}
+
+/// Registers `ids` concurrently, one task per channel.
+async fn register_all(channels: &Arc<Channels>, ids: &[String]) {
+    let handles: Vec<_> = ids
+        .iter()
+        .map(|id| {
+            let channels = channels.clone();
+            let id = id.clone();
+            tokio::spawn(async move {
+                let spec = ChannelSpec {
+                    source: Source::External,
+                    ..Default::default()
+                };
+                channels
+                    .register(id, Channel::new(|_| Ok(())), spec)
+                    .unwrap();
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.await.unwrap();
+    }
+}
+
+fn bench_register(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("register");
+
+    for size in SIZES {
+        let ids = ids(size);
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &ids, |b, ids| {
+            b.to_async(&runtime).iter(|| async {
+                let channels = Arc::new(Channels::default());
+                register_all(&channels, ids).await;
+                for id in ids {
+                    channels.remove(id);
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_status(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("status");
+
+    for size in SIZES {
+        let ids = ids(size);
+        let channels = Arc::new(Channels::default());
+        runtime.block_on(register_all(&channels, &ids));
+
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &ids, |b, ids| {
+            b.to_async(&runtime).iter(|| async {
+                let handles: Vec<_> = ids
+                    .iter()
+                    .map(|id| {
+                        let channels = channels.clone();
+                        let id = id.clone();
+                        tokio::spawn(async move {
+                            channels.control(&id).map(|control| control.is_paused())
+                        })
+                    })
+                    .collect();
+                for handle in handles {
+                    handle.await.unwrap();
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_pause_start(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("pause_start");
+
+    for size in SIZES {
+        let ids = ids(size);
+        let channels = Arc::new(Channels::default());
+        runtime.block_on(register_all(&channels, &ids));
+
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &ids, |b, ids| {
+            b.to_async(&runtime).iter(|| async {
+                let handles: Vec<_> = ids
+                    .iter()
+                    .map(|id| {
+                        let channels = channels.clone();
+                        let id = id.clone();
+                        tokio::spawn(async move {
+                            let control = channels.control(&id).unwrap();
+                            control.pause().await;
+                            control.start().await;
+                        })
+                    })
+                    .collect();
+                for handle in handles {
+                    handle.await.unwrap();
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_register, bench_status, bench_pause_start);
+criterion_main!(benches);
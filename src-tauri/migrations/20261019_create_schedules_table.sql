@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS schedules (
+    id TEXT PRIMARY KEY,
+    spec JSON NOT NULL,
+    created_at TEXT NOT NULL,
+    last_fired_at TEXT
+);
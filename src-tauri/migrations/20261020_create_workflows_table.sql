@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS workflows (
+    name TEXT PRIMARY KEY,
+    spec JSON NOT NULL
+);
@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS tasks (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    params JSON NOT NULL,
+    status TEXT NOT NULL,
+    submitted_at TEXT NOT NULL,
+    started_at TEXT,
+    finished_at TEXT,
+    duration_ms INTEGER,
+    error TEXT,
+    cancel_reason TEXT,
+    result JSON,
+    transitions JSON NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_tasks_submitted_at ON tasks (submitted_at);
@@ -0,0 +1 @@
+ALTER TABLE schedules ADD COLUMN resumed_at TEXT;
@@ -1,6 +1,8 @@
 mod commands;
+mod protocols;
 mod settings;
-mod utils;
+mod shutdown;
+pub mod utils;
 
 use tauri::Manager;
 
@@ -26,14 +28,63 @@ pub fn run() {
         .plugin(tauri_plugin_shell::init())
         .setup(|app| {
             tauri::async_runtime::block_on(async move {
-                app.manage(utils::channels::state::Channels::default());
-                app.manage(utils::tasks::state::Tasks::default());
+                let soe = utils::soe::state::Recorder::default();
+                let clock = utils::clock::SimClock::default();
+                app.manage(utils::channels::state::Channels::new(
+                    soe.clone(),
+                    clock.clone(),
+                ));
+                let (scheduler, fires) = utils::tasks::scheduler::Scheduler::new(clock.clone());
+                tauri::async_runtime::spawn(utils::tasks::scheduler::dispatch(
+                    app.handle().clone(),
+                    fires,
+                ));
+                app.manage(soe);
+                app.manage(clock);
+                app.manage(scheduler);
+                app.manage(shutdown::Shutdown::default());
+                let (tasks, history) =
+                    utils::tasks::state::Tasks::with_history(utils::tasks::state::MAX_RUNNING_JOBS);
+                app.manage(tasks);
+                app.manage(utils::tasks::history::History::start(
+                    app.handle().clone(),
+                    history,
+                ));
+                let executor = utils::tasks::executor::Executor::default();
+                executor.set_limit(utils::import::commands::READ_KIND, Some(2));
+                executor.set_limit(utils::export::commands::WRITE_KIND, Some(2));
+                app.manage(executor);
+                app.manage(utils::tasks::workflow::Workflows::default());
+                app.manage(protocols::c37118::state::PmuStreams::default());
+                app.manage(protocols::modbus::state::Pollers::default());
+                app.manage(protocols::modbus::state::Server::default());
+                app.manage(protocols::iec104::state::Server::default());
 
                 println!("-----------------------------------------------");
 
                 let settings_db = settings::database::state::DatabaseState::new(&app.handle())
                     .await
                     .expect("Failed to initialize settings db");
+                utils::tasks::history::restore(
+                    &app.state::<utils::tasks::state::Tasks>(),
+                    &*settings_db.lock().await,
+                )
+                .await
+                .expect("Failed to restore job history");
+                utils::tasks::scheduler::restore(
+                    &app.state::<utils::tasks::scheduler::Scheduler>(),
+                    &*settings_db.lock().await,
+                )
+                .await
+                .expect("Failed to restore schedules");
+                let seed = settings_db
+                    .lock()
+                    .await
+                    .get_setting::<u64>(utils::channels::source::SEED_SETTINGS_KEY)
+                    .await
+                    .expect("Failed to load the channels seed");
+                app.state::<utils::channels::state::Channels>()
+                    .set_seed(seed);
                 app.manage(settings_db);
 
                 println!("-----------------------------------------------");
@@ -55,8 +106,73 @@ pub fn run() {
             utils::channels::commands::start,
             utils::channels::commands::stop,
             utils::channels::commands::pause,
+            utils::channels::commands::set_faults,
+            utils::channels::commands::set_impairment,
             utils::channels::commands::get_status,
             utils::channels::commands::list_channels,
+            utils::channels::commands::start_matching,
+            utils::channels::commands::pause_matching,
+            utils::channels::commands::set_impairment_matching,
+            utils::channels::commands::stop_matching,
+            utils::channels::commands::unregister_matching,
+            utils::channels::commands::subscribe,
+            utils::channels::commands::unsubscribe,
+            utils::channels::commands::list_subscriptions,
+            utils::channels::commands::set_seed,
+            utils::channels::commands::get_seed,
+            // Jobs
+            utils::tasks::commands::submit_job,
+            utils::tasks::commands::list_jobs,
+            utils::tasks::commands::get_job,
+            utils::tasks::commands::cancel_job,
+            utils::tasks::commands::get_job_result,
+            utils::tasks::commands::remove_job,
+            utils::tasks::commands::list_job_history,
+            utils::tasks::commands::get_job_history,
+            utils::tasks::commands::rerun_job,
+            utils::tasks::commands::get_executor_status,
+            utils::tasks::commands::set_executor_limit,
+            // Workflows
+            utils::tasks::commands::run_workflow,
+            utils::tasks::commands::run_saved_workflow,
+            utils::tasks::commands::get_workflow_steps,
+            utils::tasks::commands::save_workflow,
+            utils::tasks::commands::get_workflow,
+            utils::tasks::commands::list_workflows,
+            utils::tasks::commands::delete_workflow,
+            // Schedules
+            utils::tasks::commands::create_schedule,
+            utils::tasks::commands::list_schedules,
+            utils::tasks::commands::get_schedule,
+            utils::tasks::commands::set_schedule_enabled,
+            utils::tasks::commands::delete_schedule,
+            // Simulation clock
+            utils::clock::commands::get_clock,
+            utils::clock::commands::set_clock_speed,
+            utils::clock::commands::pause_clock,
+            utils::clock::commands::resume_clock,
+            utils::clock::commands::seek_clock,
+            // Import and export
+            utils::import::commands::inspect_csv,
+            utils::import::commands::import_csv,
+            utils::export::commands::export_channels,
+            // Sequence of events
+            utils::soe::commands::query_soe,
+            utils::soe::commands::clear_soe,
+            // Protocols
+            protocols::c37118::commands::connect_pmu,
+            protocols::c37118::commands::disconnect_pmu,
+            protocols::c37118::commands::list_pmus,
+            protocols::modbus::commands::connect_modbus,
+            protocols::modbus::commands::disconnect_modbus,
+            protocols::modbus::commands::write_modbus,
+            protocols::modbus::commands::list_modbus,
+            protocols::modbus::commands::start_modbus_server,
+            protocols::modbus::commands::stop_modbus_server,
+            protocols::modbus::commands::get_modbus_server_map,
+            protocols::iec104::commands::start_iec104_server,
+            protocols::iec104::commands::stop_iec104_server,
+            protocols::iec104::commands::get_iec104_server_map,
             // Database
             settings::database::commands::set_setting,
             settings::database::commands::get_setting,
@@ -80,6 +196,7 @@ pub fn run() {
             settings::sidecars::commands::start_sidecar,
             settings::sidecars::commands::shutdown_sidecar,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(shutdown::on_event);
 }
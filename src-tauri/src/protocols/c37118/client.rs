@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+    sync::mpsc,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::utils::channels::source::Sample;
+
+use super::error::{Error, Result};
+use super::frame::{
+    decode_config, decode_data, decode_header, encode_command, frame_size, Command, Config,
+    DataFrame, FrameType,
+};
+
+/// Largest frame allowed by the 16 bit FRAMESIZE word.
+const MAX_FRAME_LEN: usize = u16::MAX as usize;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transport {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamConfig {
+    pub address: String,
+    #[serde(default)]
+    pub transport: Transport,
+    pub idcode: u16,
+    /// Prepended to the field keys to build the channel ids.
+    #[serde(default)]
+    pub prefix: Option<String>,
+    /// Field key to channel id, takes precedence over the generated ids.
+    #[serde(default)]
+    pub mapping: HashMap<String, String>,
+}
+
+impl StreamConfig {
+    /// Channel id for a field key such as `STATION A.VA.magnitude`.
+    pub fn channel_id(&self, key: &str) -> String {
+        match (self.mapping.get(key), &self.prefix) {
+            (Some(id), _) => id.clone(),
+            (None, Some(prefix)) => format!("{}.{}", prefix, key),
+            (None, None) => key.to_string(),
+        }
+    }
+}
+
+/// Flattens a data frame into one sample per phasor magnitude, phasor angle,
+/// frequency and ROCOF field, carrying the STAT quality of the PMU block.
+pub fn samples(stream: &StreamConfig, config: &Config, data: &DataFrame) -> Vec<(String, Sample)> {
+    let mut samples = Vec::new();
+
+    for (pmu, values) in config.pmus.iter().zip(&data.pmus) {
+        let quality = values.quality();
+        let mut push = |key: String, value: f64| {
+            samples.push((
+                stream.channel_id(&key),
+                Sample {
+                    value,
+                    quality,
+                    timestamp: data.timestamp,
+                },
+            ));
+        };
+
+        for (channel, phasor) in pmu.phasors.iter().zip(&values.phasors) {
+            push(
+                format!("{}.{}.magnitude", pmu.station, channel.name),
+                phasor.magnitude,
+            );
+            push(
+                format!("{}.{}.angle", pmu.station, channel.name),
+                phasor.angle,
+            );
+        }
+        push(format!("{}.frequency", pmu.station), values.frequency);
+        push(format!("{}.rocof", pmu.station), values.rocof);
+    }
+
+    samples
+}
+
+enum Connection {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+}
+
+impl Connection {
+    async fn open(stream: &StreamConfig) -> Result<Self> {
+        match stream.transport {
+            Transport::Tcp => Ok(Connection::Tcp(TcpStream::connect(&stream.address).await?)),
+            Transport::Udp => {
+                let socket = UdpSocket::bind("0.0.0.0:0").await?;
+                socket.connect(&stream.address).await?;
+                Ok(Connection::Udp(socket))
+            }
+        }
+    }
+
+    async fn send(&mut self, frame: &[u8]) -> Result<()> {
+        match self {
+            Connection::Tcp(socket) => socket.write_all(frame).await?,
+            Connection::Udp(socket) => {
+                socket.send(frame).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> Result<Vec<u8>> {
+        match self {
+            Connection::Tcp(socket) => {
+                let mut prefix = [0u8; 4];
+                if let Err(e) = socket.read_exact(&mut prefix).await {
+                    return match e.kind() {
+                        std::io::ErrorKind::UnexpectedEof => Err(Error::ConnectionClosed),
+                        _ => Err(e.into()),
+                    };
+                }
+                let size = frame_size(&prefix)?;
+                let mut frame = vec![0u8; size];
+                frame[..4].copy_from_slice(&prefix);
+                socket.read_exact(&mut frame[4..]).await?;
+                Ok(frame)
+            }
+            Connection::Udp(socket) => {
+                let mut buf = vec![0u8; MAX_FRAME_LEN];
+                let len = socket.recv(&mut buf).await?;
+                buf.truncate(len);
+                Ok(buf)
+            }
+        }
+    }
+
+    async fn command(&mut self, idcode: u16, command: Command) -> Result<()> {
+        self.send(&encode_command(idcode, command, Utc::now()))
+            .await
+    }
+}
+
+/// Connects to a PMU, requests its CFG-2 then streams every data frame to `tx`
+/// until `token` is cancelled or the receiver is dropped.
+pub async fn run(
+    stream: &StreamConfig,
+    token: CancellationToken,
+    tx: mpsc::Sender<Vec<(String, Sample)>>,
+) -> Result<()> {
+    let mut connection = Connection::open(stream).await?;
+    connection
+        .command(stream.idcode, Command::SendConfig2)
+        .await?;
+
+    let mut config: Option<Config> = None;
+
+    loop {
+        let frame = tokio::select! {
+            frame = connection.recv() => frame?,
+            _ = token.cancelled() => {
+                let _ = connection.command(stream.idcode, Command::DataOff).await;
+                return Ok(());
+            }
+        };
+
+        let header = match decode_header(&frame) {
+            Ok(header) => header,
+            Err(e) => {
+                log::warn!("Dropping invalid frame from PMU {}: {}", stream.address, e);
+                continue;
+            }
+        };
+
+        match header.frame_type {
+            FrameType::Config2 => {
+                let decoded = decode_config(&frame)?;
+                log::info!(
+                    "Received CFG-2 from PMU {} ({} station(s), {} frames/s)",
+                    stream.address,
+                    decoded.pmus.len(),
+                    decoded.data_rate
+                );
+                config = Some(decoded);
+                connection.command(stream.idcode, Command::DataOn).await?;
+            }
+            FrameType::Data => {
+                let Some(config) = &config else {
+                    continue;
+                };
+                match decode_data(&frame, config) {
+                    Ok(data) => {
+                        if tx.send(samples(stream, config, &data)).await.is_err() {
+                            return Ok(());
+                        }
+                    }
+                    Err(Error::ConfigMismatch { .. }) => {
+                        log::warn!(
+                            "Configuration of PMU {} changed, requesting CFG-2",
+                            stream.address
+                        );
+                        connection
+                            .command(stream.idcode, Command::SendConfig2)
+                            .await?;
+                    }
+                    Err(e) => {
+                        log::warn!("Dropping data frame from PMU {}: {}", stream.address, e)
+                    }
+                }
+            }
+            other => log::debug!("Ignoring {:?} frame from PMU {}", other, stream.address),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::c37118::frame::{decode_command, MIN_FRAME_LEN};
+    use crate::utils::channels::source::Quality;
+    use tokio::net::TcpListener;
+    use tokio::time::{timeout, Duration};
+
+    const CFG2: &[u8] = include_bytes!("testdata/cfg2.bin");
+    const DATA_GOOD: &[u8] = include_bytes!("testdata/data_good.bin");
+    const DATA_UNSYNC: &[u8] = include_bytes!("testdata/data_unsync.bin");
+
+    /// Frames replayed by the stand-in PMU for each command it receives.
+    fn replay(command: Option<Command>) -> Vec<&'static [u8]> {
+        match command {
+            Some(Command::SendConfig2) => vec![CFG2],
+            Some(Command::DataOn) => vec![DATA_GOOD, DATA_UNSYNC],
+            _ => vec![],
+        }
+    }
+
+    async fn spawn_tcp_pmu() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut command = [0u8; MIN_FRAME_LEN + 2];
+            while socket.read_exact(&mut command).await.is_ok() {
+                for frame in replay(decode_command(&command).unwrap()) {
+                    socket.write_all(frame).await.unwrap();
+                }
+            }
+        });
+
+        address
+    }
+
+    async fn spawn_udp_pmu() -> String {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap().to_string();
+
+        tokio::spawn(async move {
+            let mut command = [0u8; 64];
+            while let Ok((len, peer)) = socket.recv_from(&mut command).await {
+                for frame in replay(decode_command(&command[..len]).unwrap()) {
+                    socket.send_to(frame, peer).await.unwrap();
+                }
+            }
+        });
+
+        address
+    }
+
+    fn stream(address: String, transport: Transport) -> StreamConfig {
+        StreamConfig {
+            address,
+            transport,
+            idcode: 7734,
+            prefix: Some("pmu".to_string()),
+            mapping: HashMap::from([("STATION A.frequency".to_string(), "S1.freq".to_string())]),
+        }
+    }
+
+    async fn collect(stream: StreamConfig) -> Vec<Vec<(String, Sample)>> {
+        let token = CancellationToken::new();
+        let (tx, mut rx) = mpsc::channel(8);
+
+        let client = tokio::spawn({
+            let token = token.clone();
+            async move { run(&stream, token, tx).await }
+        });
+
+        let mut batches = Vec::new();
+        for _ in 0..2 {
+            let batch = timeout(Duration::from_secs(1), rx.recv()).await.unwrap();
+            batches.push(batch.unwrap());
+        }
+
+        token.cancel();
+        client.await.unwrap().unwrap();
+        batches
+    }
+
+    fn assert_batches(batches: &[Vec<(String, Sample)>]) {
+        let ids: Vec<&str> = batches[0].iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(
+            ids,
+            vec![
+                "pmu.STATION A.VA.magnitude",
+                "pmu.STATION A.VA.angle",
+                "pmu.STATION A.IA.magnitude",
+                "pmu.STATION A.IA.angle",
+                "S1.freq",
+                "pmu.STATION A.rocof",
+            ]
+        );
+
+        let (_, frequency) = &batches[0][4];
+        assert!((frequency.value - 60.025).abs() < 1e-6);
+        assert!(batches[0].iter().all(|(_, s)| s.quality == Quality::Good));
+        assert!(batches[1]
+            .iter()
+            .all(|(_, s)| s.quality == Quality::Suspect));
+    }
+
+    #[tokio::test]
+    async fn test_tcp_stream() {
+        let address = spawn_tcp_pmu().await;
+        let batches = collect(stream(address, Transport::Tcp)).await;
+        assert_batches(&batches);
+    }
+
+    #[tokio::test]
+    async fn test_udp_stream() {
+        let address = spawn_udp_pmu().await;
+        let batches = collect(stream(address, Transport::Udp)).await;
+        assert_batches(&batches);
+    }
+
+    #[tokio::test]
+    async fn test_connection_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let (tx, _rx) = mpsc::channel(1);
+        let result = run(
+            &stream(address, Transport::Tcp),
+            CancellationToken::new(),
+            tx,
+        )
+        .await;
+        assert!(matches!(result, Err(Error::Io(_))));
+    }
+}
@@ -0,0 +1,112 @@
+use serde::Serialize;
+use tauri::{AppHandle, Manager, State};
+use tokio::sync::mpsc;
+
+use crate::utils::{
+    channels::{source::Sample, state::Channels},
+    tasks::supervisor::{self, Supervision, SupervisorStatus},
+};
+
+use super::client::{self, StreamConfig};
+use super::error::{Error, Result};
+use super::state::{PmuStream, PmuStreams};
+
+#[derive(Debug, Serialize)]
+pub struct PmuStreamStatus {
+    id: String,
+    config: StreamConfig,
+    running: bool,
+    supervisor: SupervisorStatus,
+}
+
+/// Connects to a PMU and publishes its phasors, frequency and ROCOF into the
+/// matching external channels, reconnecting on failure unless `supervision`
+/// says otherwise.
+#[tauri::command]
+pub async fn connect_pmu(
+    app: AppHandle,
+    state: State<'_, PmuStreams>,
+    id: String,
+    config: StreamConfig,
+    supervision: Option<Supervision>,
+) -> Result<()> {
+    let mut streams = state.lock().await;
+
+    if streams.streams.contains_key(&id) {
+        return Err(Error::StreamAlreadyExists { id });
+    }
+
+    let config_clone = config.clone();
+    let id_clone = id.clone();
+
+    let supervision = supervision.unwrap_or_default();
+    let supervised = supervisor::supervise(id.clone(), supervision, move |token| {
+        let config = config_clone.clone();
+        let id = id_clone.clone();
+        let app = app.clone();
+
+        async move {
+            let (tx, mut rx) = mpsc::channel::<Vec<(String, Sample)>>(64);
+
+            let forward = async {
+                while let Some(samples) = rx.recv().await {
+                    let channels = app.state::<Channels>();
+                    for (channel, sample) in samples {
+                        channels.publish(&channel, sample);
+                    }
+                }
+            };
+
+            let (result, _) = tokio::join!(client::run(&config, (*token).clone(), tx), forward);
+            match result {
+                Ok(()) => {
+                    log::info!("PMU stream '{}' stopped", id);
+                    Ok(())
+                }
+                Err(e) => Err(e.to_string()),
+            }
+        }
+    });
+
+    streams
+        .streams
+        .insert(id.clone(), PmuStream { config, supervised });
+    log::info!("Successfully connected PMU stream '{}'", id);
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn disconnect_pmu(state: State<'_, PmuStreams>, id: String) -> Result<()> {
+    let mut streams = state.lock().await;
+
+    match streams.streams.remove(&id) {
+        Some(stream) => {
+            stream.supervised.cancel();
+            log::info!("Successfully disconnected PMU stream '{}'", id);
+            Ok(())
+        }
+        None => {
+            log::warn!("Attempted to disconnect non-existent PMU stream '{}'", id);
+            Err(Error::StreamNotFound { id })
+        }
+    }
+}
+
+#[tauri::command]
+pub async fn list_pmus(state: State<'_, PmuStreams>) -> Result<Vec<PmuStreamStatus>> {
+    let streams = state.lock().await;
+
+    let statuses = streams
+        .streams
+        .iter()
+        .map(|(id, stream)| PmuStreamStatus {
+            id: id.clone(),
+            config: stream.config.clone(),
+            running: !stream.supervised.task.is_finished(),
+            supervisor: stream.supervised.status(),
+        })
+        .collect();
+
+    Ok(statuses)
+}
@@ -0,0 +1,54 @@
+use serde::Serialize;
+use thiserror::Error;
+
+use super::frame::FrameType;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid sync byte: {0:#04x}")]
+    InvalidSync(u8),
+
+    #[error("Unknown frame type: {0}")]
+    UnknownFrameType(u8),
+
+    #[error("Unexpected frame: {0:?}")]
+    UnexpectedFrame(FrameType),
+
+    #[error("Truncated frame: expected {expected} bytes, got {actual}")]
+    Truncated { expected: usize, actual: usize },
+
+    #[error("Checksum mismatch: expected {expected:#06x}, computed {actual:#06x}")]
+    ChecksumMismatch { expected: u16, actual: u16 },
+
+    #[error("Data frame does not match configuration: expected {expected} bytes, got {actual}")]
+    ConfigMismatch { expected: usize, actual: usize },
+
+    #[error("Connection closed by PMU")]
+    ConnectionClosed,
+
+    #[error("PMU stream with id '{id}' not found")]
+    StreamNotFound { id: String },
+
+    #[error("PMU stream with id '{id}' already exists")]
+    StreamAlreadyExists { id: String },
+}
+
+impl Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_ref())
+    }
+}
+
+impl From<Error> for String {
+    fn from(err: Error) -> Self {
+        err.to_string()
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
@@ -0,0 +1,601 @@
+//! Encoding and decoding of IEEE C37.118.2-2011 frames.
+
+use chrono::{DateTime, Utc};
+
+use crate::utils::channels::source::Quality;
+
+use super::error::{Error, Result};
+
+pub const SYNC_BYTE: u8 = 0xAA;
+
+/// SYNC + FRAMESIZE + IDCODE + SOC + FRACSEC.
+pub const HEADER_LEN: usize = 14;
+
+/// Smallest valid frame: header followed by the CHK word.
+pub const MIN_FRAME_LEN: usize = HEADER_LEN + 2;
+
+const NAME_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameType {
+    Data,
+    Header,
+    Config1,
+    Config2,
+    Command,
+    Config3,
+}
+
+impl FrameType {
+    fn from_bits(bits: u8) -> Result<Self> {
+        match bits {
+            0 => Ok(FrameType::Data),
+            1 => Ok(FrameType::Header),
+            2 => Ok(FrameType::Config1),
+            3 => Ok(FrameType::Config2),
+            4 => Ok(FrameType::Command),
+            5 => Ok(FrameType::Config3),
+            other => Err(Error::UnknownFrameType(other)),
+        }
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            FrameType::Data => 0,
+            FrameType::Header => 1,
+            FrameType::Config1 => 2,
+            FrameType::Config2 => 3,
+            FrameType::Command => 4,
+            FrameType::Config3 => 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    DataOff,
+    DataOn,
+    SendHeader,
+    SendConfig1,
+    SendConfig2,
+}
+
+impl Command {
+    fn code(self) -> u16 {
+        match self {
+            Command::DataOff => 1,
+            Command::DataOn => 2,
+            Command::SendHeader => 3,
+            Command::SendConfig1 => 4,
+            Command::SendConfig2 => 5,
+        }
+    }
+
+    pub fn from_code(code: u16) -> Option<Self> {
+        match code {
+            1 => Some(Command::DataOff),
+            2 => Some(Command::DataOn),
+            3 => Some(Command::SendHeader),
+            4 => Some(Command::SendConfig1),
+            5 => Some(Command::SendConfig2),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub frame_type: FrameType,
+    pub version: u8,
+    pub size: u16,
+    pub idcode: u16,
+    pub soc: u32,
+    pub fracsec: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    pub freq_float: bool,
+    pub analog_float: bool,
+    pub phasor_float: bool,
+    pub polar: bool,
+}
+
+impl From<u16> for Format {
+    fn from(bits: u16) -> Self {
+        Self {
+            freq_float: bits & 0x0001 != 0,
+            analog_float: bits & 0x0002 != 0,
+            phasor_float: bits & 0x0004 != 0,
+            polar: bits & 0x0008 != 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhasorKind {
+    Voltage,
+    Current,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PhasorChannel {
+    pub name: String,
+    pub kind: PhasorKind,
+    /// Engineering units per bit, only used with integer phasors.
+    pub scale: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PmuConfig {
+    pub station: String,
+    pub idcode: u16,
+    pub format: Format,
+    pub phasors: Vec<PhasorChannel>,
+    pub analogs: Vec<String>,
+    pub digital_words: usize,
+    pub nominal_frequency: f64,
+    pub config_count: u16,
+}
+
+impl PmuConfig {
+    fn data_len(&self) -> usize {
+        let phasor = if self.format.phasor_float { 8 } else { 4 };
+        let freq = if self.format.freq_float { 4 } else { 2 };
+        let analog = if self.format.analog_float { 4 } else { 2 };
+        2 + phasor * self.phasors.len()
+            + 2 * freq
+            + analog * self.analogs.len()
+            + 2 * self.digital_words
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub idcode: u16,
+    pub time_base: u32,
+    pub pmus: Vec<PmuConfig>,
+    pub data_rate: i16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Phasor {
+    pub magnitude: f64,
+    /// Angle in degrees.
+    pub angle: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PmuData {
+    pub stat: u16,
+    pub phasors: Vec<Phasor>,
+    /// Frequency in Hz.
+    pub frequency: f64,
+    /// Rate of change of frequency in Hz/s.
+    pub rocof: f64,
+}
+
+impl PmuData {
+    pub fn quality(&self) -> Quality {
+        stat_quality(self.stat)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataFrame {
+    pub idcode: u16,
+    pub timestamp: DateTime<Utc>,
+    pub pmus: Vec<PmuData>,
+}
+
+/// Maps the STAT word of a PMU block to a sample quality.
+///
+/// Bits 15-14 flag a data error, bit 13 a lost synchronization and bit 9 data
+/// modified by a post-processing step.
+pub fn stat_quality(stat: u16) -> Quality {
+    if stat & 0xC000 != 0 {
+        Quality::Invalid
+    } else if stat & 0x2000 != 0 || stat & 0x0200 != 0 {
+        Quality::Suspect
+    } else {
+        Quality::Good
+    }
+}
+
+/// CRC-CCITT (polynomial 0x1021, initial value 0xFFFF) used for the CHK word.
+pub fn crc_ccitt(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8], pos: usize) -> Self {
+        Self { buf, pos }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos + len;
+        if end > self.buf.len() {
+            return Err(Error::Truncated {
+                expected: end,
+                actual: self.buf.len(),
+            });
+        }
+        let slice = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn i16(&mut self) -> Result<i16> {
+        Ok(self.u16()? as i16)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let b = self.take(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    fn name(&mut self) -> Result<String> {
+        let b = self.take(NAME_LEN)?;
+        Ok(String::from_utf8_lossy(b).trim().to_string())
+    }
+}
+
+/// Decodes and validates the common header, including the trailing CHK word.
+///
+/// `frame` must hold exactly one frame.
+pub fn decode_header(frame: &[u8]) -> Result<FrameHeader> {
+    if frame.len() < MIN_FRAME_LEN {
+        return Err(Error::Truncated {
+            expected: MIN_FRAME_LEN,
+            actual: frame.len(),
+        });
+    }
+    if frame[0] != SYNC_BYTE {
+        return Err(Error::InvalidSync(frame[0]));
+    }
+
+    let mut reader = Reader::new(frame, 1);
+    let type_version = reader.take(1)?[0];
+    let size = reader.u16()?;
+    if size as usize != frame.len() {
+        return Err(Error::Truncated {
+            expected: size as usize,
+            actual: frame.len(),
+        });
+    }
+
+    let body_end = frame.len() - 2;
+    let expected = u16::from_be_bytes([frame[body_end], frame[body_end + 1]]);
+    let actual = crc_ccitt(&frame[..body_end]);
+    if expected != actual {
+        return Err(Error::ChecksumMismatch { expected, actual });
+    }
+
+    Ok(FrameHeader {
+        frame_type: FrameType::from_bits((type_version >> 4) & 0x07)?,
+        version: type_version & 0x0F,
+        size,
+        idcode: reader.u16()?,
+        soc: reader.u32()?,
+        fracsec: reader.u32()?,
+    })
+}
+
+/// Returns the declared size of the frame starting at `prefix`, which must hold
+/// at least the SYNC and FRAMESIZE words.
+pub fn frame_size(prefix: &[u8]) -> Result<usize> {
+    if prefix.len() < 4 {
+        return Err(Error::Truncated {
+            expected: 4,
+            actual: prefix.len(),
+        });
+    }
+    if prefix[0] != SYNC_BYTE {
+        return Err(Error::InvalidSync(prefix[0]));
+    }
+    let size = u16::from_be_bytes([prefix[2], prefix[3]]) as usize;
+    if size < MIN_FRAME_LEN {
+        return Err(Error::Truncated {
+            expected: MIN_FRAME_LEN,
+            actual: size,
+        });
+    }
+    Ok(size)
+}
+
+/// Decodes a CFG-2 frame.
+pub fn decode_config(frame: &[u8]) -> Result<Config> {
+    let header = decode_header(frame)?;
+    if header.frame_type != FrameType::Config2 {
+        return Err(Error::UnexpectedFrame(header.frame_type));
+    }
+
+    let mut reader = Reader::new(frame, HEADER_LEN);
+    let time_base = reader.u32()? & 0x00FF_FFFF;
+    let num_pmu = reader.u16()?;
+
+    let mut pmus = Vec::with_capacity(num_pmu as usize);
+    for _ in 0..num_pmu {
+        let station = reader.name()?;
+        let idcode = reader.u16()?;
+        let format = Format::from(reader.u16()?);
+        let phnmr = reader.u16()? as usize;
+        let annmr = reader.u16()? as usize;
+        let dgnmr = reader.u16()? as usize;
+
+        let phasor_names = (0..phnmr)
+            .map(|_| reader.name())
+            .collect::<Result<Vec<_>>>()?;
+        let analogs = (0..annmr)
+            .map(|_| reader.name())
+            .collect::<Result<Vec<_>>>()?;
+        reader.take(NAME_LEN * 16 * dgnmr)?;
+
+        let mut phasors = Vec::with_capacity(phnmr);
+        for name in phasor_names {
+            let unit = reader.u32()?;
+            let kind = if unit >> 24 == 1 {
+                PhasorKind::Current
+            } else {
+                PhasorKind::Voltage
+            };
+            phasors.push(PhasorChannel {
+                name,
+                kind,
+                scale: (unit & 0x00FF_FFFF) as f64 * 1e-5,
+            });
+        }
+        // ANUNIT and DIGUNIT are not needed to decode the mapped fields.
+        reader.take(4 * annmr + 4 * dgnmr)?;
+
+        let fnom = reader.u16()?;
+        let config_count = reader.u16()?;
+
+        pmus.push(PmuConfig {
+            station,
+            idcode,
+            format,
+            phasors,
+            analogs,
+            digital_words: dgnmr,
+            nominal_frequency: if fnom & 0x0001 != 0 { 50.0 } else { 60.0 },
+            config_count,
+        });
+    }
+
+    let data_rate = reader.i16()?;
+
+    Ok(Config {
+        idcode: header.idcode,
+        time_base,
+        pmus,
+        data_rate,
+    })
+}
+
+/// Decodes a data frame according to the CFG-2 previously received.
+pub fn decode_data(frame: &[u8], config: &Config) -> Result<DataFrame> {
+    let header = decode_header(frame)?;
+    if header.frame_type != FrameType::Data {
+        return Err(Error::UnexpectedFrame(header.frame_type));
+    }
+
+    let expected = HEADER_LEN + config.pmus.iter().map(PmuConfig::data_len).sum::<usize>() + 2;
+    if frame.len() != expected {
+        return Err(Error::ConfigMismatch {
+            expected,
+            actual: frame.len(),
+        });
+    }
+
+    let mut reader = Reader::new(frame, HEADER_LEN);
+    let mut pmus = Vec::with_capacity(config.pmus.len());
+    for pmu in &config.pmus {
+        let stat = reader.u16()?;
+
+        let mut phasors = Vec::with_capacity(pmu.phasors.len());
+        for channel in &pmu.phasors {
+            let (magnitude, angle) = match (pmu.format.phasor_float, pmu.format.polar) {
+                (true, true) => (reader.f32()? as f64, reader.f32()? as f64),
+                (true, false) => {
+                    let (re, im) = (reader.f32()? as f64, reader.f32()? as f64);
+                    (re.hypot(im), im.atan2(re))
+                }
+                (false, true) => (
+                    reader.u16()? as f64 * channel.scale,
+                    reader.i16()? as f64 * 1e-4,
+                ),
+                (false, false) => {
+                    let re = reader.i16()? as f64 * channel.scale;
+                    let im = reader.i16()? as f64 * channel.scale;
+                    (re.hypot(im), im.atan2(re))
+                }
+            };
+            phasors.push(Phasor {
+                magnitude,
+                angle: angle.to_degrees(),
+            });
+        }
+
+        let (frequency, rocof) = if pmu.format.freq_float {
+            (reader.f32()? as f64, reader.f32()? as f64)
+        } else {
+            (
+                pmu.nominal_frequency + reader.i16()? as f64 / 1000.0,
+                reader.i16()? as f64 / 100.0,
+            )
+        };
+
+        let analog = if pmu.format.analog_float { 4 } else { 2 };
+        reader.take(analog * pmu.analogs.len() + 2 * pmu.digital_words)?;
+
+        pmus.push(PmuData {
+            stat,
+            phasors,
+            frequency,
+            rocof,
+        });
+    }
+
+    Ok(DataFrame {
+        idcode: header.idcode,
+        timestamp: timestamp(header.soc, header.fracsec, config.time_base),
+        pmus,
+    })
+}
+
+fn timestamp(soc: u32, fracsec: u32, time_base: u32) -> DateTime<Utc> {
+    let fraction = (fracsec & 0x00FF_FFFF) as f64 / time_base.max(1) as f64;
+    let nanos = (fraction * 1e9).round().min(999_999_999.0) as u32;
+    DateTime::from_timestamp(soc as i64, nanos).unwrap_or_default()
+}
+
+/// Encodes a command frame sent to the PMU.
+pub fn encode_command(idcode: u16, command: Command, now: DateTime<Utc>) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(MIN_FRAME_LEN + 2);
+    frame.push(SYNC_BYTE);
+    frame.push((FrameType::Command.bits() << 4) | 0x01);
+    frame.extend_from_slice(&((MIN_FRAME_LEN + 2) as u16).to_be_bytes());
+    frame.extend_from_slice(&idcode.to_be_bytes());
+    frame.extend_from_slice(&(now.timestamp() as u32).to_be_bytes());
+    frame.extend_from_slice(&0u32.to_be_bytes());
+    frame.extend_from_slice(&command.code().to_be_bytes());
+    let crc = crc_ccitt(&frame);
+    frame.extend_from_slice(&crc.to_be_bytes());
+    frame
+}
+
+/// Decodes the CMD word of a command frame.
+pub fn decode_command(frame: &[u8]) -> Result<Option<Command>> {
+    let header = decode_header(frame)?;
+    if header.frame_type != FrameType::Command {
+        return Err(Error::UnexpectedFrame(header.frame_type));
+    }
+    let mut reader = Reader::new(frame, HEADER_LEN);
+    Ok(Command::from_code(reader.u16()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CFG2: &[u8] = include_bytes!("testdata/cfg2.bin");
+    const DATA_GOOD: &[u8] = include_bytes!("testdata/data_good.bin");
+    const DATA_UNSYNC: &[u8] = include_bytes!("testdata/data_unsync.bin");
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn test_decode_config() {
+        let config = decode_config(CFG2).unwrap();
+
+        assert_eq!(config.idcode, 7734);
+        assert_eq!(config.time_base, 1_000_000);
+        assert_eq!(config.data_rate, 30);
+        assert_eq!(config.pmus.len(), 1);
+
+        let pmu = &config.pmus[0];
+        assert_eq!(pmu.station, "STATION A");
+        assert!(pmu.format.polar);
+        assert!(!pmu.format.phasor_float);
+        assert_eq!(pmu.phasors.len(), 2);
+        assert_eq!(pmu.phasors[0].name, "VA");
+        assert_eq!(pmu.phasors[0].kind, PhasorKind::Voltage);
+        assert_eq!(pmu.phasors[1].kind, PhasorKind::Current);
+        assert_close(pmu.phasors[0].scale, 9.15527);
+        assert_eq!(pmu.analogs, vec!["ANALOG1".to_string()]);
+        assert_eq!(pmu.digital_words, 1);
+        assert_eq!(pmu.nominal_frequency, 60.0);
+    }
+
+    #[test]
+    fn test_decode_data() {
+        let config = decode_config(CFG2).unwrap();
+        let data = decode_data(DATA_GOOD, &config).unwrap();
+
+        assert_eq!(data.timestamp.timestamp(), 1_700_000_000);
+        assert_eq!(data.timestamp.timestamp_subsec_millis(), 500);
+
+        let pmu = &data.pmus[0];
+        assert_eq!(pmu.quality(), Quality::Good);
+        assert_close(pmu.phasors[0].magnitude, 14635.0 * 9.15527);
+        assert_close(pmu.phasors[0].angle, 30.0);
+        assert_close(pmu.phasors[1].magnitude, 20000.0 * 0.45776);
+        assert_close(pmu.phasors[1].angle, -60.0);
+        assert_close(pmu.frequency, 60.025);
+        assert_close(pmu.rocof, -0.05);
+    }
+
+    #[test]
+    fn test_stat_quality() {
+        let config = decode_config(CFG2).unwrap();
+        let data = decode_data(DATA_UNSYNC, &config).unwrap();
+
+        assert_eq!(data.pmus[0].quality(), Quality::Suspect);
+        assert_eq!(stat_quality(0x8000), Quality::Invalid);
+        assert_eq!(stat_quality(0x4000), Quality::Invalid);
+        assert_eq!(stat_quality(0x0200), Quality::Suspect);
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        let mut corrupted = DATA_GOOD.to_vec();
+        corrupted[HEADER_LEN + 3] ^= 0xFF;
+
+        assert!(matches!(
+            decode_header(&corrupted),
+            Err(Error::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_data_with_wrong_config() {
+        let mut config = decode_config(CFG2).unwrap();
+        config.pmus[0].phasors.pop();
+
+        assert!(matches!(
+            decode_data(DATA_GOOD, &config),
+            Err(Error::ConfigMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_command_roundtrip() {
+        let frame = encode_command(7734, Command::SendConfig2, Utc::now());
+
+        let header = decode_header(&frame).unwrap();
+        assert_eq!(header.frame_type, FrameType::Command);
+        assert_eq!(header.idcode, 7734);
+        assert_eq!(decode_command(&frame).unwrap(), Some(Command::SendConfig2));
+    }
+}
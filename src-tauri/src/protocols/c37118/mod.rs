@@ -0,0 +1,5 @@
+pub mod client;
+pub mod commands;
+pub mod error;
+pub mod frame;
+pub mod state;
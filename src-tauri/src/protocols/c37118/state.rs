@@ -0,0 +1,18 @@
+use std::collections::HashMap;
+use tokio::sync::Mutex;
+
+use crate::utils::tasks::supervisor::Supervised;
+
+use super::client::StreamConfig;
+
+pub struct PmuStream {
+    pub config: StreamConfig,
+    pub supervised: Supervised,
+}
+
+#[derive(Default)]
+pub struct PmuStreamsInner {
+    pub streams: HashMap<String, PmuStream>,
+}
+
+pub type PmuStreams = Mutex<PmuStreamsInner>;
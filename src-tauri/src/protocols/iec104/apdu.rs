@@ -0,0 +1,603 @@
+//! IEC 60870-5-104 APDUs: APCI control field and the ASDUs used by the
+//! outstation.
+
+use chrono::{DateTime, Datelike, TimeZone, Timelike, Utc};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::error::{Error, Result};
+
+pub const START_BYTE: u8 = 0x68;
+
+/// Largest APDU length field (control field plus ASDU).
+pub const MAX_APDU_LEN: usize = 253;
+
+/// Largest ASDU carried by one APDU.
+pub const MAX_ASDU_LEN: usize = MAX_APDU_LEN - 4;
+
+/// Sequence numbers are 15 bits wide.
+pub const SEQUENCE_MODULO: u16 = 1 << 15;
+
+pub mod type_id {
+    pub const M_SP_NA_1: u8 = 1;
+    pub const M_DP_NA_1: u8 = 3;
+    pub const M_ME_NC_1: u8 = 13;
+    pub const M_SP_TB_1: u8 = 30;
+    pub const M_DP_TB_1: u8 = 31;
+    pub const M_ME_TF_1: u8 = 36;
+    pub const C_SC_NA_1: u8 = 45;
+    pub const C_DC_NA_1: u8 = 46;
+    pub const C_IC_NA_1: u8 = 100;
+}
+
+pub mod cause {
+    pub const SPONTANEOUS: u8 = 3;
+    pub const ACTIVATION: u8 = 6;
+    pub const ACTIVATION_CON: u8 = 7;
+    pub const DEACTIVATION: u8 = 8;
+    pub const DEACTIVATION_CON: u8 = 9;
+    pub const ACTIVATION_TERM: u8 = 10;
+    pub const INTERROGATED: u8 = 20;
+    pub const UNKNOWN_TYPE: u8 = 44;
+    pub const UNKNOWN_CAUSE: u8 = 45;
+    pub const UNKNOWN_COMMON_ADDRESS: u8 = 46;
+    pub const UNKNOWN_OBJECT_ADDRESS: u8 = 47;
+}
+
+/// Quality descriptor bits shared by SIQ, DIQ and QDS.
+pub mod quality {
+    pub const OVERFLOW: u8 = 0x01;
+    pub const NOT_TOPICAL: u8 = 0x40;
+    pub const INVALID: u8 = 0x80;
+}
+
+/// Qualifier of interrogation for a station interrogation.
+pub const QOI_STATION: u8 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UFunction {
+    StartDtAct,
+    StartDtCon,
+    StopDtAct,
+    StopDtCon,
+    TestFrAct,
+    TestFrCon,
+}
+
+impl UFunction {
+    fn bits(self) -> u8 {
+        match self {
+            UFunction::StartDtAct => 0x07,
+            UFunction::StartDtCon => 0x0B,
+            UFunction::StopDtAct => 0x13,
+            UFunction::StopDtCon => 0x23,
+            UFunction::TestFrAct => 0x43,
+            UFunction::TestFrCon => 0x83,
+        }
+    }
+
+    fn from_bits(bits: u8) -> Result<Self> {
+        match bits {
+            0x07 => Ok(UFunction::StartDtAct),
+            0x0B => Ok(UFunction::StartDtCon),
+            0x13 => Ok(UFunction::StopDtAct),
+            0x23 => Ok(UFunction::StopDtCon),
+            0x43 => Ok(UFunction::TestFrAct),
+            0x83 => Ok(UFunction::TestFrCon),
+            other => Err(Error::InvalidFrame(format!(
+                "unknown U-format function {:#04x}",
+                other
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Apdu {
+    I { send: u16, receive: u16, asdu: Asdu },
+    S { receive: u16 },
+    U(UFunction),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Element {
+    SinglePoint {
+        value: bool,
+        quality: u8,
+        time: Option<DateTime<Utc>>,
+    },
+    /// `value` is the DPI: 1 off, 2 on, 0 and 3 indeterminate.
+    DoublePoint {
+        value: u8,
+        quality: u8,
+        time: Option<DateTime<Utc>>,
+    },
+    ShortFloat {
+        value: f32,
+        quality: u8,
+        time: Option<DateTime<Utc>>,
+    },
+    SingleCommand {
+        value: bool,
+        select: bool,
+        qualifier: u8,
+    },
+    /// `value` is the DCS: 1 off, 2 on.
+    DoubleCommand {
+        value: u8,
+        select: bool,
+        qualifier: u8,
+    },
+    Interrogation {
+        qualifier: u8,
+    },
+}
+
+impl Element {
+    pub fn type_id(&self) -> u8 {
+        use type_id::*;
+        match self {
+            Element::SinglePoint { time: None, .. } => M_SP_NA_1,
+            Element::SinglePoint { time: Some(_), .. } => M_SP_TB_1,
+            Element::DoublePoint { time: None, .. } => M_DP_NA_1,
+            Element::DoublePoint { time: Some(_), .. } => M_DP_TB_1,
+            Element::ShortFloat { time: None, .. } => M_ME_NC_1,
+            Element::ShortFloat { time: Some(_), .. } => M_ME_TF_1,
+            Element::SingleCommand { .. } => C_SC_NA_1,
+            Element::DoubleCommand { .. } => C_DC_NA_1,
+            Element::Interrogation { .. } => C_IC_NA_1,
+        }
+    }
+
+    /// Encoded size without the information object address.
+    pub fn len(&self) -> usize {
+        let time = |time: &Option<DateTime<Utc>>| if time.is_some() { 7 } else { 0 };
+        match self {
+            Element::SinglePoint { time: t, .. } | Element::DoublePoint { time: t, .. } => {
+                1 + time(t)
+            }
+            Element::ShortFloat { time: t, .. } => 5 + time(t),
+            Element::SingleCommand { .. }
+            | Element::DoubleCommand { .. }
+            | Element::Interrogation { .. } => 1,
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        let time = match self {
+            Element::SinglePoint {
+                value,
+                quality,
+                time,
+            } => {
+                out.push(*value as u8 | (quality & 0xF0));
+                time
+            }
+            Element::DoublePoint {
+                value,
+                quality,
+                time,
+            } => {
+                out.push((value & 0x03) | (quality & 0xF0));
+                time
+            }
+            Element::ShortFloat {
+                value,
+                quality,
+                time,
+            } => {
+                out.extend_from_slice(&value.to_le_bytes());
+                out.push(*quality);
+                time
+            }
+            Element::SingleCommand {
+                value,
+                select,
+                qualifier,
+            } => {
+                out.push(*value as u8 | ((qualifier & 0x1F) << 2) | ((*select as u8) << 7));
+                &None
+            }
+            Element::DoubleCommand {
+                value,
+                select,
+                qualifier,
+            } => {
+                out.push((value & 0x03) | ((qualifier & 0x1F) << 2) | ((*select as u8) << 7));
+                &None
+            }
+            Element::Interrogation { qualifier } => {
+                out.push(*qualifier);
+                &None
+            }
+        };
+        if let Some(time) = time {
+            out.extend_from_slice(&encode_cp56(time));
+        }
+    }
+
+    /// Decodes one element, `None` for types the outstation does not know.
+    fn decode(type_id: u8, input: &mut Reader) -> Result<Option<Self>> {
+        use type_id::*;
+        let element = match type_id {
+            M_SP_NA_1 | M_SP_TB_1 => {
+                let siq = input.u8()?;
+                Element::SinglePoint {
+                    value: siq & 0x01 != 0,
+                    quality: siq & 0xF0,
+                    time: input.time(type_id == M_SP_TB_1)?,
+                }
+            }
+            M_DP_NA_1 | M_DP_TB_1 => {
+                let diq = input.u8()?;
+                Element::DoublePoint {
+                    value: diq & 0x03,
+                    quality: diq & 0xF0,
+                    time: input.time(type_id == M_DP_TB_1)?,
+                }
+            }
+            M_ME_NC_1 | M_ME_TF_1 => {
+                let b = input.take(4)?;
+                Element::ShortFloat {
+                    value: f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+                    quality: input.u8()?,
+                    time: input.time(type_id == M_ME_TF_1)?,
+                }
+            }
+            C_SC_NA_1 => {
+                let sco = input.u8()?;
+                Element::SingleCommand {
+                    value: sco & 0x01 != 0,
+                    select: sco & 0x80 != 0,
+                    qualifier: (sco >> 2) & 0x1F,
+                }
+            }
+            C_DC_NA_1 => {
+                let dco = input.u8()?;
+                Element::DoubleCommand {
+                    value: dco & 0x03,
+                    select: dco & 0x80 != 0,
+                    qualifier: (dco >> 2) & 0x1F,
+                }
+            }
+            C_IC_NA_1 => Element::Interrogation {
+                qualifier: input.u8()?,
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(element))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InformationObject {
+    pub address: u32,
+    pub element: Element,
+}
+
+/// Objects of unknown types are not decoded, leaving `objects` empty.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Asdu {
+    pub type_id: u8,
+    pub cause: u8,
+    pub negative: bool,
+    pub test: bool,
+    pub originator: u8,
+    pub common_address: u16,
+    pub objects: Vec<InformationObject>,
+}
+
+impl Asdu {
+    pub fn new(cause: u8, common_address: u16, objects: Vec<InformationObject>) -> Self {
+        Self {
+            type_id: objects.first().map_or(0, |o| o.element.type_id()),
+            cause,
+            negative: false,
+            test: false,
+            originator: 0,
+            common_address,
+            objects,
+        }
+    }
+
+    /// Mirror of a received ASDU with another cause, as used for ACTCON and
+    /// ACTTERM responses.
+    pub fn mirror(&self, cause: u8, negative: bool) -> Self {
+        Self {
+            cause,
+            negative,
+            ..self.clone()
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(self.type_id);
+        out.push((self.objects.len() as u8) & 0x7F);
+        out.push((self.cause & 0x3F) | ((self.negative as u8) << 6) | ((self.test as u8) << 7));
+        out.push(self.originator);
+        out.extend_from_slice(&self.common_address.to_le_bytes());
+        for object in &self.objects {
+            out.extend_from_slice(&object.address.to_le_bytes()[..3]);
+            object.element.encode(out);
+        }
+    }
+
+    fn decode(input: &mut Reader) -> Result<Self> {
+        let type_id = input.u8()?;
+        let vsq = input.u8()?;
+        let cot = input.u8()?;
+        let originator = input.u8()?;
+        let common_address = input.u16()?;
+
+        let count = (vsq & 0x7F) as usize;
+        let sequence = vsq & 0x80 != 0;
+        let mut objects = Vec::with_capacity(count);
+        let mut address = 0;
+        for i in 0..count {
+            address = if sequence && i > 0 {
+                address + 1
+            } else {
+                input.u24()?
+            };
+            match Element::decode(type_id, input)? {
+                Some(element) => objects.push(InformationObject { address, element }),
+                None => {
+                    objects.clear();
+                    break;
+                }
+            }
+        }
+
+        Ok(Self {
+            type_id,
+            cause: cot & 0x3F,
+            negative: cot & 0x40 != 0,
+            test: cot & 0x80 != 0,
+            originator,
+            common_address,
+            objects,
+        })
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos + len;
+        let slice = self
+            .buf
+            .get(self.pos..end)
+            .ok_or_else(|| Error::InvalidFrame(format!("ASDU truncated at {} bytes", end)))?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u24(&mut self) -> Result<u32> {
+        let b = self.take(3)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], 0]))
+    }
+
+    fn time(&mut self, present: bool) -> Result<Option<DateTime<Utc>>> {
+        if !present {
+            return Ok(None);
+        }
+        let b = self.take(7)?;
+        Ok(decode_cp56([b[0], b[1], b[2], b[3], b[4], b[5], b[6]]))
+    }
+}
+
+/// Encodes a CP56Time2a time tag, in UTC.
+pub fn encode_cp56(time: &DateTime<Utc>) -> [u8; 7] {
+    let millis = (time.second() * 1000 + time.timestamp_subsec_millis().min(999)) as u16;
+    let [ms_lo, ms_hi] = millis.to_le_bytes();
+    [
+        ms_lo,
+        ms_hi,
+        time.minute() as u8,
+        time.hour() as u8,
+        time.day() as u8 | ((time.weekday().number_from_monday() as u8) << 5),
+        time.month() as u8,
+        (time.year() % 100) as u8,
+    ]
+}
+
+pub fn decode_cp56(bytes: [u8; 7]) -> Option<DateTime<Utc>> {
+    let millis = u16::from_le_bytes([bytes[0], bytes[1]]) as u32;
+    Utc.with_ymd_and_hms(
+        2000 + (bytes[6] & 0x7F) as i32,
+        (bytes[5] & 0x0F) as u32,
+        (bytes[4] & 0x1F) as u32,
+        (bytes[3] & 0x1F) as u32,
+        (bytes[2] & 0x3F) as u32,
+        millis / 1000,
+    )
+    .single()
+    .and_then(|time| {
+        time.checked_add_signed(chrono::Duration::milliseconds((millis % 1000) as i64))
+    })
+}
+
+pub fn encode(apdu: &Apdu) -> Vec<u8> {
+    let mut out = vec![START_BYTE, 0];
+    match apdu {
+        Apdu::I {
+            send,
+            receive,
+            asdu,
+        } => {
+            out.extend_from_slice(&(send << 1).to_le_bytes());
+            out.extend_from_slice(&(receive << 1).to_le_bytes());
+            asdu.encode(&mut out);
+        }
+        Apdu::S { receive } => {
+            out.extend_from_slice(&[0x01, 0x00]);
+            out.extend_from_slice(&(receive << 1).to_le_bytes());
+        }
+        Apdu::U(function) => out.extend_from_slice(&[function.bits(), 0, 0, 0]),
+    }
+    out[1] = (out.len() - 2) as u8;
+    out
+}
+
+/// Decodes the APDU following the start byte and length octet.
+pub fn decode(body: &[u8]) -> Result<Apdu> {
+    if body.len() < 4 {
+        return Err(Error::InvalidFrame(format!(
+            "control field truncated at {} bytes",
+            body.len()
+        )));
+    }
+
+    let control = &body[..4];
+    let receive = u16::from_le_bytes([control[2], control[3]]) >> 1;
+    if control[0] & 0x01 == 0 {
+        let send = u16::from_le_bytes([control[0], control[1]]) >> 1;
+        let mut reader = Reader { buf: body, pos: 4 };
+        Ok(Apdu::I {
+            send,
+            receive,
+            asdu: Asdu::decode(&mut reader)?,
+        })
+    } else if control[0] & 0x03 == 0x01 {
+        Ok(Apdu::S { receive })
+    } else {
+        Ok(Apdu::U(UFunction::from_bits(control[0])?))
+    }
+}
+
+/// Reads one APDU from `reader`.
+pub async fn read<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Apdu> {
+    let mut header = [0u8; 2];
+    if let Err(e) = reader.read_exact(&mut header).await {
+        return match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => Err(Error::ConnectionClosed),
+            _ => Err(e.into()),
+        };
+    }
+    if header[0] != START_BYTE {
+        return Err(Error::InvalidFrame(format!(
+            "invalid start byte {:#04x}",
+            header[0]
+        )));
+    }
+    let len = header[1] as usize;
+    if !(4..=MAX_APDU_LEN).contains(&len) {
+        return Err(Error::InvalidFrame(format!("invalid length {}", len)));
+    }
+
+    let mut body = vec![0u8; len];
+    reader.read_exact(&mut body).await?;
+    decode(&body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn roundtrip(apdu: Apdu) -> Apdu {
+        let frame = encode(&apdu);
+        read(&mut frame.as_slice()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_control_frames() {
+        for apdu in [
+            Apdu::U(UFunction::StartDtAct),
+            Apdu::U(UFunction::TestFrCon),
+            Apdu::S { receive: 32767 },
+        ] {
+            assert_eq!(roundtrip(apdu.clone()).await, apdu);
+        }
+
+        assert_eq!(
+            encode(&Apdu::U(UFunction::StartDtAct)),
+            vec![0x68, 0x04, 0x07, 0, 0, 0]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_asdu_roundtrip() {
+        let time = Utc.with_ymd_and_hms(2025, 6, 3, 14, 7, 9).unwrap()
+            + chrono::Duration::milliseconds(250);
+        let asdu = Asdu::new(
+            cause::SPONTANEOUS,
+            1,
+            vec![
+                InformationObject {
+                    address: 1001,
+                    element: Element::ShortFloat {
+                        value: 123.5,
+                        quality: 0,
+                        time: Some(time),
+                    },
+                },
+                InformationObject {
+                    address: 70000,
+                    element: Element::ShortFloat {
+                        value: -1.0,
+                        quality: quality::INVALID,
+                        time: Some(time),
+                    },
+                },
+            ],
+        );
+        let apdu = Apdu::I {
+            send: 5,
+            receive: 3,
+            asdu,
+        };
+
+        assert_eq!(roundtrip(apdu.clone()).await, apdu);
+    }
+
+    #[test]
+    fn test_decode_command() {
+        // C_DC_NA_1 act, CA 1, IOA 5001, DCO close with select.
+        let body = [
+            0x02, 0x00, 0x04, 0x00, 46, 0x01, 0x06, 0x00, 0x01, 0x00, 0x89, 0x13, 0x00, 0x82,
+        ];
+
+        let Apdu::I { send, asdu, .. } = decode(&body).unwrap() else {
+            panic!("expected an I-format APDU");
+        };
+        assert_eq!(send, 1);
+        assert_eq!(asdu.cause, cause::ACTIVATION);
+        assert_eq!(
+            asdu.objects,
+            vec![InformationObject {
+                address: 5001,
+                element: Element::DoubleCommand {
+                    value: 2,
+                    select: true,
+                    qualifier: 0,
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn test_unknown_type() {
+        let body = [
+            0x00, 0x00, 0x00, 0x00, 200, 0x01, 0x06, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0xFF,
+        ];
+
+        let Apdu::I { asdu, .. } = decode(&body).unwrap() else {
+            panic!("expected an I-format APDU");
+        };
+        assert_eq!(asdu.type_id, 200);
+        assert_eq!(asdu.cause, cause::ACTIVATION);
+        assert!(asdu.objects.is_empty());
+    }
+}
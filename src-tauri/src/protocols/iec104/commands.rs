@@ -0,0 +1,142 @@
+use std::sync::Arc;
+
+use tauri::{AppHandle, Manager, State};
+use tokio::{
+    net::TcpListener,
+    sync::{mpsc, Mutex},
+    time::{interval, Duration},
+};
+
+use crate::settings::database::state::DatabaseState;
+use crate::utils::{
+    channels::state::Channels,
+    control,
+    tasks::supervisor::{self, Supervision},
+};
+
+use super::error::{Error, Result};
+use super::server::{self, Handler, ObjectMap, ServerConfig, Values};
+use super::state::{Server, ServerHandle};
+
+/// How often the values served to SCADA are refreshed from the channels.
+const SERVER_REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Starts the IEC 104 outstation with the object map stored under the
+/// `iec104.server` setting and returns the validated map. The outstation is
+/// restarted on failure unless `supervision` says otherwise.
+#[tauri::command]
+pub async fn start_iec104_server(
+    app: AppHandle,
+    state: State<'_, Server>,
+    db: State<'_, Mutex<DatabaseState>>,
+    supervision: Option<Supervision>,
+) -> Result<ObjectMap> {
+    let mut server = state.lock().await;
+
+    if server.server.is_some() {
+        return Err(Error::ServerAlreadyRunning);
+    }
+
+    let config = db
+        .lock()
+        .await
+        .get_setting::<ServerConfig>(server::SETTINGS_KEY)
+        .await?
+        .ok_or_else(|| Error::MissingSettings(server::SETTINGS_KEY.to_string()))?;
+    let map = ObjectMap::build(&config)?;
+    let listener = TcpListener::bind(&config.bind).await?;
+    log::info!(
+        "IEC 104 server listening on {} (common address {}, {} points, {} commands)",
+        config.bind,
+        map.common_address,
+        map.points.len(),
+        map.commands.len()
+    );
+
+    let listener = Arc::new(listener);
+    let map_clone = map.clone();
+
+    let supervision = supervision.unwrap_or_default();
+    let name = "iec104-server".to_string();
+    let supervised = supervisor::supervise(name, supervision, move |token| {
+        let map = map_clone.clone();
+        let listener = listener.clone();
+        let app = app.clone();
+
+        async move {
+            let values = Values::default();
+            let channels = map.channels();
+            let (tx, mut rx) = mpsc::channel(16);
+            let handler = Handler::new(&map, values.clone(), tx);
+            // Cancelled once the server ends, to end the refresh with it.
+            let stop = token.child();
+
+            let serve = async {
+                let result = server::run(&listener, handler, (*stop).clone()).await;
+                stop.cancel();
+                result
+            };
+
+            let dispatch = async {
+                while let Some(command) = rx.recv().await {
+                    control::dispatch(&app, &command);
+                }
+            };
+
+            let refresh = async {
+                let mut ticker = interval(SERVER_REFRESH_INTERVAL);
+                loop {
+                    tokio::select! {
+                        _ = ticker.tick() => {
+                            let state = app.state::<Channels>();
+                            if let Ok(mut values) = values.write() {
+                                for id in &channels {
+                                    if let Some(sample) = state.latest(id) {
+                                        values.insert(id.clone(), sample);
+                                    }
+                                }
+                            }
+                        }
+                        _ = stop.cancelled() => break,
+                    }
+                }
+            };
+
+            let (result, _, _) = tokio::join!(serve, dispatch, refresh);
+            match result {
+                Ok(()) => {
+                    log::info!("IEC 104 server stopped");
+                    Ok(())
+                }
+                Err(e) => Err(e.to_string()),
+            }
+        }
+    });
+
+    server.server = Some(ServerHandle {
+        map: map.clone(),
+        supervised,
+    });
+
+    Ok(map)
+}
+
+#[tauri::command]
+pub async fn stop_iec104_server(state: State<'_, Server>) -> Result<()> {
+    match state.lock().await.server.take() {
+        Some(server) => {
+            server.supervised.cancel();
+            log::info!("Successfully stopped IEC 104 server");
+            Ok(())
+        }
+        None => Err(Error::ServerNotRunning),
+    }
+}
+
+#[tauri::command]
+pub async fn get_iec104_server_map(state: State<'_, Server>) -> Result<ObjectMap> {
+    match &state.lock().await.server {
+        Some(server) => Ok(server.map.clone()),
+        None => Err(Error::ServerNotRunning),
+    }
+}
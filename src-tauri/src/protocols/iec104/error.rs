@@ -0,0 +1,54 @@
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::settings::database::error::Error as DatabaseError;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid IEC 104 frame: {0}")]
+    InvalidFrame(String),
+
+    #[error("Sequence error: expected {expected}, got {actual}")]
+    SequenceMismatch { expected: u16, actual: u16 },
+
+    #[error("Connection closed by peer")]
+    ConnectionClosed,
+
+    #[error("No acknowledgement from the master within t1")]
+    AcknowledgeTimeout,
+
+    #[error("Invalid information object map: {0}")]
+    InvalidMap(String),
+
+    #[error("Settings error: {0}")]
+    Settings(#[from] DatabaseError),
+
+    #[error("Missing setting '{0}'")]
+    MissingSettings(String),
+
+    #[error("IEC 104 server is already running")]
+    ServerAlreadyRunning,
+
+    #[error("IEC 104 server is not running")]
+    ServerNotRunning,
+}
+
+impl Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_ref())
+    }
+}
+
+impl From<Error> for String {
+    fn from(err: Error) -> Self {
+        err.to_string()
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
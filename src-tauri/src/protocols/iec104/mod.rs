@@ -0,0 +1,5 @@
+pub mod apdu;
+pub mod commands;
+pub mod error;
+pub mod server;
+pub mod state;
@@ -0,0 +1,1094 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{Arc, RwLock},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    time::{interval, sleep, Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::utils::{
+    channels::source::{Quality, Sample},
+    control::{Action, OperatorCommand},
+};
+
+use super::apdu::{
+    self, cause, quality, Apdu, Asdu, Element, InformationObject, UFunction, MAX_ASDU_LEN,
+    QOI_STATION, SEQUENCE_MODULO,
+};
+use super::error::{Error, Result};
+
+/// Settings key holding the `ServerConfig`.
+pub const SETTINGS_KEY: &str = "iec104.server";
+
+/// Latest sample of every mapped channel, keyed by channel id.
+pub type Values = Arc<RwLock<HashMap<String, Sample>>>;
+
+/// Unacknowledged I-frames sent before the outstation stops transmitting.
+const K: u16 = 12;
+
+/// Received I-frames acknowledged at the latest by an S-frame.
+const W: u16 = 8;
+
+/// Time after which an unacknowledged I-frame closes the connection.
+const T1: Duration = Duration::from_secs(15);
+
+/// How often values are checked for spontaneous transmission. Pending
+/// acknowledgements are also sent on every tick, well within t2.
+const SPONTANEOUS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Wait before accepting again after a failed accept.
+const ACCEPT_RETRY: Duration = Duration::from_millis(100);
+
+/// Largest information object address (3 octets).
+const MAX_ADDRESS: u32 = 0xFF_FFFF;
+
+const BROADCAST_ADDRESS: u16 = 0xFFFF;
+
+/// Local only.
+fn default_bind() -> String {
+    "127.0.0.1:2404".to_string()
+}
+
+fn default_common_address() -> u16 {
+    1
+}
+
+fn default_on() -> Action {
+    Action::Close
+}
+
+fn default_off() -> Action {
+    Action::Open
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PointKind {
+    /// Short floating point measured value (M_ME_NC_1 / M_ME_TF_1).
+    #[default]
+    Measured,
+    /// Single point (M_SP_NA_1 / M_SP_TB_1), non-zero is on.
+    Single,
+    /// Double point (M_DP_NA_1 / M_DP_TB_1), non-zero is on.
+    Double,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandKind {
+    /// Single command (C_SC_NA_1).
+    Single,
+    /// Double command (C_DC_NA_1).
+    Double,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PointEntry {
+    pub channel: String,
+    pub address: u32,
+    #[serde(default)]
+    pub kind: PointKind,
+    /// Smallest change of a measured value transmitted spontaneously.
+    #[serde(default)]
+    pub deadband: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandEntry {
+    /// Equipment targeted by the operator commands.
+    pub target: String,
+    pub address: u32,
+    pub kind: CommandKind,
+    #[serde(default = "default_on")]
+    pub on: Action,
+    #[serde(default = "default_off")]
+    pub off: Action,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerConfig {
+    /// Serving masters on the network, which can then send the mapped
+    /// commands, takes an explicit address such as `0.0.0.0:2404`.
+    #[serde(default = "default_bind")]
+    pub bind: String,
+    #[serde(default = "default_common_address")]
+    pub common_address: u16,
+    #[serde(default)]
+    pub points: Vec<PointEntry>,
+    #[serde(default)]
+    pub commands: Vec<CommandEntry>,
+}
+
+/// Validated information object map, sorted by address.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ObjectMap {
+    pub common_address: u16,
+    pub points: Vec<PointEntry>,
+    pub commands: Vec<CommandEntry>,
+}
+
+impl ObjectMap {
+    /// Rejects out of range and duplicate addresses, monitored and command
+    /// objects sharing a single address space.
+    pub fn build(config: &ServerConfig) -> Result<Self> {
+        if config.common_address == 0 || config.common_address == BROADCAST_ADDRESS {
+            return Err(Error::InvalidMap(format!(
+                "common address {} is reserved",
+                config.common_address
+            )));
+        }
+
+        let mut owners: BTreeMap<u32, &str> = BTreeMap::new();
+        let entries = config
+            .points
+            .iter()
+            .map(|p| (p.address, p.channel.as_str()))
+            .chain(
+                config
+                    .commands
+                    .iter()
+                    .map(|c| (c.address, c.target.as_str())),
+            );
+        for (address, owner) in entries {
+            if address == 0 || address > MAX_ADDRESS {
+                return Err(Error::InvalidMap(format!(
+                    "'{}' has out of range address {}",
+                    owner, address
+                )));
+            }
+            if let Some(other) = owners.insert(address, owner) {
+                return Err(Error::InvalidMap(format!(
+                    "'{}' and '{}' share address {}",
+                    other, owner, address
+                )));
+            }
+        }
+
+        let mut points = config.points.clone();
+        points.sort_by_key(|p| p.address);
+        let mut commands = config.commands.clone();
+        commands.sort_by_key(|c| c.address);
+
+        Ok(Self {
+            common_address: config.common_address,
+            points,
+            commands,
+        })
+    }
+
+    /// Channels whose values are served by this map.
+    pub fn channels(&self) -> Vec<String> {
+        let mut channels: Vec<String> = self.points.iter().map(|p| p.channel.clone()).collect();
+        channels.sort();
+        channels.dedup();
+        channels
+    }
+}
+
+fn quality_bits(quality: Quality) -> u8 {
+    match quality {
+        Quality::Good => 0,
+        Quality::Suspect => quality::NOT_TOPICAL,
+        Quality::Invalid => quality::INVALID,
+    }
+}
+
+/// Whether `current` differs enough from `reported` to be sent spontaneously.
+fn changed(reported: &Element, current: &Element, deadband: f64) -> bool {
+    match (reported, current) {
+        (
+            Element::ShortFloat {
+                value: a,
+                quality: qa,
+                ..
+            },
+            Element::ShortFloat {
+                value: b,
+                quality: qb,
+                ..
+            },
+        ) => qa != qb || f64::from((b - a).abs()) > deadband,
+        _ => reported != current,
+    }
+}
+
+/// Packs objects into as few ASDUs as possible, keeping their order.
+fn pack(cause: u8, common_address: u16, objects: Vec<InformationObject>) -> Vec<Asdu> {
+    let mut asdus: Vec<Asdu> = Vec::new();
+    for object in objects {
+        let object_len = 3 + object.element.len();
+        match asdus.last_mut() {
+            Some(asdu)
+                if asdu.type_id == object.element.type_id()
+                    && asdu.objects.len() < 127
+                    && 6 + asdu.objects.len() * object_len + object_len <= MAX_ASDU_LEN =>
+            {
+                asdu.objects.push(object)
+            }
+            _ => asdus.push(Asdu::new(cause, common_address, vec![object])),
+        }
+    }
+    asdus
+}
+
+/// Answers control direction ASDUs against an object map and the current
+/// channel values.
+pub struct Handler {
+    map: ObjectMap,
+    commands: HashMap<u32, CommandEntry>,
+    values: Values,
+    sink: mpsc::Sender<OperatorCommand>,
+}
+
+impl Handler {
+    pub fn new(map: &ObjectMap, values: Values, sink: mpsc::Sender<OperatorCommand>) -> Self {
+        Self {
+            map: map.clone(),
+            commands: map
+                .commands
+                .iter()
+                .map(|c| (c.address, c.clone()))
+                .collect(),
+            values,
+            sink,
+        }
+    }
+
+    /// Current state of every point, without time tag, along with the time
+    /// of its sample.
+    fn snapshot(&self) -> Vec<(InformationObject, Option<DateTime<Utc>>)> {
+        let values = self.values.read().ok();
+
+        self.map
+            .points
+            .iter()
+            .map(|point| {
+                let sample = values.as_ref().and_then(|v| v.get(&point.channel));
+                let (value, quality) = match sample {
+                    Some(sample) => (sample.value, quality_bits(sample.quality)),
+                    None => (0.0, quality::INVALID | quality::NOT_TOPICAL),
+                };
+                let element = match point.kind {
+                    PointKind::Measured => Element::ShortFloat {
+                        value: value as f32,
+                        quality: if (value as f32).is_finite() {
+                            quality
+                        } else {
+                            quality | quality::OVERFLOW
+                        },
+                        time: None,
+                    },
+                    PointKind::Single => Element::SinglePoint {
+                        value: value != 0.0,
+                        quality,
+                        time: None,
+                    },
+                    PointKind::Double => Element::DoublePoint {
+                        value: if value != 0.0 { 2 } else { 1 },
+                        quality,
+                        time: None,
+                    },
+                };
+                let object = InformationObject {
+                    address: point.address,
+                    element,
+                };
+                (object, sample.map(|sample| sample.timestamp))
+            })
+            .collect()
+    }
+
+    /// Response to a station interrogation.
+    fn interrogation(&self) -> Vec<Asdu> {
+        pack(
+            cause::INTERROGATED,
+            self.map.common_address,
+            self.snapshot()
+                .into_iter()
+                .map(|(object, _)| object)
+                .collect(),
+        )
+    }
+
+    /// Spontaneous ASDUs for the points that changed since they were last
+    /// reported, updating `reported`. They are time-tagged with the time of
+    /// the sample, or now for a point without one.
+    fn changes(&self, reported: &mut HashMap<u32, Element>) -> Vec<Asdu> {
+        let now = Utc::now();
+        let mut objects = Vec::new();
+
+        for (point, (object, time)) in self.map.points.iter().zip(self.snapshot()) {
+            let time = time.unwrap_or(now);
+            if reported
+                .get(&object.address)
+                .is_some_and(|r| !changed(r, &object.element, point.deadband))
+            {
+                continue;
+            }
+            reported.insert(object.address, object.element);
+
+            let element = match object.element {
+                Element::ShortFloat { value, quality, .. } => Element::ShortFloat {
+                    value,
+                    quality,
+                    time: Some(time),
+                },
+                Element::SinglePoint { value, quality, .. } => Element::SinglePoint {
+                    value,
+                    quality,
+                    time: Some(time),
+                },
+                Element::DoublePoint { value, quality, .. } => Element::DoublePoint {
+                    value,
+                    quality,
+                    time: Some(time),
+                },
+                other => other,
+            };
+            objects.push(InformationObject {
+                address: object.address,
+                element,
+            });
+        }
+
+        pack(cause::SPONTANEOUS, self.map.common_address, objects)
+    }
+
+    async fn command(&self, asdu: &Asdu, origin: &str) -> Vec<Asdu> {
+        if asdu.cause == cause::DEACTIVATION {
+            return vec![asdu.mirror(cause::DEACTIVATION_CON, false)];
+        }
+        if asdu.cause != cause::ACTIVATION {
+            return vec![asdu.mirror(cause::UNKNOWN_CAUSE, true)];
+        }
+
+        let [object] = asdu.objects.as_slice() else {
+            return vec![asdu.mirror(cause::ACTIVATION_CON, true)];
+        };
+        let (kind, on, select) = match object.element {
+            Element::SingleCommand { value, select, .. } => {
+                (CommandKind::Single, Some(value), select)
+            }
+            Element::DoubleCommand { value, select, .. } => (
+                CommandKind::Double,
+                match value {
+                    1 => Some(false),
+                    2 => Some(true),
+                    _ => None,
+                },
+                select,
+            ),
+            _ => return vec![asdu.mirror(cause::UNKNOWN_TYPE, true)],
+        };
+
+        let Some(entry) = self
+            .commands
+            .get(&object.address)
+            .filter(|c| c.kind == kind)
+        else {
+            return vec![asdu.mirror(cause::UNKNOWN_OBJECT_ADDRESS, true)];
+        };
+        let Some(on) = on else {
+            return vec![asdu.mirror(cause::ACTIVATION_CON, true)];
+        };
+        if select {
+            return vec![asdu.mirror(cause::ACTIVATION_CON, false)];
+        }
+
+        let action = if on {
+            entry.on.clone()
+        } else {
+            entry.off.clone()
+        };
+        let command = OperatorCommand::new(entry.target.clone(), action, origin);
+        if self.sink.send(command).await.is_err() {
+            return vec![asdu.mirror(cause::ACTIVATION_CON, true)];
+        }
+
+        vec![
+            asdu.mirror(cause::ACTIVATION_CON, false),
+            asdu.mirror(cause::ACTIVATION_TERM, false),
+        ]
+    }
+
+    /// Responses to a control direction ASDU, in transmission order.
+    pub async fn handle(&self, asdu: &Asdu, origin: &str) -> Vec<Asdu> {
+        if asdu.common_address != self.map.common_address
+            && asdu.common_address != BROADCAST_ADDRESS
+        {
+            return vec![asdu.mirror(cause::UNKNOWN_COMMON_ADDRESS, true)];
+        }
+
+        match asdu.type_id {
+            apdu::type_id::C_IC_NA_1 => {
+                let qualifier = match asdu.objects.first().map(|o| o.element) {
+                    Some(Element::Interrogation { qualifier }) => qualifier,
+                    _ => return vec![asdu.mirror(cause::ACTIVATION_CON, true)],
+                };
+                match asdu.cause {
+                    cause::ACTIVATION if qualifier == QOI_STATION => {
+                        let mut responses = vec![asdu.mirror(cause::ACTIVATION_CON, false)];
+                        responses.extend(self.interrogation());
+                        responses.push(asdu.mirror(cause::ACTIVATION_TERM, false));
+                        responses
+                    }
+                    // Group interrogations are not supported.
+                    cause::ACTIVATION => vec![asdu.mirror(cause::ACTIVATION_CON, true)],
+                    cause::DEACTIVATION => vec![asdu.mirror(cause::DEACTIVATION_CON, false)],
+                    _ => vec![asdu.mirror(cause::UNKNOWN_CAUSE, true)],
+                }
+            }
+            apdu::type_id::C_SC_NA_1 | apdu::type_id::C_DC_NA_1 => self.command(asdu, origin).await,
+            _ => vec![asdu.mirror(cause::UNKNOWN_TYPE, true)],
+        }
+    }
+}
+
+/// Link state of one master connection.
+struct Session {
+    handler: Arc<Handler>,
+    origin: String,
+    started: bool,
+    /// V(S), next send sequence number.
+    send: u16,
+    /// V(R), next expected receive sequence number.
+    receive: u16,
+    /// Last N(R) received from the master.
+    acknowledged: u16,
+    /// I-frames received since the last acknowledgement sent.
+    unacknowledged: u16,
+    /// Send time of the I-frames not yet acknowledged by the master.
+    sent: VecDeque<Instant>,
+    queue: VecDeque<Asdu>,
+    reported: HashMap<u32, Element>,
+}
+
+impl Session {
+    fn new(handler: Arc<Handler>, origin: String) -> Self {
+        Self {
+            handler,
+            origin,
+            started: false,
+            send: 0,
+            receive: 0,
+            acknowledged: 0,
+            unacknowledged: 0,
+            sent: VecDeque::new(),
+            queue: VecDeque::new(),
+            reported: HashMap::new(),
+        }
+    }
+
+    fn outstanding(&self) -> u16 {
+        (self.send + SEQUENCE_MODULO - self.acknowledged) % SEQUENCE_MODULO
+    }
+
+    fn acknowledge(&mut self, receive: u16) -> Result<()> {
+        let acknowledged = (receive + SEQUENCE_MODULO - self.acknowledged) % SEQUENCE_MODULO;
+        if acknowledged > self.outstanding() {
+            return Err(Error::SequenceMismatch {
+                expected: self.send,
+                actual: receive,
+            });
+        }
+        self.acknowledged = receive;
+        self.sent.drain(..acknowledged as usize);
+        Ok(())
+    }
+
+    /// Handles one APDU from the master, returning the immediate reply to a
+    /// U-format function.
+    async fn receive(&mut self, apdu: Apdu) -> Result<Option<Apdu>> {
+        match apdu {
+            Apdu::U(UFunction::StartDtAct) => {
+                if !self.started {
+                    self.started = true;
+                    self.handler.changes(&mut self.reported);
+                }
+                Ok(Some(Apdu::U(UFunction::StartDtCon)))
+            }
+            Apdu::U(UFunction::StopDtAct) => {
+                self.started = false;
+                self.queue.clear();
+                Ok(Some(Apdu::U(UFunction::StopDtCon)))
+            }
+            Apdu::U(UFunction::TestFrAct) => Ok(Some(Apdu::U(UFunction::TestFrCon))),
+            Apdu::U(_) => Ok(None),
+            Apdu::S { receive } => {
+                self.acknowledge(receive)?;
+                Ok(None)
+            }
+            Apdu::I {
+                send,
+                receive,
+                asdu,
+            } => {
+                if send != self.receive {
+                    return Err(Error::SequenceMismatch {
+                        expected: self.receive,
+                        actual: send,
+                    });
+                }
+                self.receive = (self.receive + 1) % SEQUENCE_MODULO;
+                self.unacknowledged += 1;
+                self.acknowledge(receive)?;
+
+                if self.started {
+                    let responses = self.handler.handle(&asdu, &self.origin).await;
+                    self.queue.extend(responses);
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    /// Queues the changes while the k window is open, failing once an
+    /// I-frame is left unacknowledged for t1.
+    fn tick(&mut self) -> Result<()> {
+        if self.sent.front().is_some_and(|sent| sent.elapsed() >= T1) {
+            return Err(Error::AcknowledgeTimeout);
+        }
+
+        // Changes made while the window is full are picked up by a later
+        // tick, which only reports the latest value of each point.
+        if self.started && self.outstanding() < K {
+            let changes = self.handler.changes(&mut self.reported);
+            self.queue.extend(changes);
+        }
+        Ok(())
+    }
+
+    /// Sends queued ASDUs within the k window, then an S-frame if received
+    /// I-frames are left unacknowledged and `force` or the w limit is reached.
+    async fn flush(
+        &mut self,
+        writer: &mut (impl AsyncWriteExt + Unpin),
+        force: bool,
+    ) -> Result<()> {
+        while self.outstanding() < K {
+            let Some(asdu) = self.queue.pop_front() else {
+                break;
+            };
+            let frame = apdu::encode(&Apdu::I {
+                send: self.send,
+                receive: self.receive,
+                asdu,
+            });
+            writer.write_all(&frame).await?;
+            self.send = (self.send + 1) % SEQUENCE_MODULO;
+            self.sent.push_back(Instant::now());
+            self.unacknowledged = 0;
+        }
+
+        if self.unacknowledged > 0 && (force || self.unacknowledged >= W) {
+            writer
+                .write_all(&apdu::encode(&Apdu::S {
+                    receive: self.receive,
+                }))
+                .await?;
+            self.unacknowledged = 0;
+        }
+        Ok(())
+    }
+}
+
+async fn serve(socket: TcpStream, handler: Arc<Handler>, token: CancellationToken) -> Result<()> {
+    let mut session = Session::new(handler, format!("iec104:{}", socket.peer_addr()?));
+    let (mut reader, mut writer) = socket.into_split();
+
+    // Reading is not cancel safe, so APDUs are read apart from the session.
+    let (incoming_tx, mut incoming) = mpsc::channel(16);
+    let reading = async move {
+        loop {
+            let result = apdu::read(&mut reader).await;
+            let failed = result.is_err();
+            if incoming_tx.send(result).await.is_err() || failed {
+                break;
+            }
+        }
+        std::future::pending::<()>().await
+    };
+
+    let session = async {
+        let mut ticker = interval(SPONTANEOUS_INTERVAL);
+        loop {
+            let force = tokio::select! {
+                apdu = incoming.recv() => {
+                    let apdu = apdu.ok_or(Error::ConnectionClosed)??;
+                    if let Some(reply) = session.receive(apdu).await? {
+                        writer.write_all(&apdu::encode(&reply)).await?;
+                    }
+                    false
+                }
+                _ = ticker.tick() => {
+                    session.tick()?;
+                    true
+                }
+                _ = token.cancelled() => return Ok(()),
+            };
+            session.flush(&mut writer, force).await?;
+        }
+    };
+
+    tokio::select! {
+        _ = reading => Ok(()),
+        result = session => result,
+    }
+}
+
+/// Accepts master connections on `listener` until `token` is cancelled,
+/// retrying failed accepts.
+pub async fn run(listener: &TcpListener, handler: Handler, token: CancellationToken) -> Result<()> {
+    let handler = Arc::new(handler);
+
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = token.cancelled() => return Ok(()),
+        };
+        let (socket, peer) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                // E.g. out of file descriptors, which may clear once clients leave.
+                log::warn!("Failed to accept IEC 104 connection: {}", e);
+                sleep(ACCEPT_RETRY).await;
+                continue;
+            }
+        };
+        log::info!("IEC 104 master {} connected", peer);
+
+        let handler = handler.clone();
+        let token = token.clone();
+        tokio::spawn(async move {
+            match serve(socket, handler, token).await {
+                Ok(()) | Err(Error::ConnectionClosed) => {
+                    log::info!("IEC 104 master {} disconnected", peer)
+                }
+                Err(e) => log::warn!("IEC 104 master {} dropped: {}", peer, e),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tokio::time::timeout;
+
+    fn config() -> ServerConfig {
+        serde_json::from_value(json!({
+            "bind": "127.0.0.1:0",
+            "common_address": 7,
+            "points": [
+                { "channel": "S1.P", "address": 1001, "deadband": 0.5 },
+                { "channel": "S1.Q", "address": 1002 },
+                { "channel": "S1.CB1.status", "address": 2001, "kind": "double" },
+                { "channel": "S1.alarm", "address": 3001, "kind": "single" },
+            ],
+            "commands": [
+                { "target": "S1.CB1", "address": 5001, "kind": "double" },
+                { "target": "S1.reset", "address": 5002, "kind": "single",
+                  "on": { "type": "set", "value": 1.0 } },
+            ],
+        }))
+        .unwrap()
+    }
+
+    /// Minimal 104 master driving the outstation over TCP.
+    struct Master {
+        socket: TcpStream,
+        send: u16,
+        receive: u16,
+    }
+
+    impl Master {
+        async fn connect(address: &str) -> Self {
+            let mut master = Self {
+                socket: TcpStream::connect(address).await.unwrap(),
+                send: 0,
+                receive: 0,
+            };
+            master.write(Apdu::U(UFunction::StartDtAct)).await;
+            assert_eq!(master.read().await, Apdu::U(UFunction::StartDtCon));
+            master
+        }
+
+        async fn write(&mut self, apdu: Apdu) {
+            self.socket.write_all(&apdu::encode(&apdu)).await.unwrap();
+        }
+
+        async fn read(&mut self) -> Apdu {
+            let apdu = timeout(Duration::from_secs(2), apdu::read(&mut self.socket))
+                .await
+                .expect("no APDU from the outstation")
+                .unwrap();
+            if let Apdu::I { send, .. } = apdu {
+                assert_eq!(send, self.receive);
+                self.receive += 1;
+            }
+            apdu
+        }
+
+        /// Next ASDU, skipping S-frames.
+        async fn asdu(&mut self) -> Asdu {
+            loop {
+                if let Apdu::I { asdu, .. } = self.read().await {
+                    return asdu;
+                }
+            }
+        }
+
+        async fn activate(&mut self, address: u32, element: Element) {
+            let mut asdu = Asdu::new(
+                cause::ACTIVATION,
+                7,
+                vec![InformationObject { address, element }],
+            );
+            asdu.originator = 3;
+            let apdu = Apdu::I {
+                send: self.send,
+                receive: self.receive,
+                asdu,
+            };
+            self.send += 1;
+            self.write(apdu).await;
+        }
+    }
+
+    fn sample(value: f64) -> Sample {
+        Sample {
+            value,
+            quality: Quality::Good,
+            timestamp: Utc::now(),
+        }
+    }
+
+    async fn start() -> (
+        String,
+        Values,
+        mpsc::Receiver<OperatorCommand>,
+        CancellationToken,
+    ) {
+        let map = ObjectMap::build(&config()).unwrap();
+        let values: Values = Arc::new(RwLock::new(HashMap::from([
+            ("S1.P".to_string(), sample(123.5)),
+            ("S1.CB1.status".to_string(), sample(1.0)),
+            (
+                "S1.alarm".to_string(),
+                Sample {
+                    quality: Quality::Invalid,
+                    ..sample(0.0)
+                },
+            ),
+        ])));
+        let (tx, rx) = mpsc::channel(8);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let token = CancellationToken::new();
+        let handler = Handler::new(&map, values.clone(), tx);
+        tokio::spawn({
+            let token = token.clone();
+            async move { run(&listener, handler, token).await }
+        });
+
+        (address, values, rx, token)
+    }
+
+    #[test]
+    fn test_build_rejects_duplicates() {
+        let mut config = config();
+        config.commands[0].address = 1002;
+        assert!(matches!(
+            ObjectMap::build(&config),
+            Err(Error::InvalidMap(_))
+        ));
+
+        let mut config = self::config();
+        config.points[0].address = 0x0100_0000;
+        assert!(matches!(
+            ObjectMap::build(&config),
+            Err(Error::InvalidMap(_))
+        ));
+    }
+
+    #[test]
+    fn test_pack_splits_types() {
+        let map = ObjectMap::build(&config()).unwrap();
+        let (tx, _rx) = mpsc::channel(1);
+        let handler = Handler::new(&map, Values::default(), tx);
+
+        let types: Vec<(u8, usize)> = handler
+            .interrogation()
+            .iter()
+            .map(|a| (a.type_id, a.objects.len()))
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                (apdu::type_id::M_ME_NC_1, 2),
+                (apdu::type_id::M_DP_NA_1, 1),
+                (apdu::type_id::M_SP_NA_1, 1),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_general_interrogation() {
+        let (address, _values, _commands, token) = start().await;
+        let mut master = Master::connect(&address).await;
+
+        master
+            .activate(
+                0,
+                Element::Interrogation {
+                    qualifier: QOI_STATION,
+                },
+            )
+            .await;
+
+        let confirmation = master.asdu().await;
+        assert_eq!(confirmation.cause, cause::ACTIVATION_CON);
+        assert!(!confirmation.negative);
+        assert_eq!(confirmation.originator, 3);
+
+        let mut objects = HashMap::new();
+        loop {
+            let asdu = master.asdu().await;
+            if asdu.cause == cause::ACTIVATION_TERM {
+                break;
+            }
+            assert_eq!(asdu.cause, cause::INTERROGATED);
+            assert_eq!(asdu.common_address, 7);
+            objects.extend(asdu.objects.into_iter().map(|o| (o.address, o.element)));
+        }
+
+        assert_eq!(
+            objects[&1001],
+            Element::ShortFloat {
+                value: 123.5,
+                quality: 0,
+                time: None
+            }
+        );
+        assert!(matches!(
+            objects[&1002],
+            Element::ShortFloat { quality, .. } if quality & quality::INVALID != 0
+        ));
+        assert!(matches!(
+            objects[&2001],
+            Element::DoublePoint { value: 2, .. }
+        ));
+        assert!(matches!(
+            objects[&3001],
+            Element::SinglePoint {
+                value: false,
+                quality: quality::INVALID,
+                ..
+            }
+        ));
+        token.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_spontaneous_transmission() {
+        let (address, values, _commands, token) = start().await;
+        let mut master = Master::connect(&address).await;
+
+        // Within the deadband, then a switch of the breaker.
+        values
+            .write()
+            .unwrap()
+            .insert("S1.P".to_string(), sample(123.7));
+        let switched_at = DateTime::from_timestamp_millis(1_700_000_000_123).unwrap();
+        values.write().unwrap().insert(
+            "S1.CB1.status".to_string(),
+            Sample {
+                timestamp: switched_at,
+                ..sample(0.0)
+            },
+        );
+
+        let asdu = master.asdu().await;
+        assert_eq!(asdu.cause, cause::SPONTANEOUS);
+        assert_eq!(asdu.type_id, apdu::type_id::M_DP_TB_1);
+        assert_eq!(asdu.objects[0].address, 2001);
+        assert!(matches!(
+            asdu.objects[0].element,
+            Element::DoublePoint {
+                value: 1,
+                time: Some(time),
+                ..
+            } if time == switched_at
+        ));
+
+        values
+            .write()
+            .unwrap()
+            .insert("S1.P".to_string(), sample(125.0));
+        let asdu = master.asdu().await;
+        assert_eq!(asdu.type_id, apdu::type_id::M_ME_TF_1);
+        assert!(matches!(
+            asdu.objects[0].element,
+            Element::ShortFloat { value, .. } if value == 125.0
+        ));
+        token.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_commands() {
+        let (address, _values, mut commands, token) = start().await;
+        let mut master = Master::connect(&address).await;
+
+        // Select only confirms, execute dispatches the command.
+        for select in [true, false] {
+            master
+                .activate(
+                    5001,
+                    Element::DoubleCommand {
+                        value: 1,
+                        select,
+                        qualifier: 0,
+                    },
+                )
+                .await;
+            let confirmation = master.asdu().await;
+            assert_eq!(confirmation.cause, cause::ACTIVATION_CON);
+            assert!(!confirmation.negative);
+        }
+        assert_eq!(master.asdu().await.cause, cause::ACTIVATION_TERM);
+
+        let command = commands.recv().await.unwrap();
+        assert_eq!(command.target, "S1.CB1");
+        assert_eq!(command.action, Action::Open);
+        assert!(command.origin.starts_with("iec104:127.0.0.1:"));
+
+        master
+            .activate(
+                5002,
+                Element::SingleCommand {
+                    value: true,
+                    select: false,
+                    qualifier: 0,
+                },
+            )
+            .await;
+        assert_eq!(master.asdu().await.cause, cause::ACTIVATION_CON);
+        assert_eq!(master.asdu().await.cause, cause::ACTIVATION_TERM);
+        assert_eq!(
+            commands.recv().await.unwrap().action,
+            Action::Set { value: 1.0 }
+        );
+
+        // A single command on a double command object is unknown.
+        master
+            .activate(
+                5001,
+                Element::SingleCommand {
+                    value: true,
+                    select: false,
+                    qualifier: 0,
+                },
+            )
+            .await;
+        let rejection = master.asdu().await;
+        assert_eq!(rejection.cause, cause::UNKNOWN_OBJECT_ADDRESS);
+        assert!(rejection.negative);
+        assert!(commands.try_recv().is_err());
+        token.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_link_layer() {
+        let (address, _values, _commands, token) = start().await;
+        let mut master = Master::connect(&address).await;
+
+        master.write(Apdu::U(UFunction::TestFrAct)).await;
+        assert_eq!(master.read().await, Apdu::U(UFunction::TestFrCon));
+
+        // Group interrogations are rejected, acknowledging the request.
+        for expected in 1..=2 {
+            master
+                .activate(0, Element::Interrogation { qualifier: 21 })
+                .await;
+            let Apdu::I { receive, asdu, .. } = master.read().await else {
+                panic!("expected an I-format APDU");
+            };
+            assert_eq!(receive, expected);
+            assert!(asdu.negative);
+        }
+
+        // Out of sequence I-frames drop the connection.
+        master.send = 10;
+        master
+            .activate(0, Element::Interrogation { qualifier: 21 })
+            .await;
+        let mut buf = [0u8; 1];
+        let closed = timeout(Duration::from_secs(2), async {
+            loop {
+                match tokio::io::AsyncReadExt::read(&mut master.socket, &mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => continue,
+                }
+            }
+        })
+        .await;
+        assert!(closed.is_ok());
+        token.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_full_window() {
+        let map = ObjectMap::build(&config()).unwrap();
+        let (tx, _rx) = mpsc::channel(1);
+        let values = Values::default();
+        let handler = Arc::new(Handler::new(&map, values.clone(), tx));
+        let mut session = Session::new(handler, "test".to_string());
+        session
+            .receive(Apdu::U(UFunction::StartDtAct))
+            .await
+            .unwrap();
+
+        // The master does not acknowledge while the alarm keeps toggling.
+        let mut writer = Vec::new();
+        for i in 0..3 * K {
+            let value = (i % 2) as f64;
+            values
+                .write()
+                .unwrap()
+                .insert("S1.alarm".to_string(), sample(value));
+            session.tick().unwrap();
+            session.flush(&mut writer, true).await.unwrap();
+        }
+        assert_eq!(session.outstanding(), K);
+        assert!(session.queue.is_empty());
+
+        session
+            .receive(Apdu::S {
+                receive: session.send,
+            })
+            .await
+            .unwrap();
+        assert_eq!(session.outstanding(), 0);
+        assert!(session.sent.is_empty());
+
+        values
+            .write()
+            .unwrap()
+            .insert("S1.alarm".to_string(), sample(0.0));
+        session.tick().unwrap();
+        session.flush(&mut writer, true).await.unwrap();
+        assert_eq!(session.outstanding(), 1);
+
+        session.sent[0] = Instant::now() - T1;
+        assert!(matches!(session.tick(), Err(Error::AcknowledgeTimeout)));
+    }
+}
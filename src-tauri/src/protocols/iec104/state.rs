@@ -0,0 +1,17 @@
+use tokio::sync::Mutex;
+
+use crate::utils::tasks::supervisor::Supervised;
+
+use super::server::ObjectMap;
+
+pub struct ServerHandle {
+    pub map: ObjectMap,
+    pub supervised: Supervised,
+}
+
+#[derive(Default)]
+pub struct ServerInner {
+    pub server: Option<ServerHandle>,
+}
+
+pub type Server = Mutex<ServerInner>;
@@ -0,0 +1,3 @@
+pub mod c37118;
+pub mod iec104;
+pub mod modbus;
@@ -0,0 +1,162 @@
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::AsyncWriteExt,
+    net::TcpStream,
+    time::{timeout, Duration},
+};
+
+use super::error::{Error, Result};
+use super::frame::{decode_response, encode_request, read_adu, Header, Request, Response};
+use super::value::Encoding;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Table {
+    Holding,
+    Input,
+}
+
+/// Write issued by an operator through the `write_modbus` command.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Write {
+    Coil {
+        address: u16,
+        value: bool,
+    },
+    Coils {
+        address: u16,
+        values: Vec<bool>,
+    },
+    Register {
+        address: u16,
+        value: u16,
+    },
+    Registers {
+        address: u16,
+        values: Vec<u16>,
+    },
+    /// Engineering value encoded into one or two holding registers.
+    Value {
+        address: u16,
+        value: f64,
+        #[serde(flatten)]
+        encoding: Encoding,
+    },
+}
+
+impl Write {
+    /// Request of the write, checked against the limits of its function.
+    fn request(&self) -> Result<Request> {
+        let request = match self {
+            Write::Coil { address, value } => Request::WriteSingleCoil {
+                address: *address,
+                value: *value,
+            },
+            Write::Coils { address, values } => Request::WriteMultipleCoils {
+                address: *address,
+                values: values.clone(),
+            },
+            Write::Register { address, value } => Request::WriteSingleRegister {
+                address: *address,
+                value: *value,
+            },
+            Write::Registers { address, values } => Request::WriteMultipleRegisters {
+                address: *address,
+                values: values.clone(),
+            },
+            Write::Value {
+                address,
+                value,
+                encoding,
+            } => Request::WriteMultipleRegisters {
+                address: *address,
+                values: encoding.encode(*value),
+            },
+        };
+        request.validate()?;
+        Ok(request)
+    }
+}
+
+/// Modbus TCP client issuing one request at a time.
+pub struct Client {
+    stream: TcpStream,
+    unit: u8,
+    transaction: u16,
+}
+
+impl Client {
+    pub async fn connect(address: &str, unit: u8) -> Result<Self> {
+        let stream = timeout(REQUEST_TIMEOUT, TcpStream::connect(address))
+            .await
+            .map_err(|_| Error::Timeout)??;
+        stream.set_nodelay(true)?;
+
+        Ok(Self {
+            stream,
+            unit,
+            transaction: 0,
+        })
+    }
+
+    /// Sends `request` and waits for its response, exception responses are
+    /// turned into `Error::Exception`.
+    pub async fn call(&mut self, request: Request) -> Result<Response> {
+        self.transaction = self.transaction.wrapping_add(1);
+        let header = Header {
+            transaction: self.transaction,
+            unit: self.unit,
+        };
+
+        let frame = encode_request(header, &request)?;
+
+        let exchange = async {
+            self.stream.write_all(&frame).await?;
+            read_adu(&mut self.stream).await
+        };
+        let (response_header, pdu) = timeout(REQUEST_TIMEOUT, exchange)
+            .await
+            .map_err(|_| Error::Timeout)??;
+
+        if response_header.transaction != header.transaction {
+            return Err(Error::TransactionMismatch {
+                expected: header.transaction,
+                actual: response_header.transaction,
+            });
+        }
+
+        match decode_response(&pdu, &request)? {
+            Response::Exception { function, code } => Err(Error::Exception { function, code }),
+            response => Ok(response),
+        }
+    }
+
+    pub async fn read_registers(
+        &mut self,
+        table: Table,
+        address: u16,
+        count: u16,
+    ) -> Result<Vec<u16>> {
+        let request = match table {
+            Table::Holding => Request::ReadHoldingRegisters { address, count },
+            Table::Input => Request::ReadInputRegisters { address, count },
+        };
+
+        match self.call(request).await? {
+            Response::ReadHoldingRegisters(values) | Response::ReadInputRegisters(values) => {
+                Ok(values)
+            }
+            other => Err(Error::InvalidFrame(format!(
+                "unexpected response {:?}",
+                other
+            ))),
+        }
+    }
+
+    pub async fn write(&mut self, write: &Write) -> Result<()> {
+        self.call(write.request()?).await.map(|_| ())
+    }
+}
@@ -0,0 +1,272 @@
+use std::sync::Arc;
+
+use serde::Serialize;
+use tauri::{AppHandle, Manager, State};
+use tokio::{
+    net::TcpListener,
+    sync::{mpsc, oneshot, Mutex},
+    time::{interval, Duration},
+};
+
+use crate::settings::database::state::DatabaseState;
+use crate::utils::{
+    channels::{source::Sample, state::Channels},
+    control,
+    tasks::supervisor::{self, Supervision, SupervisorStatus},
+};
+
+use super::client::Write;
+use super::error::{Error, Result};
+use super::poller::{self, PollerConfig};
+use super::server::{self, Handler, RegisterMap, ServerConfig, Values};
+use super::state::{Poller, Pollers, Server, ServerHandle};
+
+/// How often the values served to SCADA are refreshed from the channels.
+const SERVER_REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Serialize)]
+pub struct PollerStatus {
+    id: String,
+    config: PollerConfig,
+    running: bool,
+    supervisor: SupervisorStatus,
+}
+
+/// Starts polling a Modbus TCP device and publishes the decoded registers into
+/// the matching external channels, restarting the poller on failure unless
+/// `supervision` says otherwise.
+#[tauri::command]
+pub async fn connect_modbus(
+    app: AppHandle,
+    state: State<'_, Pollers>,
+    id: String,
+    config: PollerConfig,
+    supervision: Option<Supervision>,
+) -> Result<()> {
+    let mut pollers = state.lock().await;
+
+    if pollers.pollers.contains_key(&id) {
+        return Err(Error::PollerAlreadyExists { id });
+    }
+
+    let (writes, writes_rx) = mpsc::channel(16);
+    // Handed from one run of the poller to the next.
+    let writes_rx = Arc::new(Mutex::new(writes_rx));
+    let config_clone = config.clone();
+    let id_clone = id.clone();
+
+    let supervision = supervision.unwrap_or_default();
+    let supervised = supervisor::supervise(id.clone(), supervision, move |token| {
+        let config = config_clone.clone();
+        let id = id_clone.clone();
+        let app = app.clone();
+        let writes_rx = writes_rx.clone();
+
+        async move {
+            let (tx, mut rx) = mpsc::channel::<Vec<(String, Sample)>>(64);
+            let mut writes_rx = writes_rx.lock().await;
+
+            let forward = async {
+                while let Some(samples) = rx.recv().await {
+                    let channels = app.state::<Channels>();
+                    for (channel, sample) in samples {
+                        channels.publish(&channel, sample);
+                    }
+                }
+            };
+
+            let (result, _) = tokio::join!(
+                poller::run(&config, (*token).clone(), tx, &mut writes_rx),
+                forward
+            );
+            match result {
+                Ok(()) => {
+                    log::info!("Modbus poller '{}' stopped", id);
+                    Ok(())
+                }
+                Err(e) => Err(e.to_string()),
+            }
+        }
+    });
+
+    pollers.pollers.insert(
+        id.clone(),
+        Poller {
+            config,
+            writes,
+            supervised,
+        },
+    );
+    log::info!("Successfully started Modbus poller '{}'", id);
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn disconnect_modbus(state: State<'_, Pollers>, id: String) -> Result<()> {
+    let mut pollers = state.lock().await;
+
+    match pollers.pollers.remove(&id) {
+        Some(poller) => {
+            poller.supervised.cancel();
+            log::info!("Successfully stopped Modbus poller '{}'", id);
+            Ok(())
+        }
+        None => {
+            log::warn!("Attempted to stop non-existent Modbus poller '{}'", id);
+            Err(Error::PollerNotFound { id })
+        }
+    }
+}
+
+/// Writes coils or registers through the connection of a running poller.
+#[tauri::command]
+pub async fn write_modbus(state: State<'_, Pollers>, id: String, write: Write) -> Result<()> {
+    let writes = match state.lock().await.pollers.get(&id) {
+        Some(poller) => poller.writes.clone(),
+        None => return Err(Error::PollerNotFound { id }),
+    };
+
+    let (reply, result) = oneshot::channel();
+    if writes.send((write, reply)).await.is_err() {
+        return Err(Error::PollerStopped { id });
+    }
+    result.await.map_err(|_| Error::PollerStopped { id })?
+}
+
+#[tauri::command]
+pub async fn list_modbus(state: State<'_, Pollers>) -> Result<Vec<PollerStatus>> {
+    let pollers = state.lock().await;
+
+    let statuses = pollers
+        .pollers
+        .iter()
+        .map(|(id, poller)| PollerStatus {
+            id: id.clone(),
+            config: poller.config.clone(),
+            running: !poller.supervised.task.is_finished(),
+            supervisor: poller.supervised.status(),
+        })
+        .collect();
+
+    Ok(statuses)
+}
+
+/// Starts the Modbus server with the register map stored under the
+/// `modbus.server` setting and returns the resolved map. The server is
+/// restarted on failure unless `supervision` says otherwise.
+#[tauri::command]
+pub async fn start_modbus_server(
+    app: AppHandle,
+    state: State<'_, Server>,
+    db: State<'_, Mutex<DatabaseState>>,
+    supervision: Option<Supervision>,
+) -> Result<RegisterMap> {
+    let mut server = state.lock().await;
+
+    if server.server.is_some() {
+        return Err(Error::ServerAlreadyRunning);
+    }
+
+    let config = db
+        .lock()
+        .await
+        .get_setting::<ServerConfig>(server::SETTINGS_KEY)
+        .await?
+        .ok_or_else(|| Error::MissingSettings(server::SETTINGS_KEY.to_string()))?;
+    let map = RegisterMap::build(&config)?;
+    let listener = TcpListener::bind(&config.bind).await?;
+    log::info!(
+        "Modbus server listening on {} ({} registers, {} coils)",
+        config.bind,
+        map.registers.len(),
+        map.coils.len()
+    );
+
+    let listener = Arc::new(listener);
+    let map_clone = map.clone();
+
+    let supervision = supervision.unwrap_or_default();
+    let name = "modbus-server".to_string();
+    let supervised = supervisor::supervise(name, supervision, move |token| {
+        let map = map_clone.clone();
+        let listener = listener.clone();
+        let app = app.clone();
+
+        async move {
+            let values = Values::default();
+            let channels = map.channels();
+            let (tx, mut rx) = mpsc::channel(16);
+            let handler = Handler::new(&map, values.clone(), tx);
+            // Cancelled once the server ends, to end the refresh with it.
+            let stop = token.child();
+
+            let serve = async {
+                let result = server::run(&listener, handler, (*stop).clone()).await;
+                stop.cancel();
+                result
+            };
+
+            let dispatch = async {
+                while let Some(command) = rx.recv().await {
+                    control::dispatch(&app, &command);
+                }
+            };
+
+            let refresh = async {
+                let mut ticker = interval(SERVER_REFRESH_INTERVAL);
+                loop {
+                    tokio::select! {
+                        _ = ticker.tick() => {
+                            let state = app.state::<Channels>();
+                            if let Ok(mut values) = values.write() {
+                                for id in &channels {
+                                    if let Some(sample) = state.latest(id) {
+                                        values.insert(id.clone(), sample.value);
+                                    }
+                                }
+                            }
+                        }
+                        _ = stop.cancelled() => break,
+                    }
+                }
+            };
+
+            let (result, _, _) = tokio::join!(serve, dispatch, refresh);
+            match result {
+                Ok(()) => {
+                    log::info!("Modbus server stopped");
+                    Ok(())
+                }
+                Err(e) => Err(e.to_string()),
+            }
+        }
+    });
+
+    server.server = Some(ServerHandle {
+        map: map.clone(),
+        supervised,
+    });
+
+    Ok(map)
+}
+
+#[tauri::command]
+pub async fn stop_modbus_server(state: State<'_, Server>) -> Result<()> {
+    match state.lock().await.server.take() {
+        Some(server) => {
+            server.supervised.cancel();
+            log::info!("Successfully stopped Modbus server");
+            Ok(())
+        }
+        None => Err(Error::ServerNotRunning),
+    }
+}
+
+#[tauri::command]
+pub async fn get_modbus_server_map(state: State<'_, Server>) -> Result<RegisterMap> {
+    match &state.lock().await.server {
+        Some(server) => Ok(server.map.clone()),
+        None => Err(Error::ServerNotRunning),
+    }
+}
@@ -0,0 +1,71 @@
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::settings::database::error::Error as DatabaseError;
+
+use super::frame::ExceptionCode;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Request timed out")]
+    Timeout,
+
+    #[error("Invalid Modbus frame: {0}")]
+    InvalidFrame(String),
+
+    #[error("Invalid Modbus request: {0}")]
+    InvalidRequest(String),
+
+    #[error("Transaction mismatch: expected {expected}, got {actual}")]
+    TransactionMismatch { expected: u16, actual: u16 },
+
+    #[error("Modbus exception on function {function:#04x}: {code:?}")]
+    Exception { function: u8, code: ExceptionCode },
+
+    #[error("Connection closed by peer")]
+    ConnectionClosed,
+
+    #[error("Modbus poller with id '{id}' not found")]
+    PollerNotFound { id: String },
+
+    #[error("Modbus poller with id '{id}' already exists")]
+    PollerAlreadyExists { id: String },
+
+    #[error("Modbus poller with id '{id}' is not running")]
+    PollerStopped { id: String },
+
+    #[error("Invalid register map: {0}")]
+    InvalidMap(String),
+
+    #[error("Settings error: {0}")]
+    Settings(#[from] DatabaseError),
+
+    #[error("Missing setting '{0}'")]
+    MissingSettings(String),
+
+    #[error("Modbus server is already running")]
+    ServerAlreadyRunning,
+
+    #[error("Modbus server is not running")]
+    ServerNotRunning,
+}
+
+impl Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_ref())
+    }
+}
+
+impl From<Error> for String {
+    fn from(err: Error) -> Self {
+        err.to_string()
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
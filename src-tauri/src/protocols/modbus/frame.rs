@@ -0,0 +1,665 @@
+//! Modbus TCP application data units (MBAP header followed by the PDU).
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::error::{Error, Result};
+
+/// Transaction id, protocol id, length and unit id.
+pub const MBAP_LEN: usize = 7;
+
+/// Largest register count of a single read request.
+pub const MAX_READ_REGISTERS: u16 = 125;
+
+/// Largest register count of a single write request.
+pub const MAX_WRITE_REGISTERS: u16 = 123;
+
+/// Largest coil count of a single read request.
+pub const MAX_READ_COILS: u16 = 2000;
+
+/// Largest coil count of a single write request.
+pub const MAX_WRITE_COILS: u16 = 1968;
+
+/// Largest byte count of the values carried by a PDU.
+const MAX_BYTE_COUNT: usize = 250;
+
+/// Number of addresses of a table.
+const ADDRESS_SPACE: usize = 0x10000;
+
+const EXCEPTION_FLAG: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub transaction: u16,
+    pub unit: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExceptionCode {
+    IllegalFunction,
+    IllegalDataAddress,
+    IllegalDataValue,
+    ServerDeviceFailure,
+    Other(u8),
+}
+
+impl From<u8> for ExceptionCode {
+    fn from(code: u8) -> Self {
+        match code {
+            1 => ExceptionCode::IllegalFunction,
+            2 => ExceptionCode::IllegalDataAddress,
+            3 => ExceptionCode::IllegalDataValue,
+            4 => ExceptionCode::ServerDeviceFailure,
+            other => ExceptionCode::Other(other),
+        }
+    }
+}
+
+impl From<ExceptionCode> for u8 {
+    fn from(code: ExceptionCode) -> Self {
+        match code {
+            ExceptionCode::IllegalFunction => 1,
+            ExceptionCode::IllegalDataAddress => 2,
+            ExceptionCode::IllegalDataValue => 3,
+            ExceptionCode::ServerDeviceFailure => 4,
+            ExceptionCode::Other(other) => other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    ReadCoils { address: u16, count: u16 },
+    ReadDiscreteInputs { address: u16, count: u16 },
+    ReadHoldingRegisters { address: u16, count: u16 },
+    ReadInputRegisters { address: u16, count: u16 },
+    WriteSingleCoil { address: u16, value: bool },
+    WriteSingleRegister { address: u16, value: u16 },
+    WriteMultipleCoils { address: u16, values: Vec<bool> },
+    WriteMultipleRegisters { address: u16, values: Vec<u16> },
+}
+
+impl Request {
+    pub fn function(&self) -> u8 {
+        match self {
+            Request::ReadCoils { .. } => 0x01,
+            Request::ReadDiscreteInputs { .. } => 0x02,
+            Request::ReadHoldingRegisters { .. } => 0x03,
+            Request::ReadInputRegisters { .. } => 0x04,
+            Request::WriteSingleCoil { .. } => 0x05,
+            Request::WriteSingleRegister { .. } => 0x06,
+            Request::WriteMultipleCoils { .. } => 0x0F,
+            Request::WriteMultipleRegisters { .. } => 0x10,
+        }
+    }
+
+    /// First address and number of items covered by the request.
+    pub fn span(&self) -> (u16, usize) {
+        match self {
+            Request::ReadCoils { address, count }
+            | Request::ReadDiscreteInputs { address, count }
+            | Request::ReadHoldingRegisters { address, count }
+            | Request::ReadInputRegisters { address, count } => (*address, *count as usize),
+            Request::WriteSingleCoil { address, .. }
+            | Request::WriteSingleRegister { address, .. } => (*address, 1),
+            Request::WriteMultipleCoils { address, values } => (*address, values.len()),
+            Request::WriteMultipleRegisters { address, values } => (*address, values.len()),
+        }
+    }
+
+    /// Largest number of items of a request of this function.
+    pub fn max_count(&self) -> usize {
+        let max = match self {
+            Request::ReadCoils { .. } | Request::ReadDiscreteInputs { .. } => MAX_READ_COILS,
+            Request::ReadHoldingRegisters { .. } | Request::ReadInputRegisters { .. } => {
+                MAX_READ_REGISTERS
+            }
+            Request::WriteSingleCoil { .. } | Request::WriteSingleRegister { .. } => 1,
+            Request::WriteMultipleCoils { .. } => MAX_WRITE_COILS,
+            Request::WriteMultipleRegisters { .. } => MAX_WRITE_REGISTERS,
+        };
+        max as usize
+    }
+
+    /// Checks that the request covers 1 to `max_count` items, all within the
+    /// address space.
+    pub fn validate(&self) -> Result<()> {
+        let (address, count) = self.span();
+        let max = self.max_count();
+        if !(1..=max).contains(&count) {
+            return Err(Error::InvalidRequest(format!(
+                "function {:#04x} takes 1 to {} items, got {}",
+                self.function(),
+                max,
+                count
+            )));
+        }
+        if address as usize + count > ADDRESS_SPACE {
+            return Err(Error::InvalidRequest(format!(
+                "{} items from address {} exceed the address space",
+                count, address
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    ReadCoils(Vec<bool>),
+    ReadDiscreteInputs(Vec<bool>),
+    ReadHoldingRegisters(Vec<u16>),
+    ReadInputRegisters(Vec<u16>),
+    WriteSingleCoil { address: u16, value: bool },
+    WriteSingleRegister { address: u16, value: u16 },
+    WriteMultipleCoils { address: u16, count: u16 },
+    WriteMultipleRegisters { address: u16, count: u16 },
+    Exception { function: u8, code: ExceptionCode },
+}
+
+fn pack_bits(values: &[bool]) -> Vec<u8> {
+    let mut bytes = vec![0u8; values.len().div_ceil(8)];
+    for (i, value) in values.iter().enumerate() {
+        if *value {
+            bytes[i / 8] |= 1 << (i % 8);
+        }
+    }
+    bytes
+}
+
+fn unpack_bits(bytes: &[u8], count: usize) -> Vec<bool> {
+    (0..count)
+        .map(|i| bytes.get(i / 8).is_some_and(|b| b & (1 << (i % 8)) != 0))
+        .collect()
+}
+
+fn pack_registers(values: &[u16]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_be_bytes()).collect()
+}
+
+fn unpack_registers(bytes: &[u8]) -> Vec<u16> {
+    bytes
+        .chunks_exact(2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .collect()
+}
+
+/// Byte count field of `len` bytes of values.
+fn byte_count(len: usize) -> Result<u8> {
+    if len > MAX_BYTE_COUNT {
+        return Err(Error::InvalidFrame(format!(
+            "{} bytes of values exceed a PDU",
+            len
+        )));
+    }
+    Ok(len as u8)
+}
+
+fn coil_value(value: bool) -> u16 {
+    if value {
+        0xFF00
+    } else {
+        0x0000
+    }
+}
+
+fn adu(header: Header, pdu: Vec<u8>) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(MBAP_LEN + pdu.len());
+    frame.extend_from_slice(&header.transaction.to_be_bytes());
+    frame.extend_from_slice(&0u16.to_be_bytes());
+    frame.extend_from_slice(&((pdu.len() + 1) as u16).to_be_bytes());
+    frame.push(header.unit);
+    frame.extend(pdu);
+    frame
+}
+
+struct Reader<'a> {
+    pdu: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(pdu: &'a [u8]) -> Self {
+        Self { pdu, pos: 1 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos + len;
+        let slice = self
+            .pdu
+            .get(self.pos..end)
+            .ok_or_else(|| Error::InvalidFrame(format!("PDU truncated at {} bytes", end)))?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+}
+
+/// Encodes `request`, which must pass `Request::validate`.
+pub fn encode_request(header: Header, request: &Request) -> Result<Vec<u8>> {
+    request.validate()?;
+
+    let mut pdu = vec![request.function()];
+    match request {
+        Request::ReadCoils { address, count }
+        | Request::ReadDiscreteInputs { address, count }
+        | Request::ReadHoldingRegisters { address, count }
+        | Request::ReadInputRegisters { address, count } => {
+            pdu.extend_from_slice(&address.to_be_bytes());
+            pdu.extend_from_slice(&count.to_be_bytes());
+        }
+        Request::WriteSingleCoil { address, value } => {
+            pdu.extend_from_slice(&address.to_be_bytes());
+            pdu.extend_from_slice(&coil_value(*value).to_be_bytes());
+        }
+        Request::WriteSingleRegister { address, value } => {
+            pdu.extend_from_slice(&address.to_be_bytes());
+            pdu.extend_from_slice(&value.to_be_bytes());
+        }
+        Request::WriteMultipleCoils { address, values } => {
+            let bytes = pack_bits(values);
+            pdu.extend_from_slice(&address.to_be_bytes());
+            pdu.extend_from_slice(&(values.len() as u16).to_be_bytes());
+            pdu.push(byte_count(bytes.len())?);
+            pdu.extend(bytes);
+        }
+        Request::WriteMultipleRegisters { address, values } => {
+            pdu.extend_from_slice(&address.to_be_bytes());
+            pdu.extend_from_slice(&(values.len() as u16).to_be_bytes());
+            pdu.push(byte_count(values.len() * 2)?);
+            pdu.extend(pack_registers(values));
+        }
+    }
+    Ok(adu(header, pdu))
+}
+
+/// Decodes a request PDU, unsupported function codes are reported as an
+/// `IllegalFunction` exception so a server can answer them.
+pub fn decode_request(pdu: &[u8]) -> core::result::Result<Request, Response> {
+    let function = *pdu.first().unwrap_or(&0);
+    let exception = |code| Response::Exception { function, code };
+    let mut reader = Reader::new(pdu);
+
+    let parse = |reader: &mut Reader| -> Result<Option<Request>> {
+        let address = reader.u16()?;
+        let request = match function {
+            0x01 => Request::ReadCoils {
+                address,
+                count: reader.u16()?,
+            },
+            0x02 => Request::ReadDiscreteInputs {
+                address,
+                count: reader.u16()?,
+            },
+            0x03 => Request::ReadHoldingRegisters {
+                address,
+                count: reader.u16()?,
+            },
+            0x04 => Request::ReadInputRegisters {
+                address,
+                count: reader.u16()?,
+            },
+            0x05 => Request::WriteSingleCoil {
+                address,
+                value: reader.u16()? == 0xFF00,
+            },
+            0x06 => Request::WriteSingleRegister {
+                address,
+                value: reader.u16()?,
+            },
+            0x0F => {
+                let count = reader.u16()? as usize;
+                let len = reader.u8()? as usize;
+                Request::WriteMultipleCoils {
+                    address,
+                    values: unpack_bits(reader.take(len)?, count),
+                }
+            }
+            0x10 => {
+                let _count = reader.u16()?;
+                let len = reader.u8()? as usize;
+                Request::WriteMultipleRegisters {
+                    address,
+                    values: unpack_registers(reader.take(len)?),
+                }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(request))
+    };
+
+    match parse(&mut reader) {
+        Ok(Some(request)) => Ok(request),
+        Ok(None) => Err(exception(ExceptionCode::IllegalFunction)),
+        Err(_) if !matches!(function, 0x01..=0x06 | 0x0F | 0x10) => {
+            Err(exception(ExceptionCode::IllegalFunction))
+        }
+        Err(_) => Err(exception(ExceptionCode::IllegalDataValue)),
+    }
+}
+
+pub fn encode_response(header: Header, response: &Response) -> Result<Vec<u8>> {
+    let pdu = match response {
+        Response::ReadCoils(values) | Response::ReadDiscreteInputs(values) => {
+            let function = if matches!(response, Response::ReadCoils(_)) {
+                0x01
+            } else {
+                0x02
+            };
+            let bytes = pack_bits(values);
+            let mut pdu = vec![function, byte_count(bytes.len())?];
+            pdu.extend(bytes);
+            pdu
+        }
+        Response::ReadHoldingRegisters(values) | Response::ReadInputRegisters(values) => {
+            let function = if matches!(response, Response::ReadHoldingRegisters(_)) {
+                0x03
+            } else {
+                0x04
+            };
+            let mut pdu = vec![function, byte_count(values.len() * 2)?];
+            pdu.extend(pack_registers(values));
+            pdu
+        }
+        Response::WriteSingleCoil { address, value } => {
+            let mut pdu = vec![0x05];
+            pdu.extend_from_slice(&address.to_be_bytes());
+            pdu.extend_from_slice(&coil_value(*value).to_be_bytes());
+            pdu
+        }
+        Response::WriteSingleRegister { address, value } => {
+            let mut pdu = vec![0x06];
+            pdu.extend_from_slice(&address.to_be_bytes());
+            pdu.extend_from_slice(&value.to_be_bytes());
+            pdu
+        }
+        Response::WriteMultipleCoils { address, count }
+        | Response::WriteMultipleRegisters { address, count } => {
+            let function = if matches!(response, Response::WriteMultipleCoils { .. }) {
+                0x0F
+            } else {
+                0x10
+            };
+            let mut pdu = vec![function];
+            pdu.extend_from_slice(&address.to_be_bytes());
+            pdu.extend_from_slice(&count.to_be_bytes());
+            pdu
+        }
+        Response::Exception { function, code } => {
+            vec![function | EXCEPTION_FLAG, u8::from(*code)]
+        }
+    };
+    Ok(adu(header, pdu))
+}
+
+/// Decodes the response PDU to `request`.
+pub fn decode_response(pdu: &[u8], request: &Request) -> Result<Response> {
+    let function = *pdu
+        .first()
+        .ok_or_else(|| Error::InvalidFrame("empty PDU".to_string()))?;
+    let mut reader = Reader::new(pdu);
+
+    if function == request.function() | EXCEPTION_FLAG {
+        return Ok(Response::Exception {
+            function: request.function(),
+            code: ExceptionCode::from(reader.u8()?),
+        });
+    }
+    if function != request.function() {
+        return Err(Error::InvalidFrame(format!(
+            "expected function {:#04x}, got {:#04x}",
+            request.function(),
+            function
+        )));
+    }
+
+    let response = match request {
+        Request::ReadCoils { count, .. } | Request::ReadDiscreteInputs { count, .. } => {
+            let len = reader.u8()? as usize;
+            let values = unpack_bits(reader.take(len)?, *count as usize);
+            if matches!(request, Request::ReadCoils { .. }) {
+                Response::ReadCoils(values)
+            } else {
+                Response::ReadDiscreteInputs(values)
+            }
+        }
+        Request::ReadHoldingRegisters { count, .. } | Request::ReadInputRegisters { count, .. } => {
+            let len = reader.u8()? as usize;
+            let values = unpack_registers(reader.take(len)?);
+            if values.len() != *count as usize {
+                return Err(Error::InvalidFrame(format!(
+                    "expected {} registers, got {}",
+                    count,
+                    values.len()
+                )));
+            }
+            if matches!(request, Request::ReadHoldingRegisters { .. }) {
+                Response::ReadHoldingRegisters(values)
+            } else {
+                Response::ReadInputRegisters(values)
+            }
+        }
+        Request::WriteSingleCoil { .. } => Response::WriteSingleCoil {
+            address: reader.u16()?,
+            value: reader.u16()? == 0xFF00,
+        },
+        Request::WriteSingleRegister { .. } => Response::WriteSingleRegister {
+            address: reader.u16()?,
+            value: reader.u16()?,
+        },
+        Request::WriteMultipleCoils { .. } => Response::WriteMultipleCoils {
+            address: reader.u16()?,
+            count: reader.u16()?,
+        },
+        Request::WriteMultipleRegisters { .. } => Response::WriteMultipleRegisters {
+            address: reader.u16()?,
+            count: reader.u16()?,
+        },
+    };
+    Ok(response)
+}
+
+/// Reads one ADU, returning its header and PDU.
+pub async fn read_adu<R: AsyncRead + Unpin>(reader: &mut R) -> Result<(Header, Vec<u8>)> {
+    let mut mbap = [0u8; MBAP_LEN];
+    if let Err(e) = reader.read_exact(&mut mbap).await {
+        return match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => Err(Error::ConnectionClosed),
+            _ => Err(e.into()),
+        };
+    }
+
+    let protocol = u16::from_be_bytes([mbap[2], mbap[3]]);
+    if protocol != 0 {
+        return Err(Error::InvalidFrame(format!(
+            "unexpected protocol id {}",
+            protocol
+        )));
+    }
+    let length = u16::from_be_bytes([mbap[4], mbap[5]]) as usize;
+    if !(2..=254).contains(&length) {
+        return Err(Error::InvalidFrame(format!("invalid length {}", length)));
+    }
+
+    let mut pdu = vec![0u8; length - 1];
+    reader.read_exact(&mut pdu).await?;
+
+    let header = Header {
+        transaction: u16::from_be_bytes([mbap[0], mbap[1]]),
+        unit: mbap[6],
+    };
+    Ok((header, pdu))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: Header = Header {
+        transaction: 0x1234,
+        unit: 1,
+    };
+
+    async fn roundtrip_request(request: Request) -> Request {
+        let frame = encode_request(HEADER, &request).unwrap();
+        let (header, pdu) = read_adu(&mut frame.as_slice()).await.unwrap();
+        assert_eq!(header, HEADER);
+        decode_request(&pdu).unwrap()
+    }
+
+    async fn roundtrip_response(request: &Request, response: Response) -> Response {
+        let frame = encode_response(HEADER, &response).unwrap();
+        let (_, pdu) = read_adu(&mut frame.as_slice()).await.unwrap();
+        decode_response(&pdu, request).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_request_roundtrip() {
+        let requests = vec![
+            Request::ReadHoldingRegisters {
+                address: 40,
+                count: 4,
+            },
+            Request::ReadInputRegisters {
+                address: 0,
+                count: 125,
+            },
+            Request::WriteSingleCoil {
+                address: 3,
+                value: true,
+            },
+            Request::WriteMultipleCoils {
+                address: 10,
+                values: vec![true, false, true, true, false, false, false, false, true],
+            },
+            Request::WriteMultipleRegisters {
+                address: 7,
+                values: vec![1, 0xFFFF, 42],
+            },
+        ];
+
+        for request in requests {
+            assert_eq!(roundtrip_request(request.clone()).await, request);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_response_roundtrip() {
+        let request = Request::ReadCoils {
+            address: 0,
+            count: 3,
+        };
+        let response =
+            roundtrip_response(&request, Response::ReadCoils(vec![true, false, true])).await;
+        assert_eq!(response, Response::ReadCoils(vec![true, false, true]));
+
+        let request = Request::ReadHoldingRegisters {
+            address: 0,
+            count: 2,
+        };
+        let response =
+            roundtrip_response(&request, Response::ReadHoldingRegisters(vec![1, 2])).await;
+        assert_eq!(response, Response::ReadHoldingRegisters(vec![1, 2]));
+    }
+
+    #[tokio::test]
+    async fn test_exception_response() {
+        let request = Request::ReadHoldingRegisters {
+            address: 9000,
+            count: 1,
+        };
+        let response = roundtrip_response(
+            &request,
+            Response::Exception {
+                function: 0x03,
+                code: ExceptionCode::IllegalDataAddress,
+            },
+        )
+        .await;
+        assert_eq!(
+            response,
+            Response::Exception {
+                function: 0x03,
+                code: ExceptionCode::IllegalDataAddress
+            }
+        );
+    }
+
+    #[test]
+    fn test_unsupported_function() {
+        assert_eq!(
+            decode_request(&[0x2B, 0x0E, 0x01, 0x00]),
+            Err(Response::Exception {
+                function: 0x2B,
+                code: ExceptionCode::IllegalFunction
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_protocol_id() {
+        let mut frame = encode_request(
+            HEADER,
+            &Request::ReadCoils {
+                address: 0,
+                count: 1,
+            },
+        )
+        .unwrap();
+        frame[3] = 1;
+
+        assert!(matches!(
+            read_adu(&mut frame.as_slice()).await,
+            Err(Error::InvalidFrame(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_counts() {
+        let invalid = vec![
+            Request::WriteMultipleCoils {
+                address: 0,
+                values: vec![],
+            },
+            Request::WriteMultipleCoils {
+                address: 0,
+                values: vec![true; 1969],
+            },
+            Request::WriteMultipleRegisters {
+                address: 0,
+                values: vec![0; 124],
+            },
+            Request::ReadHoldingRegisters {
+                address: 0,
+                count: 0,
+            },
+            Request::ReadCoils {
+                address: 0xFFFF,
+                count: 2,
+            },
+        ];
+        for request in invalid {
+            assert!(matches!(
+                encode_request(HEADER, &request),
+                Err(Error::InvalidRequest(_))
+            ));
+        }
+
+        let request = Request::WriteMultipleCoils {
+            address: 0,
+            values: vec![true; 1968],
+        };
+        assert!(encode_request(HEADER, &request).is_ok());
+        assert!(matches!(
+            encode_response(HEADER, &Response::ReadHoldingRegisters(vec![0; 126])),
+            Err(Error::InvalidFrame(_))
+        ));
+    }
+}
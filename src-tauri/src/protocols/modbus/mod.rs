@@ -0,0 +1,8 @@
+pub mod client;
+pub mod commands;
+pub mod error;
+pub mod frame;
+pub mod poller;
+pub mod server;
+pub mod state;
+pub mod value;
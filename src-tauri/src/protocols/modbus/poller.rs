@@ -0,0 +1,451 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::{interval, Duration, MissedTickBehavior},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::utils::channels::source::Sample;
+
+use super::client::{Client, Table, Write};
+use super::error::{Error, Result};
+use super::frame::MAX_READ_REGISTERS;
+use super::value::Encoding;
+
+pub type WriteCommand = (Write, oneshot::Sender<Result<()>>);
+
+/// Unmapped registers tolerated between two points read by the same request.
+const MAX_GAP: u32 = 8;
+
+fn default_unit() -> u8 {
+    1
+}
+
+fn default_interval_ms() -> u64 {
+    1000
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Point {
+    pub channel: String,
+    pub table: Table,
+    pub address: u16,
+    #[serde(flatten)]
+    pub encoding: Encoding,
+}
+
+impl Point {
+    fn end(&self) -> u32 {
+        self.address as u32 + self.encoding.registers() as u32
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PollerConfig {
+    pub address: String,
+    #[serde(default = "default_unit")]
+    pub unit: u8,
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms: u64,
+    pub points: Vec<Point>,
+}
+
+/// Contiguous range of registers read with a single request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Block {
+    table: Table,
+    address: u16,
+    count: u16,
+    points: Vec<usize>,
+}
+
+/// Groups the points into as few read requests as possible without reading
+/// large unmapped ranges, which devices commonly reject.
+fn plan(points: &[Point]) -> Vec<Block> {
+    let mut order: Vec<usize> = (0..points.len()).collect();
+    order.sort_by_key(|&i| (points[i].table, points[i].address));
+
+    let mut blocks: Vec<Block> = Vec::new();
+    for i in order {
+        let point = &points[i];
+        match blocks.last_mut() {
+            Some(block)
+                if block.table == point.table
+                    && point.address as u32
+                        <= block.address as u32 + block.count as u32 + MAX_GAP
+                    && point.end() - block.address as u32 <= MAX_READ_REGISTERS as u32 =>
+            {
+                let end = (block.address as u32 + block.count as u32).max(point.end());
+                block.count = (end - block.address as u32) as u16;
+                block.points.push(i);
+            }
+            _ => blocks.push(Block {
+                table: point.table,
+                address: point.address,
+                count: point.encoding.registers() as u16,
+                points: vec![i],
+            }),
+        }
+    }
+    blocks
+}
+
+async fn poll(
+    client: &mut Client,
+    config: &PollerConfig,
+    blocks: &[Block],
+) -> Result<Vec<(String, Sample)>> {
+    let mut samples = Vec::with_capacity(config.points.len());
+
+    for block in blocks {
+        let registers = match client
+            .read_registers(block.table, block.address, block.count)
+            .await
+        {
+            Ok(registers) => registers,
+            Err(Error::Exception { function, code }) => {
+                log::warn!(
+                    "Modbus device {} rejected read of {:?} {}..{} (function {:#04x}): {:?}",
+                    config.address,
+                    block.table,
+                    block.address,
+                    block.address as u32 + block.count as u32,
+                    function,
+                    code
+                );
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+
+        let timestamp = Utc::now();
+        for &i in &block.points {
+            let point = &config.points[i];
+            let offset = (point.address - block.address) as usize;
+            if let Some(value) = point.encoding.decode(&registers[offset..]) {
+                let mut sample = Sample::now(value);
+                sample.timestamp = timestamp;
+                samples.push((point.channel.clone(), sample));
+            }
+        }
+    }
+
+    Ok(samples)
+}
+
+async fn connect(config: &PollerConfig) -> Result<Client> {
+    let client = Client::connect(&config.address, config.unit).await;
+    if let Err(e) = &client {
+        log::warn!(
+            "Failed to connect to Modbus device {}: {}",
+            config.address,
+            e
+        );
+    }
+    client
+}
+
+/// Polls the configured points every `interval_ms` and forwards the decoded
+/// samples to `tx`, serving the writes received on `writes` in between.
+///
+/// Connection failures are retried on the next tick.
+pub async fn run(
+    config: &PollerConfig,
+    token: CancellationToken,
+    tx: mpsc::Sender<Vec<(String, Sample)>>,
+    writes: &mut mpsc::Receiver<WriteCommand>,
+) -> Result<()> {
+    let blocks = plan(&config.points);
+    let mut client: Option<Client> = None;
+
+    let mut ticker = interval(Duration::from_millis(config.interval_ms.max(1)));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                if client.is_none() {
+                    client = connect(config).await.ok();
+                }
+                let Some(connected) = client.as_mut() else {
+                    continue;
+                };
+
+                match poll(connected, config, &blocks).await {
+                    Ok(samples) => {
+                        if !samples.is_empty() && tx.send(samples).await.is_err() {
+                            return Ok(());
+                        }
+                    }
+                    Err(e) => {
+                        log::warn!("Polling Modbus device {} failed: {}", config.address, e);
+                        client = None;
+                    }
+                }
+            }
+            Some((write, reply)) = writes.recv() => {
+                if client.is_none() {
+                    client = connect(config).await.ok();
+                }
+                let result = match client.as_mut() {
+                    Some(connected) => connected.write(&write).await,
+                    None => Err(Error::ConnectionClosed),
+                };
+                if matches!(result, Err(Error::Io(_) | Error::Timeout | Error::ConnectionClosed)) {
+                    client = None;
+                }
+                let _ = reply.send(result);
+            }
+            _ = token.cancelled() => {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::modbus::frame::{
+        decode_request, encode_response, read_adu, ExceptionCode, Request, Response,
+    };
+    use crate::protocols::modbus::value::{ByteOrder, DataType};
+    use std::sync::{Arc, Mutex};
+    use tokio::{io::AsyncWriteExt, net::TcpListener, time::timeout};
+
+    #[derive(Default)]
+    struct Memory {
+        coils: Vec<bool>,
+        holding: Vec<u16>,
+        input: Vec<u16>,
+    }
+
+    fn read(table: &[u16], address: u16, count: u16) -> Response {
+        match table.get(address as usize..(address + count) as usize) {
+            Some(values) => Response::ReadHoldingRegisters(values.to_vec()),
+            None => Response::Exception {
+                function: 0,
+                code: ExceptionCode::IllegalDataAddress,
+            },
+        }
+    }
+
+    fn handle(memory: &mut Memory, request: Request) -> Response {
+        match request {
+            Request::ReadHoldingRegisters { address, count } => {
+                match read(&memory.holding, address, count) {
+                    Response::Exception { code, .. } => Response::Exception {
+                        function: 0x03,
+                        code,
+                    },
+                    response => response,
+                }
+            }
+            Request::ReadInputRegisters { address, count } => {
+                match read(&memory.input, address, count) {
+                    Response::ReadHoldingRegisters(values) => Response::ReadInputRegisters(values),
+                    Response::Exception { code, .. } => Response::Exception {
+                        function: 0x04,
+                        code,
+                    },
+                    response => response,
+                }
+            }
+            Request::WriteSingleCoil { address, value } => {
+                memory.coils[address as usize] = value;
+                Response::WriteSingleCoil { address, value }
+            }
+            Request::WriteMultipleRegisters { address, values } => {
+                let start = address as usize;
+                memory.holding[start..start + values.len()].copy_from_slice(&values);
+                Response::WriteMultipleRegisters {
+                    address,
+                    count: values.len() as u16,
+                }
+            }
+            other => Response::Exception {
+                function: other.function(),
+                code: ExceptionCode::IllegalFunction,
+            },
+        }
+    }
+
+    /// In-process stand-in for a Modbus TCP device.
+    async fn spawn_device(memory: Arc<Mutex<Memory>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let memory = memory.clone();
+                tokio::spawn(async move {
+                    while let Ok((header, pdu)) = read_adu(&mut socket).await {
+                        let response = match decode_request(&pdu) {
+                            Ok(request) => handle(&mut memory.lock().unwrap(), request),
+                            Err(exception) => exception,
+                        };
+                        let frame = encode_response(header, &response).unwrap();
+                        if socket.write_all(&frame).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        address
+    }
+
+    fn point(channel: &str, table: Table, address: u16, data_type: DataType) -> Point {
+        Point {
+            channel: channel.to_string(),
+            table,
+            address,
+            encoding: Encoding {
+                data_type,
+                byte_order: ByteOrder::Abcd,
+                scale: 1.0,
+                offset: 0.0,
+            },
+        }
+    }
+
+    fn memory() -> Arc<Mutex<Memory>> {
+        let mut holding = vec![0u16; 20];
+        holding[0..2].copy_from_slice(&[0x42F6, 0xE979]);
+        holding[10] = 650;
+
+        let mut input = vec![0u16; 4];
+        input[2..4].copy_from_slice(&[0xFFFF, 0xFFFE]);
+
+        Arc::new(Mutex::new(Memory {
+            coils: vec![false; 8],
+            holding,
+            input,
+        }))
+    }
+
+    struct Running {
+        token: CancellationToken,
+        samples: mpsc::Receiver<Vec<(String, Sample)>>,
+        writes: mpsc::Sender<WriteCommand>,
+    }
+
+    async fn start(config: PollerConfig) -> Running {
+        let token = CancellationToken::new();
+        let (tx, samples) = mpsc::channel(8);
+        let (writes, mut rx) = mpsc::channel(8);
+
+        tokio::spawn({
+            let token = token.clone();
+            async move { run(&config, token, tx, &mut rx).await }
+        });
+
+        Running {
+            token,
+            samples,
+            writes,
+        }
+    }
+
+    #[test]
+    fn test_plan_merges_contiguous_points() {
+        let points = vec![
+            point("a", Table::Holding, 10, DataType::Int16),
+            point("b", Table::Holding, 0, DataType::Float32),
+            point("c", Table::Input, 2, DataType::Int32),
+            point("d", Table::Holding, 200, DataType::Uint16),
+        ];
+
+        let blocks = plan(&points);
+        assert_eq!(blocks.len(), 3);
+        assert_eq!((blocks[0].address, blocks[0].count), (0, 11));
+        assert_eq!(blocks[0].points, vec![1, 0]);
+        assert_eq!((blocks[1].address, blocks[1].count), (200, 1));
+        assert_eq!(blocks[2].table, Table::Input);
+    }
+
+    #[tokio::test]
+    async fn test_poll_registers() {
+        let address = spawn_device(memory()).await;
+        let mut running = start(PollerConfig {
+            address,
+            unit: 1,
+            interval_ms: 10,
+            points: vec![
+                point("S1.P", Table::Holding, 0, DataType::Float32),
+                point("S1.T", Table::Holding, 10, DataType::Int16),
+                point("S1.E", Table::Input, 2, DataType::Int32),
+                point("S1.missing", Table::Input, 100, DataType::Uint16),
+            ],
+        })
+        .await;
+
+        let samples = timeout(Duration::from_secs(1), running.samples.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        running.token.cancel();
+
+        let ids: Vec<&str> = samples.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(ids, vec!["S1.P", "S1.T", "S1.E"]);
+        assert!((samples[0].1.value - 123.456).abs() < 1e-4);
+        assert_eq!(samples[1].1.value, 650.0);
+        assert_eq!(samples[2].1.value, -2.0);
+    }
+
+    #[tokio::test]
+    async fn test_writes() {
+        let memory = memory();
+        let address = spawn_device(memory.clone()).await;
+        let running = start(PollerConfig {
+            address,
+            unit: 1,
+            interval_ms: 1000,
+            points: vec![],
+        })
+        .await;
+
+        let writes = vec![
+            Write::Coil {
+                address: 3,
+                value: true,
+            },
+            Write::Value {
+                address: 4,
+                value: 123.456,
+                encoding: Encoding {
+                    data_type: DataType::Float32,
+                    byte_order: ByteOrder::Cdab,
+                    scale: 1.0,
+                    offset: 0.0,
+                },
+            },
+        ];
+        for write in writes {
+            let (reply, result) = oneshot::channel();
+            running.writes.send((write, reply)).await.unwrap();
+            result.await.unwrap().unwrap();
+        }
+
+        let (reply, result) = oneshot::channel();
+        let write = Write::Register {
+            address: 0,
+            value: 1,
+        };
+        running.writes.send((write, reply)).await.unwrap();
+        assert!(matches!(
+            result.await.unwrap(),
+            Err(Error::Exception {
+                code: ExceptionCode::IllegalFunction,
+                ..
+            })
+        ));
+        running.token.cancel();
+
+        let memory = memory.lock().unwrap();
+        assert!(memory.coils[3]);
+        assert_eq!(memory.holding[4..6], [0xE979, 0x42F6]);
+    }
+}
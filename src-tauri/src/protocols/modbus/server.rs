@@ -0,0 +1,623 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, RwLock},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    time::{sleep, Duration},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::utils::control::{Action, OperatorCommand};
+
+use super::client::Table;
+use super::error::{Error, Result};
+use super::frame::{
+    decode_request, encode_response, read_adu, ExceptionCode, Request, Response, MAX_READ_COILS,
+    MAX_READ_REGISTERS, MAX_WRITE_COILS,
+};
+use super::value::{ByteOrder, DataType, Encoding};
+
+/// Settings key holding the `ServerConfig`.
+pub const SETTINGS_KEY: &str = "modbus.server";
+
+/// Wait before accepting again after a failed accept.
+const ACCEPT_RETRY: Duration = Duration::from_millis(100);
+
+/// Latest value of every mapped channel, keyed by channel id.
+pub type Values = Arc<RwLock<HashMap<String, f64>>>;
+
+/// Local only, on an unprivileged port.
+fn default_bind() -> String {
+    "127.0.0.1:5020".to_string()
+}
+
+fn default_table() -> Table {
+    Table::Input
+}
+
+fn default_encoding() -> Encoding {
+    Encoding {
+        data_type: DataType::Float32,
+        byte_order: ByteOrder::Abcd,
+        scale: 1.0,
+        offset: 0.0,
+    }
+}
+
+fn default_on() -> Action {
+    Action::Close
+}
+
+fn default_off() -> Action {
+    Action::Open
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegisterEntry {
+    pub channel: String,
+    #[serde(default = "default_table")]
+    pub table: Table,
+    /// Allocated after the previous register of the same table when omitted.
+    #[serde(default)]
+    pub address: Option<u16>,
+    #[serde(default = "default_encoding")]
+    pub encoding: Encoding,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoilEntry {
+    /// Equipment targeted by the operator commands.
+    pub target: String,
+    /// Allocated after the previous coil when omitted.
+    #[serde(default)]
+    pub address: Option<u16>,
+    /// Channel whose value reflects the coil state (non-zero is on).
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default = "default_on")]
+    pub on: Action,
+    #[serde(default = "default_off")]
+    pub off: Action,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerConfig {
+    /// Serving SCADA hosts on the network, which can then operate the
+    /// mapped coils, takes an explicit address such as `0.0.0.0:502`.
+    #[serde(default = "default_bind")]
+    pub bind: String,
+    #[serde(default)]
+    pub registers: Vec<RegisterEntry>,
+    #[serde(default)]
+    pub coils: Vec<CoilEntry>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MappedRegister {
+    pub channel: String,
+    pub table: Table,
+    pub address: u16,
+    pub encoding: Encoding,
+}
+
+impl MappedRegister {
+    fn end(&self) -> u32 {
+        self.address as u32 + self.encoding.registers() as u32
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MappedCoil {
+    pub address: u16,
+    pub target: String,
+    pub status: Option<String>,
+    pub on: Action,
+    pub off: Action,
+}
+
+/// Register map with every address resolved.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RegisterMap {
+    pub registers: Vec<MappedRegister>,
+    pub coils: Vec<MappedCoil>,
+}
+
+impl RegisterMap {
+    /// Resolves the addresses of `config`, allocating the missing ones in
+    /// declaration order, and rejects overlapping entries.
+    pub fn build(config: &ServerConfig) -> Result<Self> {
+        let mut next: HashMap<Table, u32> = HashMap::new();
+        let mut registers: Vec<MappedRegister> = Vec::with_capacity(config.registers.len());
+
+        for entry in &config.registers {
+            let cursor = next.entry(entry.table).or_insert(0);
+            let address = entry.address.map(u32::from).unwrap_or(*cursor);
+            let register = MappedRegister {
+                channel: entry.channel.clone(),
+                table: entry.table,
+                address: u16::try_from(address).map_err(|_| {
+                    Error::InvalidMap(format!("no address left for channel '{}'", entry.channel))
+                })?,
+                encoding: entry.encoding,
+            };
+            if register.end() > u16::MAX as u32 + 1 {
+                return Err(Error::InvalidMap(format!(
+                    "channel '{}' overflows the address space",
+                    entry.channel
+                )));
+            }
+
+            if let Some(other) = registers.iter().find(|r| {
+                r.table == register.table
+                    && u32::from(r.address) < register.end()
+                    && u32::from(register.address) < r.end()
+            }) {
+                return Err(Error::InvalidMap(format!(
+                    "channels '{}' and '{}' overlap at {:?} register {}",
+                    other.channel, register.channel, register.table, register.address
+                )));
+            }
+
+            *cursor = (*cursor).max(register.end());
+            registers.push(register);
+        }
+
+        let mut cursor: u32 = 0;
+        let mut coils: Vec<MappedCoil> = Vec::with_capacity(config.coils.len());
+        for entry in &config.coils {
+            let address = entry.address.map(u32::from).unwrap_or(cursor);
+            let address = u16::try_from(address).map_err(|_| {
+                Error::InvalidMap(format!("no coil left for target '{}'", entry.target))
+            })?;
+            if let Some(other) = coils.iter().find(|c| c.address == address) {
+                return Err(Error::InvalidMap(format!(
+                    "targets '{}' and '{}' share coil {}",
+                    other.target, entry.target, address
+                )));
+            }
+
+            cursor = cursor.max(address as u32 + 1);
+            coils.push(MappedCoil {
+                address,
+                target: entry.target.clone(),
+                status: entry.status.clone(),
+                on: entry.on.clone(),
+                off: entry.off.clone(),
+            });
+        }
+
+        Ok(Self { registers, coils })
+    }
+
+    /// Channels whose values are served by this map.
+    pub fn channels(&self) -> Vec<String> {
+        let mut channels: Vec<String> = self
+            .registers
+            .iter()
+            .map(|r| r.channel.clone())
+            .chain(self.coils.iter().filter_map(|c| c.status.clone()))
+            .collect();
+        channels.sort();
+        channels.dedup();
+        channels
+    }
+}
+
+/// Answers requests against a register map and the current channel values.
+pub struct Handler {
+    tables: HashMap<Table, BTreeMap<u16, MappedRegister>>,
+    coils: BTreeMap<u16, MappedCoil>,
+    values: Values,
+    commands: mpsc::Sender<OperatorCommand>,
+}
+
+impl Handler {
+    pub fn new(map: &RegisterMap, values: Values, commands: mpsc::Sender<OperatorCommand>) -> Self {
+        let mut tables: HashMap<Table, BTreeMap<u16, MappedRegister>> = HashMap::new();
+        for register in &map.registers {
+            tables
+                .entry(register.table)
+                .or_default()
+                .insert(register.address, register.clone());
+        }
+
+        Self {
+            tables,
+            coils: map.coils.iter().map(|c| (c.address, c.clone())).collect(),
+            values,
+            commands,
+        }
+    }
+
+    fn value(&self, channel: &str) -> f64 {
+        self.values
+            .read()
+            .ok()
+            .and_then(|values| values.get(channel).copied())
+            .unwrap_or(0.0)
+    }
+
+    fn registers(
+        &self,
+        table: Table,
+        address: u16,
+        count: u16,
+    ) -> core::result::Result<Vec<u16>, ExceptionCode> {
+        check_span(address, count as usize, MAX_READ_REGISTERS)?;
+        let mapped = self
+            .tables
+            .get(&table)
+            .ok_or(ExceptionCode::IllegalDataAddress)?;
+
+        let end = address as u32 + count as u32;
+        let mut values = Vec::with_capacity(count as usize);
+        let mut current = address as u32;
+        while current < end {
+            let register = mapped
+                .range(..=current as u16)
+                .next_back()
+                .map(|(_, r)| r)
+                .filter(|r| current < r.end())
+                .ok_or(ExceptionCode::IllegalDataAddress)?;
+
+            let encoded = register.encoding.encode(self.value(&register.channel));
+            let skip = (current - register.address as u32) as usize;
+            for word in encoded.into_iter().skip(skip) {
+                if current >= end {
+                    break;
+                }
+                values.push(word);
+                current += 1;
+            }
+        }
+        Ok(values)
+    }
+
+    fn coils(&self, address: u16, count: u16) -> core::result::Result<Vec<bool>, ExceptionCode> {
+        check_span(address, count as usize, MAX_READ_COILS)?;
+        (address as u32..address as u32 + count as u32)
+            .map(|a| {
+                let coil = self
+                    .coils
+                    .get(&(a as u16))
+                    .ok_or(ExceptionCode::IllegalDataAddress)?;
+                Ok(coil
+                    .status
+                    .as_ref()
+                    .is_some_and(|status| self.value(status) != 0.0))
+            })
+            .collect()
+    }
+
+    async fn write_coils(
+        &self,
+        address: u16,
+        values: &[bool],
+        origin: &str,
+    ) -> core::result::Result<(), ExceptionCode> {
+        check_span(address, values.len(), MAX_WRITE_COILS)?;
+        let coils = (0..values.len())
+            .map(|i| {
+                self.coils
+                    .get(&(address + i as u16))
+                    .ok_or(ExceptionCode::IllegalDataAddress)
+            })
+            .collect::<core::result::Result<Vec<_>, _>>()?;
+
+        for (coil, value) in coils.into_iter().zip(values) {
+            let action = if *value {
+                coil.on.clone()
+            } else {
+                coil.off.clone()
+            };
+            let command = OperatorCommand::new(coil.target.clone(), action, origin);
+            self.commands
+                .send(command)
+                .await
+                .map_err(|_| ExceptionCode::ServerDeviceFailure)?;
+        }
+        Ok(())
+    }
+
+    pub async fn handle(&self, request: Request, origin: &str) -> Response {
+        let function = request.function();
+        let result = match request {
+            Request::ReadHoldingRegisters { address, count } => self
+                .registers(Table::Holding, address, count)
+                .map(Response::ReadHoldingRegisters),
+            Request::ReadInputRegisters { address, count } => self
+                .registers(Table::Input, address, count)
+                .map(Response::ReadInputRegisters),
+            Request::ReadCoils { address, count } => {
+                self.coils(address, count).map(Response::ReadCoils)
+            }
+            Request::WriteSingleCoil { address, value } => self
+                .write_coils(address, &[value], origin)
+                .await
+                .map(|_| Response::WriteSingleCoil { address, value }),
+            Request::WriteMultipleCoils { address, values } => self
+                .write_coils(address, &values, origin)
+                .await
+                .map(|_| Response::WriteMultipleCoils {
+                    address,
+                    count: values.len() as u16,
+                }),
+            _ => Err(ExceptionCode::IllegalFunction),
+        };
+
+        result.unwrap_or_else(|code| Response::Exception { function, code })
+    }
+}
+
+/// Checks that `count` items from `address` are within the limit `max` of
+/// the function and within the address space.
+fn check_span(address: u16, count: usize, max: u16) -> core::result::Result<(), ExceptionCode> {
+    if count == 0 || count > max as usize {
+        return Err(ExceptionCode::IllegalDataValue);
+    }
+    if address as usize + count > 0x10000 {
+        return Err(ExceptionCode::IllegalDataAddress);
+    }
+    Ok(())
+}
+
+async fn serve(
+    mut socket: TcpStream,
+    handler: Arc<Handler>,
+    token: CancellationToken,
+) -> Result<()> {
+    let origin = format!("modbus:{}", socket.peer_addr()?);
+
+    loop {
+        let (header, pdu) = tokio::select! {
+            adu = read_adu(&mut socket) => adu?,
+            _ = token.cancelled() => return Ok(()),
+        };
+
+        let response = match decode_request(&pdu) {
+            Ok(request) => handler.handle(request, &origin).await,
+            Err(exception) => exception,
+        };
+        socket
+            .write_all(&encode_response(header, &response)?)
+            .await?;
+    }
+}
+
+/// Accepts SCADA connections on `listener` until `token` is cancelled,
+/// retrying failed accepts.
+pub async fn run(listener: &TcpListener, handler: Handler, token: CancellationToken) -> Result<()> {
+    let handler = Arc::new(handler);
+
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = token.cancelled() => return Ok(()),
+        };
+        let (socket, peer) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                // E.g. out of file descriptors, which may clear once clients leave.
+                log::warn!("Failed to accept Modbus connection: {}", e);
+                sleep(ACCEPT_RETRY).await;
+                continue;
+            }
+        };
+        log::info!("Modbus client {} connected", peer);
+
+        let handler = handler.clone();
+        let token = token.clone();
+        tokio::spawn(async move {
+            match serve(socket, handler, token).await {
+                Ok(()) | Err(Error::ConnectionClosed) => {
+                    log::info!("Modbus client {} disconnected", peer)
+                }
+                Err(e) => log::warn!("Modbus client {} dropped: {}", peer, e),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::modbus::client::{Client, Write};
+    use serde_json::json;
+
+    fn config() -> ServerConfig {
+        serde_json::from_value(json!({
+            "bind": "127.0.0.1:0",
+            "registers": [
+                { "channel": "S1.P" },
+                { "channel": "S1.Q" },
+                { "channel": "S1.V", "table": "holding", "address": 100,
+                  "encoding": { "data_type": "int16", "scale": 0.1 } },
+            ],
+            "coils": [
+                { "target": "S1.CB1", "status": "S1.CB1.status" },
+                { "target": "S1.CB2", "address": 5 },
+            ],
+        }))
+        .unwrap()
+    }
+
+    async fn start() -> (
+        String,
+        Values,
+        mpsc::Receiver<OperatorCommand>,
+        CancellationToken,
+    ) {
+        let map = RegisterMap::build(&config()).unwrap();
+        let values: Values = Arc::new(RwLock::new(HashMap::from([
+            ("S1.P".to_string(), 123.456),
+            ("S1.Q".to_string(), -1.5),
+            ("S1.V".to_string(), 230.0),
+            ("S1.CB1.status".to_string(), 1.0),
+        ])));
+        let (tx, rx) = mpsc::channel(8);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let token = CancellationToken::new();
+        let handler = Handler::new(&map, values.clone(), tx);
+        tokio::spawn({
+            let token = token.clone();
+            async move { run(&listener, handler, token).await }
+        });
+
+        (address, values, rx, token)
+    }
+
+    #[test]
+    fn test_build_allocates_addresses() {
+        let map = RegisterMap::build(&config()).unwrap();
+
+        let addresses: Vec<(Table, u16)> =
+            map.registers.iter().map(|r| (r.table, r.address)).collect();
+        assert_eq!(
+            addresses,
+            vec![(Table::Input, 0), (Table::Input, 2), (Table::Holding, 100)]
+        );
+        assert_eq!(map.coils[0].address, 0);
+        assert_eq!(map.coils[1].address, 5);
+        assert_eq!(
+            map.channels(),
+            vec!["S1.CB1.status", "S1.P", "S1.Q", "S1.V"]
+        );
+    }
+
+    #[test]
+    fn test_build_rejects_overlaps() {
+        let mut config = config();
+        config.registers[1].address = Some(1);
+
+        assert!(matches!(
+            RegisterMap::build(&config),
+            Err(Error::InvalidMap(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_read_registers() {
+        let (address, values, _commands, token) = start().await;
+        let mut client = Client::connect(&address, 1).await.unwrap();
+
+        let registers = client.read_registers(Table::Input, 0, 4).await.unwrap();
+        assert!((default_encoding().decode(&registers).unwrap() - 123.456).abs() < 1e-4);
+        assert_eq!(default_encoding().decode(&registers[2..]), Some(-1.5));
+
+        values.write().unwrap().insert("S1.Q".to_string(), 2.5);
+        let registers = client.read_registers(Table::Input, 2, 2).await.unwrap();
+        assert_eq!(default_encoding().decode(&registers), Some(2.5));
+
+        let registers = client.read_registers(Table::Holding, 100, 1).await.unwrap();
+        assert_eq!(registers, vec![2300]);
+
+        assert!(matches!(
+            client.read_registers(Table::Input, 3, 2).await,
+            Err(Error::Exception {
+                code: ExceptionCode::IllegalDataAddress,
+                ..
+            })
+        ));
+        token.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_coils() {
+        let (address, _values, mut commands, token) = start().await;
+        let mut client = Client::connect(&address, 1).await.unwrap();
+
+        let response = client
+            .call(Request::ReadCoils {
+                address: 0,
+                count: 1,
+            })
+            .await
+            .unwrap();
+        assert_eq!(response, Response::ReadCoils(vec![true]));
+
+        client
+            .write(&Write::Coil {
+                address: 5,
+                value: false,
+            })
+            .await
+            .unwrap();
+        let command = commands.recv().await.unwrap();
+        assert_eq!(command.target, "S1.CB2");
+        assert_eq!(command.action, Action::Open);
+        assert!(command.origin.starts_with("modbus:127.0.0.1:"));
+
+        assert!(matches!(
+            client
+                .write(&Write::Coil {
+                    address: 1,
+                    value: true,
+                })
+                .await,
+            Err(Error::Exception {
+                code: ExceptionCode::IllegalDataAddress,
+                ..
+            })
+        ));
+        token.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_out_of_range_requests() {
+        let map = RegisterMap::build(&config()).unwrap();
+        let (tx, _rx) = mpsc::channel(8);
+        let handler = Handler::new(&map, Values::default(), tx);
+
+        let requests = [
+            (
+                Request::ReadCoils {
+                    address: 0,
+                    count: 2001,
+                },
+                ExceptionCode::IllegalDataValue,
+            ),
+            (
+                Request::ReadCoils {
+                    address: 0xFFFF,
+                    count: 2,
+                },
+                ExceptionCode::IllegalDataAddress,
+            ),
+            (
+                Request::ReadInputRegisters {
+                    address: 0xFFFF,
+                    count: 2,
+                },
+                ExceptionCode::IllegalDataAddress,
+            ),
+            (
+                Request::WriteMultipleCoils {
+                    address: 0xFFFF,
+                    values: vec![true, true],
+                },
+                ExceptionCode::IllegalDataAddress,
+            ),
+            (
+                Request::WriteMultipleCoils {
+                    address: 0,
+                    values: vec![],
+                },
+                ExceptionCode::IllegalDataValue,
+            ),
+        ];
+        for (request, code) in requests {
+            let function = request.function();
+            assert_eq!(
+                handler.handle(request, "test").await,
+                Response::Exception { function, code }
+            );
+        }
+    }
+}
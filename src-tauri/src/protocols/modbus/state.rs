@@ -0,0 +1,32 @@
+use std::collections::HashMap;
+use tokio::sync::{mpsc, Mutex};
+
+use crate::utils::tasks::supervisor::Supervised;
+
+use super::poller::{PollerConfig, WriteCommand};
+use super::server::RegisterMap;
+
+pub struct Poller {
+    pub config: PollerConfig,
+    pub writes: mpsc::Sender<WriteCommand>,
+    pub supervised: Supervised,
+}
+
+#[derive(Default)]
+pub struct PollersInner {
+    pub pollers: HashMap<String, Poller>,
+}
+
+pub type Pollers = Mutex<PollersInner>;
+
+pub struct ServerHandle {
+    pub map: RegisterMap,
+    pub supervised: Supervised,
+}
+
+#[derive(Default)]
+pub struct ServerInner {
+    pub server: Option<ServerHandle>,
+}
+
+pub type Server = Mutex<ServerInner>;
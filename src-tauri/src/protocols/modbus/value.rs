@@ -0,0 +1,194 @@
+//! Conversion between register contents and engineering values.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DataType {
+    Int16,
+    Uint16,
+    Int32,
+    Uint32,
+    Float32,
+}
+
+impl DataType {
+    pub fn registers(self) -> usize {
+        match self {
+            DataType::Int16 | DataType::Uint16 => 1,
+            DataType::Int32 | DataType::Uint32 | DataType::Float32 => 2,
+        }
+    }
+}
+
+/// Order of the bytes on the wire, `A` being the most significant one.
+///
+/// Single register values only honour the byte swap (`Dcba` and `Badc`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ByteOrder {
+    #[default]
+    Abcd,
+    Dcba,
+    Cdab,
+    Badc,
+}
+
+impl ByteOrder {
+    /// Reorders between wire and big-endian order, every ordering is its own
+    /// inverse.
+    fn apply(self, bytes: &mut [u8]) {
+        match self {
+            ByteOrder::Abcd => {}
+            ByteOrder::Dcba => bytes.reverse(),
+            ByteOrder::Cdab => {
+                let half = bytes.len() / 2;
+                if half >= 2 {
+                    bytes.rotate_left(half);
+                }
+            }
+            ByteOrder::Badc => bytes.chunks_exact_mut(2).for_each(|w| w.swap(0, 1)),
+        }
+    }
+}
+
+fn one() -> f64 {
+    1.0
+}
+
+/// How a value is stored in consecutive registers: `value = raw * scale + offset`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Encoding {
+    pub data_type: DataType,
+    #[serde(default)]
+    pub byte_order: ByteOrder,
+    #[serde(default = "one")]
+    pub scale: f64,
+    #[serde(default)]
+    pub offset: f64,
+}
+
+impl Encoding {
+    pub fn registers(&self) -> usize {
+        self.data_type.registers()
+    }
+
+    /// Decodes the first `registers()` registers of `registers`.
+    pub fn decode(&self, registers: &[u16]) -> Option<f64> {
+        let registers = registers.get(..self.registers())?;
+        let mut bytes: Vec<u8> = registers.iter().flat_map(|r| r.to_be_bytes()).collect();
+        self.byte_order.apply(&mut bytes);
+
+        let raw = match self.data_type {
+            DataType::Int16 => i16::from_be_bytes([bytes[0], bytes[1]]) as f64,
+            DataType::Uint16 => u16::from_be_bytes([bytes[0], bytes[1]]) as f64,
+            DataType::Int32 => i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            DataType::Uint32 => u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            DataType::Float32 => {
+                f32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64
+            }
+        };
+        Some(raw * self.scale + self.offset)
+    }
+
+    /// Encodes `value`, saturating integers to the range of the data type.
+    pub fn encode(&self, value: f64) -> Vec<u16> {
+        let raw = if self.scale == 0.0 {
+            0.0
+        } else {
+            (value - self.offset) / self.scale
+        };
+
+        let mut bytes = match self.data_type {
+            DataType::Int16 => (raw.round() as i16).to_be_bytes().to_vec(),
+            DataType::Uint16 => (raw.round() as u16).to_be_bytes().to_vec(),
+            DataType::Int32 => (raw.round() as i32).to_be_bytes().to_vec(),
+            DataType::Uint32 => (raw.round() as u32).to_be_bytes().to_vec(),
+            DataType::Float32 => (raw as f32).to_be_bytes().to_vec(),
+        };
+        self.byte_order.apply(&mut bytes);
+
+        bytes
+            .chunks_exact(2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoding(data_type: DataType, byte_order: ByteOrder) -> Encoding {
+        Encoding {
+            data_type,
+            byte_order,
+            scale: 1.0,
+            offset: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_float32_byte_orders() {
+        // 123.456 is 0x42F6E979 in IEEE 754.
+        let cases = [
+            (ByteOrder::Abcd, [0x42F6, 0xE979]),
+            (ByteOrder::Dcba, [0x79E9, 0xF642]),
+            (ByteOrder::Cdab, [0xE979, 0x42F6]),
+            (ByteOrder::Badc, [0xF642, 0x79E9]),
+        ];
+
+        for (byte_order, registers) in cases {
+            let encoding = encoding(DataType::Float32, byte_order);
+            let value = encoding.decode(&registers).unwrap();
+            assert!((value - 123.456).abs() < 1e-4, "{:?}", byte_order);
+            assert_eq!(encoding.encode(value), registers.to_vec());
+        }
+    }
+
+    #[test]
+    fn test_integers() {
+        assert_eq!(
+            encoding(DataType::Int16, ByteOrder::Abcd).decode(&[0xFFFE]),
+            Some(-2.0)
+        );
+        assert_eq!(
+            encoding(DataType::Uint16, ByteOrder::Abcd).decode(&[0xFFFE]),
+            Some(65534.0)
+        );
+        assert_eq!(
+            encoding(DataType::Uint16, ByteOrder::Badc).decode(&[0x0100]),
+            Some(1.0)
+        );
+        assert_eq!(
+            encoding(DataType::Int32, ByteOrder::Cdab).decode(&[0xFFFF, 0xFFFF]),
+            Some(-1.0)
+        );
+        assert_eq!(
+            encoding(DataType::Uint32, ByteOrder::Cdab).decode(&[0x0001, 0x0002]),
+            Some(131073.0)
+        );
+    }
+
+    #[test]
+    fn test_scaling() {
+        let encoding = Encoding {
+            data_type: DataType::Int16,
+            byte_order: ByteOrder::Abcd,
+            scale: 0.1,
+            offset: -40.0,
+        };
+
+        assert_eq!(encoding.decode(&[650]), Some(25.0));
+        assert_eq!(encoding.encode(25.0), vec![650]);
+        assert_eq!(encoding.encode(1e9), vec![i16::MAX as u16]);
+    }
+
+    #[test]
+    fn test_missing_registers() {
+        assert_eq!(
+            encoding(DataType::Float32, ByteOrder::Abcd).decode(&[0x42F6]),
+            None
+        );
+    }
+}
@@ -1,8 +1,9 @@
 use std::collections::HashMap;
 
-use serde::Serialize;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
-use sqlx::{Pool, Row, Sqlite, SqlitePool};
+use sqlx::{sqlite::SqliteRow, Pool, QueryBuilder, Row, Sqlite, SqlitePool};
 use tauri::{AppHandle, Manager};
 
 use super::error::{Error, Result};
@@ -11,6 +12,80 @@ pub struct DatabaseState {
     pub pool: Pool<Sqlite>,
 }
 
+/// Row of the `schedules` table, the times being on the clock of the schedule.
+pub struct StoredSchedule<T> {
+    pub id: String,
+    pub spec: T,
+    pub created_at: DateTime<Utc>,
+    pub last_fired_at: Option<DateTime<Utc>>,
+    /// Last time the schedule was enabled again after being disabled.
+    pub resumed_at: Option<DateTime<Utc>>,
+}
+
+/// Row of the `tasks` table, the history of a job.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StoredJob {
+    pub id: String,
+    pub name: String,
+    pub params: Value,
+    pub status: String,
+    pub submitted_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Time from start to end of a finished job.
+    pub duration_ms: Option<i64>,
+    pub error: Option<String>,
+    pub cancel_reason: Option<String>,
+    /// Message, location and backtrace of a job that panicked.
+    pub panic: Option<Value>,
+    pub result: Option<Value>,
+    /// Statuses the job went through, with their times.
+    pub transitions: Value,
+}
+
+/// Filter and page of a job history query, newest jobs first.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct JobQuery {
+    pub name: Option<String>,
+    pub status: Option<String>,
+    /// Jobs submitted at or after.
+    pub from: Option<DateTime<Utc>>,
+    /// Jobs submitted at or before.
+    pub to: Option<DateTime<Utc>>,
+    pub offset: u32,
+    /// Page size, `DEFAULT_JOB_PAGE` if not given.
+    pub limit: Option<u32>,
+}
+
+pub const DEFAULT_JOB_PAGE: u32 = 50;
+pub const MAX_JOB_PAGE: u32 = 500;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobPage {
+    pub jobs: Vec<StoredJob>,
+    /// Jobs matching the filter over all pages.
+    pub total: i64,
+}
+
+impl JobQuery {
+    fn push_filter<'a>(&'a self, query: &mut QueryBuilder<'a, Sqlite>) {
+        query.push(" WHERE 1 = 1");
+        if let Some(name) = &self.name {
+            query.push(" AND name = ").push_bind(name);
+        }
+        if let Some(status) = &self.status {
+            query.push(" AND status = ").push_bind(status);
+        }
+        if let Some(from) = self.from {
+            query.push(" AND submitted_at >= ").push_bind(from);
+        }
+        if let Some(to) = self.to {
+            query.push(" AND submitted_at <= ").push_bind(to);
+        }
+    }
+}
+
 impl DatabaseState {
     pub async fn new(app_handle: &AppHandle) -> Result<tokio::sync::Mutex<Self>> {
         let app_dir = app_handle
@@ -42,6 +117,12 @@ impl DatabaseState {
         Ok(tokio::sync::Mutex::new(Self { pool }))
     }
 
+    /// Waits for the pending queries and closes the connections, which
+    /// checkpoints the write-ahead log into the database file.
+    pub async fn close(&self) {
+        self.pool.close().await;
+    }
+
     pub async fn set_setting<T: Serialize>(&self, key: &str, value: &T) -> Result<()> {
         let json_value = serde_json::to_string(value)?;
         sqlx::query("INSERT OR REPLACE INTO settings (key, value) VALUES (?, ?)")
@@ -199,6 +280,228 @@ impl DatabaseState {
         Ok(settings)
     }
 
+    pub async fn insert_schedule<T: Serialize>(
+        &self,
+        id: &str,
+        spec: &T,
+        created_at: DateTime<Utc>,
+    ) -> Result<()> {
+        let json_value = serde_json::to_string(spec)?;
+        sqlx::query("INSERT INTO schedules (id, spec, created_at) VALUES (?, ?, ?)")
+            .bind(id)
+            .bind(json_value)
+            .bind(created_at)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Replaces the spec of an existing schedule.
+    pub async fn save_schedule<T: Serialize>(&self, id: &str, spec: &T) -> Result<()> {
+        let json_value = serde_json::to_string(spec)?;
+        sqlx::query("UPDATE schedules SET spec = ? WHERE id = ?")
+            .bind(json_value)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_schedule_fired(&self, id: &str, fired_at: DateTime<Utc>) -> Result<()> {
+        sqlx::query("UPDATE schedules SET last_fired_at = ? WHERE id = ?")
+            .bind(fired_at)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_schedule_resumed(&self, id: &str, resumed_at: DateTime<Utc>) -> Result<()> {
+        sqlx::query("UPDATE schedules SET resumed_at = ? WHERE id = ?")
+            .bind(resumed_at)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_schedule(&self, id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM schedules WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn list_schedules<T: for<'de> serde::Deserialize<'de>>(
+        &self,
+    ) -> Result<Vec<StoredSchedule<T>>> {
+        let rows = sqlx::query(
+            "SELECT id, spec, created_at, last_fired_at, resumed_at FROM schedules ORDER BY id",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut schedules = Vec::with_capacity(rows.len());
+        for row in rows {
+            let json_str: String = row.get("spec");
+            schedules.push(StoredSchedule {
+                id: row.get("id"),
+                spec: serde_json::from_str(&json_str)?,
+                created_at: row.get("created_at"),
+                last_fired_at: row.get("last_fired_at"),
+                resumed_at: row.get("resumed_at"),
+            });
+        }
+
+        Ok(schedules)
+    }
+
+    pub async fn save_workflow<T: Serialize>(&self, name: &str, spec: &T) -> Result<()> {
+        let json_value = serde_json::to_string(spec)?;
+        sqlx::query("INSERT OR REPLACE INTO workflows (name, spec) VALUES (?, ?)")
+            .bind(name)
+            .bind(json_value)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_workflow<T: for<'de> serde::Deserialize<'de>>(
+        &self,
+        name: &str,
+    ) -> Result<Option<T>> {
+        let row = sqlx::query("SELECT spec FROM workflows WHERE name = ?")
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        match row {
+            Some(row) => {
+                let json_str: String = row.get("spec");
+                Ok(Some(serde_json::from_str(&json_str)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub async fn list_workflows<T: for<'de> serde::Deserialize<'de>>(&self) -> Result<Vec<T>> {
+        let rows = sqlx::query("SELECT spec FROM workflows ORDER BY name")
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut workflows = Vec::with_capacity(rows.len());
+        for row in rows {
+            let json_str: String = row.get("spec");
+            workflows.push(serde_json::from_str(&json_str)?);
+        }
+
+        Ok(workflows)
+    }
+
+    pub async fn delete_workflow(&self, name: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM workflows WHERE name = ?")
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Inserts or replaces the history of job `job.id`.
+    pub async fn save_job(&self, job: &StoredJob) -> Result<()> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO tasks (id, name, params, status, submitted_at, started_at,
+             finished_at, duration_ms, error, cancel_reason, panic, result, transitions)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&job.id)
+        .bind(&job.name)
+        .bind(serde_json::to_string(&job.params)?)
+        .bind(&job.status)
+        .bind(job.submitted_at)
+        .bind(job.started_at)
+        .bind(job.finished_at)
+        .bind(job.duration_ms)
+        .bind(&job.error)
+        .bind(&job.cancel_reason)
+        .bind(job.panic.as_ref().map(serde_json::to_string).transpose()?)
+        .bind(job.result.as_ref().map(serde_json::to_string).transpose()?)
+        .bind(serde_json::to_string(&job.transitions)?)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_job(&self, id: &str) -> Result<Option<StoredJob>> {
+        let row = sqlx::query("SELECT * FROM tasks WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        row.as_ref().map(stored_job).transpose()
+    }
+
+    pub async fn list_jobs(&self, query: &JobQuery) -> Result<JobPage> {
+        let mut count = QueryBuilder::new("SELECT COUNT(*) FROM tasks");
+        query.push_filter(&mut count);
+        let total: i64 = count.build().fetch_one(&self.pool).await?.get(0);
+
+        let limit = query
+            .limit
+            .unwrap_or(DEFAULT_JOB_PAGE)
+            .clamp(1, MAX_JOB_PAGE);
+        let mut select = QueryBuilder::new("SELECT * FROM tasks");
+        query.push_filter(&mut select);
+        select
+            .push(" ORDER BY submitted_at DESC, id DESC LIMIT ")
+            .push_bind(limit)
+            .push(" OFFSET ")
+            .push_bind(query.offset);
+        let rows = select.build().fetch_all(&self.pool).await?;
+
+        let jobs = rows.iter().map(stored_job).collect::<Result<_>>()?;
+        Ok(JobPage { jobs, total })
+    }
+
+    pub async fn delete_job(&self, id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM tasks WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Marks the jobs left queued or running by a previous run of the app as
+    /// failed at `at`, returning how many there were.
+    pub async fn interrupt_jobs(&self, error: &str, at: DateTime<Utc>) -> Result<u64> {
+        let transition = serde_json::json!({"status": "failed", "at": at});
+        let result = sqlx::query(
+            "UPDATE tasks SET status = 'failed', error = ?, finished_at = ?,
+             duration_ms = CAST(ROUND((julianday(?) - julianday(started_at)) * 86400000)
+                 AS INTEGER),
+             transitions = json_insert(transitions, '$[#]', json(?))
+             WHERE status IN ('queued', 'running')",
+        )
+        .bind(error)
+        .bind(at)
+        .bind(at)
+        .bind(serde_json::to_string(&transition)?)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Highest number among the `job-<n>` ids, so that new jobs do not reuse
+    /// the id of a stored one.
+    pub async fn last_job_number(&self) -> Result<u64> {
+        let row = sqlx::query(
+            "SELECT MAX(CAST(SUBSTR(id, 5) AS INTEGER)) FROM tasks WHERE id LIKE 'job-%'",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        let last: Option<i64> = row.get(0);
+        Ok(last.unwrap_or(0).max(0) as u64)
+    }
+
     fn get_nested_value(&self, obj: &Value, path: &str) -> Result<Value> {
         let parts: Vec<&str> = path.split('.').collect();
         let mut current = obj;
@@ -247,6 +550,28 @@ impl DatabaseState {
     }
 }
 
+fn stored_job(row: &SqliteRow) -> Result<StoredJob> {
+    let params: String = row.get("params");
+    let panic: Option<String> = row.get("panic");
+    let result: Option<String> = row.get("result");
+    let transitions: String = row.get("transitions");
+    Ok(StoredJob {
+        id: row.get("id"),
+        name: row.get("name"),
+        params: serde_json::from_str(&params)?,
+        status: row.get("status"),
+        submitted_at: row.get("submitted_at"),
+        started_at: row.get("started_at"),
+        finished_at: row.get("finished_at"),
+        duration_ms: row.get("duration_ms"),
+        error: row.get("error"),
+        cancel_reason: row.get("cancel_reason"),
+        panic: panic.as_deref().map(serde_json::from_str).transpose()?,
+        result: result.as_deref().map(serde_json::from_str).transpose()?,
+        transitions: serde_json::from_str(&transitions)?,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -357,6 +682,177 @@ mod tests {
     // Tests de base (set/get)
     // =============================================================================
 
+    #[tokio::test]
+    async fn test_schedules() {
+        let db_state = setup_test_db().await;
+        let created_at = Utc::now();
+        db_state
+            .insert_schedule("nightly", &json!({"every": 1}), created_at)
+            .await
+            .unwrap();
+        assert!(db_state
+            .insert_schedule("nightly", &json!({"every": 1}), created_at)
+            .await
+            .is_err());
+        db_state
+            .set_schedule_fired("nightly", created_at)
+            .await
+            .unwrap();
+        let resumed_at = Utc::now();
+        db_state
+            .set_schedule_resumed("nightly", resumed_at)
+            .await
+            .unwrap();
+        db_state
+            .save_schedule("nightly", &json!({"every": 2}))
+            .await
+            .unwrap();
+
+        let schedules = db_state.list_schedules::<Value>().await.unwrap();
+        assert_eq!(schedules.len(), 1);
+        assert_eq!(schedules[0].spec, json!({"every": 2}));
+        assert_eq!(schedules[0].created_at, created_at);
+        assert_eq!(schedules[0].last_fired_at, Some(created_at));
+        assert_eq!(schedules[0].resumed_at, Some(resumed_at));
+
+        assert!(db_state.delete_schedule("nightly").await.unwrap());
+        assert!(!db_state.delete_schedule("nightly").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_workflows() {
+        let db_state = setup_test_db().await;
+        let spec = json!({"name": "study", "steps": []});
+        db_state.save_workflow("study", &spec).await.unwrap();
+
+        assert_eq!(
+            db_state.get_workflow::<Value>("study").await.unwrap(),
+            Some(spec.clone())
+        );
+        assert_eq!(
+            db_state.list_workflows::<Value>().await.unwrap(),
+            vec![spec]
+        );
+        assert!(db_state.delete_workflow("study").await.unwrap());
+        assert_eq!(db_state.get_workflow::<Value>("study").await.unwrap(), None);
+    }
+
+    fn job(id: &str, name: &str, status: &str, submitted_at: DateTime<Utc>) -> StoredJob {
+        StoredJob {
+            id: id.to_string(),
+            name: name.to_string(),
+            params: json!({"type": "inspect_csv"}),
+            status: status.to_string(),
+            submitted_at,
+            started_at: None,
+            finished_at: None,
+            duration_ms: None,
+            error: None,
+            cancel_reason: None,
+            panic: None,
+            result: None,
+            transitions: json!([]),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_jobs() {
+        let db_state = setup_test_db().await;
+        let start = Utc::now();
+        for n in 1..=4 {
+            let name = if n % 2 == 0 { "export" } else { "inspect" };
+            let submitted_at = start + chrono::TimeDelta::seconds(n);
+            let job = job(&format!("job-{}", n), name, "queued", submitted_at);
+            db_state.save_job(&job).await.unwrap();
+        }
+        let started_at = start + chrono::TimeDelta::seconds(5);
+        let running = StoredJob {
+            started_at: Some(started_at),
+            transitions: json!([{"status": "queued", "at": start}]),
+            ..job("job-5", "inspect", "running", started_at)
+        };
+        db_state.save_job(&running).await.unwrap();
+        let finished = StoredJob {
+            status: "finished".to_string(),
+            duration_ms: Some(20),
+            panic: Some(json!({"message": "singular matrix", "location": "solver.rs:10:5"})),
+            result: Some(json!({"rows": 3})),
+            ..job("job-12", "inspect", "finished", start)
+        };
+        db_state.save_job(&finished).await.unwrap();
+
+        assert_eq!(
+            db_state.get_job("job-12").await.unwrap(),
+            Some(finished.clone())
+        );
+        assert_eq!(db_state.last_job_number().await.unwrap(), 12);
+
+        let page = db_state
+            .list_jobs(&JobQuery {
+                name: Some("inspect".to_string()),
+                limit: Some(2),
+                ..JobQuery::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(page.total, 4);
+        let ids: Vec<&str> = page.jobs.iter().map(|j| j.id.as_str()).collect();
+        assert_eq!(ids, vec!["job-5", "job-3"]);
+
+        let page = db_state
+            .list_jobs(&JobQuery {
+                from: Some(start + chrono::TimeDelta::seconds(2)),
+                offset: 3,
+                ..JobQuery::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(page.total, 4);
+        assert_eq!(page.jobs.len(), 1);
+        assert_eq!(page.jobs[0].id, "job-2");
+
+        let interrupted_at = start + chrono::TimeDelta::seconds(7);
+        assert_eq!(
+            db_state
+                .interrupt_jobs("interrupted", interrupted_at)
+                .await
+                .unwrap(),
+            5
+        );
+        let page = db_state
+            .list_jobs(&JobQuery {
+                status: Some("failed".to_string()),
+                ..JobQuery::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(page.total, 5);
+        assert_eq!(page.jobs[0].error.as_deref(), Some("interrupted"));
+        let interrupted = db_state.get_job("job-5").await.unwrap().unwrap();
+        assert_eq!(interrupted.finished_at, Some(interrupted_at));
+        assert_eq!(interrupted.duration_ms, Some(2000));
+        assert_eq!(
+            interrupted.transitions[1],
+            json!({"status": "failed", "at": interrupted_at})
+        );
+        let queued = db_state.get_job("job-1").await.unwrap().unwrap();
+        assert_eq!(queued.finished_at, Some(interrupted_at));
+        assert_eq!(queued.duration_ms, None);
+
+        assert!(db_state.delete_job("job-12").await.unwrap());
+        assert_eq!(db_state.get_job("job-12").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_close() {
+        let db_state = setup_test_db().await;
+        db_state.set_setting("key", &1).await.unwrap();
+
+        db_state.close().await;
+        assert!(db_state.pool.is_closed());
+        assert!(db_state.set_setting("key", &2).await.is_err());
+    }
+
     #[tokio::test]
     async fn test_basic_set_get() {
         let db_state = setup_test_db().await;
@@ -5,12 +5,22 @@ use tauri_plugin_shell::{
     process::{CommandChild, CommandEvent},
     ShellExt,
 };
+use tokio::{
+    sync::oneshot,
+    time::{Duration, Instant},
+};
 
 use super::error::{Error, Result};
 
+struct Sidecar {
+    child: CommandChild,
+    /// Resolved once the process has terminated.
+    exited: oneshot::Receiver<()>,
+}
+
 pub struct SidecarsState {
     app_handle: Arc<AppHandle>,
-    sidecars: HashMap<String, CommandChild>,
+    sidecars: HashMap<String, Sidecar>,
 }
 
 impl SidecarsState {
@@ -49,11 +59,14 @@ impl SidecarsState {
         // Insert sidecar
         let sidecar_command = self.app_handle.shell().sidecar(sidecar)?;
         let (mut rx, child) = sidecar_command.spawn()?;
-        self.sidecars.insert(sidecar.to_string(), child);
+        let (exited_tx, exited) = oneshot::channel();
+        self.sidecars
+            .insert(sidecar.to_string(), Sidecar { child, exited });
 
         // Monitor sidecar
         let app_handle = self.app_handle.clone();
         tauri::async_runtime::spawn(async move {
+            let mut exited_tx = Some(exited_tx);
             while let Some(event) = rx.recv().await {
                 match event {
                     CommandEvent::Stdout(line_bytes) => {
@@ -81,6 +94,9 @@ impl SidecarsState {
                         app_handle
                             .emit("sidecar-terminated", format!("{:?}", payload))
                             .expect("Failed to emit sidecar terminated event");
+                        if let Some(exited_tx) = exited_tx.take() {
+                            let _ = exited_tx.send(());
+                        }
                     }
                     _ => {}
                 }
@@ -91,24 +107,66 @@ impl SidecarsState {
     }
 
     pub fn despawn_sidecar(&mut self, sidecar: &str) -> Result<()> {
-        let mut child = self
+        let mut sidecar = self
             .sidecars
             .remove(sidecar)
             .ok_or(Error::SidecarNotFound(sidecar.to_string()))?;
 
-        // Send msg via stdin to sidecar where it self terminates
-        let command = "sidecar shutdown\n";
-        let buf: &[u8] = command.as_bytes();
-        child.write(buf)?;
+        request_exit(&mut sidecar.child)
+    }
 
-        // *Important* `process.kill()` will only shutdown the parent sidecar (python process). Tauri doesnt know about the second process spawned by the "bootloader" script.
-        // This only applies if you compile a "one-file" exe using PyInstaller. Otherwise, just use the line below to kill the process normally.
-        // let _ = process.kill();
+    /// Asks every sidecar to exit and waits up to `timeout` for them to
+    /// terminate, the ones still running then are killed. Returns the names
+    /// of the killed sidecars.
+    pub async fn shutdown(&mut self, timeout: Duration) -> Vec<String> {
+        let deadline = Instant::now() + timeout;
+        let mut sidecars = Vec::new();
+        for (name, mut sidecar) in self.sidecars.drain() {
+            if let Err(e) = request_exit(&mut sidecar.child) {
+                warn!("[tauri] Failed to ask sidecar {} to exit: {}", name, e);
+            }
+            sidecars.push((name, sidecar));
+        }
 
-        Ok(())
+        let mut killed = Vec::new();
+        for (name, sidecar) in sidecars {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if tokio::time::timeout(remaining, sidecar.exited)
+                .await
+                .is_ok()
+            {
+                info!("[tauri] Sidecar {} exited.", name);
+                continue;
+            }
+
+            warn!(
+                "[tauri] Sidecar {} did not exit within {:?}, killing it.",
+                name, timeout
+            );
+            if let Err(e) = sidecar.child.kill() {
+                error!("[tauri] Failed to kill sidecar {}: {}", name, e);
+            }
+            killed.push(name);
+        }
+        killed
     }
 }
 
+/// Sends the shutdown line on the stdin of a sidecar, which then terminates
+/// by itself.
+fn request_exit(child: &mut CommandChild) -> Result<()> {
+    // Send msg via stdin to sidecar where it self terminates
+    let command = "sidecar shutdown\n";
+    let buf: &[u8] = command.as_bytes();
+    child.write(buf)?;
+
+    // *Important* `process.kill()` will only shutdown the parent sidecar (python process). Tauri doesnt know about the second process spawned by the "bootloader" script.
+    // This only applies if you compile a "one-file" exe using PyInstaller. Otherwise, just use the line below to kill the process normally.
+    // let _ = process.kill();
+
+    Ok(())
+}
+
 impl Drop for SidecarsState {
     fn drop(&mut self) {
         let keys: Vec<String> = self.sidecars.keys().cloned().collect();
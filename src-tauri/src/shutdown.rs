@@ -0,0 +1,167 @@
+//! Orderly teardown of the app state before the process exits.
+//!
+//! The phases run in dependency order, each within its own timeout: the
+//! schedules first so that no job is submitted anymore, the protocols since
+//! they feed and read the channels, then the jobs, the executor running
+//! their work and the writer of their history, the channels along with their
+//! recorders, the settings database and finally the sidecars.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tauri::{AppHandle, Manager, RunEvent};
+use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant};
+
+use crate::protocols::{c37118, iec104, modbus};
+use crate::settings::{database::state::DatabaseState, sidecars::state::SidecarsState};
+use crate::utils::channels::state::Channels;
+use crate::utils::tasks::{
+    executor::Executor, history::History, join_all, scheduler::Scheduler, state::Tasks,
+    CancelReason, CancellableTask,
+};
+
+/// Time given to the tasks of a phase before they are aborted.
+const TASK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Time given to the sidecars to exit before they are killed.
+const SIDECAR_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+pub struct Shutdown {
+    started: AtomicBool,
+    done: Mutex<bool>,
+}
+
+impl Shutdown {
+    /// Stops everything once, later calls wait for the first one to end.
+    pub async fn run(&self, app: &AppHandle) {
+        let mut done = self.done.lock().await;
+        if *done {
+            return;
+        }
+
+        log::info!("Shutting down");
+        if let Some(scheduler) = app.try_state::<Scheduler>() {
+            phase("schedules", scheduler.shutdown(TASK_TIMEOUT)).await;
+        }
+        phase("protocols", stop_protocols(app)).await;
+        if let Some(tasks) = app.try_state::<Tasks>() {
+            phase("jobs", tasks.shutdown(TASK_TIMEOUT)).await;
+        }
+        if let Some(executor) = app.try_state::<Executor>() {
+            phase("executor", executor.shutdown(TASK_TIMEOUT)).await;
+        }
+        if let Some(history) = app.try_state::<History>() {
+            phase("job history", history.shutdown(TASK_TIMEOUT)).await;
+        }
+        if let Some(channels) = app.try_state::<Channels>() {
+            phase("channels", channels.shutdown(TASK_TIMEOUT)).await;
+        }
+        if let Some(db) = app.try_state::<Mutex<DatabaseState>>() {
+            phase("database", close_database(&db)).await;
+        }
+        if let Some(sidecars) = app.try_state::<Mutex<SidecarsState>>() {
+            phase("sidecars", async {
+                sidecars.lock().await.shutdown(SIDECAR_TIMEOUT).await
+            })
+            .await;
+        }
+        log::logger().flush();
+
+        *done = true;
+    }
+
+    fn is_done(&self) -> bool {
+        self.done.try_lock().is_ok_and(|done| *done)
+    }
+}
+
+/// Handler of the app events. The first exit request is held back while the
+/// shutdown runs, the exit is then requested again with the same code.
+pub fn on_event(app: &AppHandle, event: RunEvent) {
+    let Some(shutdown) = app.try_state::<Shutdown>() else {
+        return;
+    };
+
+    match event {
+        RunEvent::ExitRequested { code, api, .. } => {
+            if shutdown.is_done() {
+                return;
+            }
+            api.prevent_exit();
+            if !shutdown.started.swap(true, Ordering::SeqCst) {
+                let app = app.clone();
+                tauri::async_runtime::spawn(async move {
+                    app.state::<Shutdown>().run(&app).await;
+                    app.exit(code.unwrap_or(0));
+                });
+            }
+        }
+        // Exits that could not be held back still wait for the shutdown.
+        RunEvent::Exit => tauri::async_runtime::block_on(shutdown.run(app)),
+        _ => {}
+    }
+}
+
+/// Runs one phase, logging how long it took and what had to be aborted.
+async fn phase(name: &str, stop: impl Future<Output = Vec<String>>) {
+    let started = Instant::now();
+    let late = stop.await;
+
+    if late.is_empty() {
+        log::info!("Stopped {} in {:?}", name, started.elapsed());
+    } else {
+        log::warn!(
+            "Stopped {} in {:?}, aborted: {}",
+            name,
+            started.elapsed(),
+            late.join(", ")
+        );
+    }
+}
+
+async fn stop_protocols(app: &AppHandle) -> Vec<String> {
+    let mut tasks: Vec<(String, CancellableTask<()>)> = Vec::new();
+
+    if let Some(streams) = app.try_state::<c37118::state::PmuStreams>() {
+        let mut streams = streams.lock().await;
+        tasks.extend(
+            streams
+                .streams
+                .drain()
+                .map(|(id, stream)| (format!("pmu:{}", id), stream.supervised.task)),
+        );
+    }
+    if let Some(pollers) = app.try_state::<modbus::state::Pollers>() {
+        let mut pollers = pollers.lock().await;
+        tasks.extend(
+            pollers
+                .pollers
+                .drain()
+                .map(|(id, poller)| (format!("modbus:{}", id), poller.supervised.task)),
+        );
+    }
+    if let Some(server) = app.try_state::<modbus::state::Server>() {
+        if let Some(server) = server.lock().await.server.take() {
+            tasks.push(("modbus-server".to_string(), server.supervised.task));
+        }
+    }
+    if let Some(server) = app.try_state::<iec104::state::Server>() {
+        if let Some(server) = server.lock().await.server.take() {
+            tasks.push(("iec104-server".to_string(), server.supervised.task));
+        }
+    }
+
+    for (_, task) in &tasks {
+        task.cancel_with(CancelReason::Shutdown);
+    }
+    join_all(tasks, TASK_TIMEOUT).await
+}
+
+async fn close_database(db: &Mutex<DatabaseState>) -> Vec<String> {
+    match tokio::time::timeout(TASK_TIMEOUT, async { db.lock().await.close().await }).await {
+        Ok(()) => Vec::new(),
+        Err(_) => vec!["settings database".to_string()],
+    }
+}
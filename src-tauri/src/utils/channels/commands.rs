@@ -1,106 +1,101 @@
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
-};
-
-use chrono::{DateTime, Utc};
-use rand::Rng;
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
 use tauri::{ipc::Channel, State};
-use tokio::time::{sleep, Duration};
+use tokio::sync::Mutex;
 
-use crate::utils::tasks::CancellableTask;
+use crate::settings::database::state::DatabaseState;
+use crate::utils::tasks::supervisor::SupervisorStatus;
 
 use super::error::{Error, Result};
-use super::state::Channels;
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Event {
-    id: String,
-    value: f64,
-    timestamp: DateTime<Utc>,
-}
+use super::fault::{self, Fault};
+use super::link::Impairment;
+use super::outbox::{Backpressure, DeliveryStats};
+use super::selector::{Pattern, Selector};
+use super::source::SEED_SETTINGS_KEY;
+use super::state::{ChannelSpec, Channels, Snapshot};
+use super::worker::Event;
 
 #[derive(Debug, Serialize)]
 pub struct ChannelStatus {
     id: String,
     exists: bool,
     paused: Option<bool>,
+    tags: Vec<String>,
+    unit: Option<String>,
+    faults: Vec<Fault>,
+    impairment: Option<Impairment>,
+    delivery: Option<DeliveryStats>,
+    supervisor: Option<SupervisorStatus>,
 }
 
-fn generate_random_float() -> f64 {
-    let mut rng = rand::thread_rng();
+impl From<Snapshot> for ChannelStatus {
+    fn from(snapshot: Snapshot) -> Self {
+        Self {
+            id: snapshot.id,
+            exists: true,
+            paused: Some(snapshot.paused),
+            tags: snapshot.tags,
+            unit: snapshot.unit,
+            faults: snapshot.faults,
+            impairment: snapshot.impairment,
+            delivery: Some(snapshot.delivery),
+            supervisor: Some(snapshot.supervisor),
+        }
+    }
+}
 
-    let int_value = rng.gen_range(-100000..=100000);
-    int_value as f64 / 10000.0
+#[derive(Debug, Serialize)]
+pub struct SubscriptionStatus {
+    id: String,
+    pattern: String,
+    delivery: DeliveryStats,
+}
+
+/// Outcome of a bulk operation on one channel.
+#[derive(Debug, Serialize)]
+pub struct BulkResult {
+    id: String,
+    ok: bool,
+    error: Option<String>,
+}
+
+impl BulkResult {
+    fn new(id: String, result: Result<()>) -> Self {
+        match result {
+            Ok(()) => Self {
+                id,
+                ok: true,
+                error: None,
+            },
+            Err(e) => Self {
+                id,
+                ok: false,
+                error: Some(e.to_string()),
+            },
+        }
+    }
 }
 
+/// Registers a channel, unseeded random sources derive their seed from the
+/// global seed when it is set.
 #[tauri::command]
 pub async fn register(
     state: State<'_, Channels>,
     id: String,
     channel: Channel<Event>,
+    spec: Option<ChannelSpec>,
 ) -> Result<()> {
-    let mut channels = state.lock().await;
+    let mut spec = spec.unwrap_or_default();
+    spec.source = spec.source.with_global_seed(state.seed(), &id);
 
-    if channels.channels.contains_key(&id) {
-        return Err(Error::ChannelAlreadyExists { id });
-    }
-
-    let paused = Arc::new(AtomicBool::new(false));
-    let paused_clone = paused.clone();
-    let id_clone = id.clone();
-    let channel_clone = channel.clone();
-
-    let task = CancellableTask::new(move |token| {
-        let id = id_clone;
-        let channel = channel_clone;
-        let paused = paused_clone.clone();
-
-        async move {
-            loop {
-                tokio::select! {
-                    _ = sleep(Duration::from_millis(10)) => {
-                        if paused.load(Ordering::Relaxed) {
-                            continue;
-                        }
-
-                        let value = generate_random_float();
-                        let event = Event {
-                            id: id.clone(),
-                            value,
-                            timestamp: Utc::now(),
-                        };
-
-                        if let Err(e) = channel.send(event) {
-                            log::warn!("Failed to send event to channel '{}': {:?}", id, e);
-                            break;
-                        }
-
-                        log::info!("Sent to {} - value: {}", id, value);
-                    }
-                    _ = token.cancelled() => {
-                        log::info!("Task for channel '{}' was cancelled", id);
-                        break;
-                    }
-                }
-            }
-        }
-    });
-
-    channels.channels.insert(id, (paused, task));
+    state.register(id, channel, spec)?;
     log::info!("Successfully registered channel");
 
     Ok(())
 }
 
-#[tauri::command]
-pub async fn unregister(state: State<'_, Channels>, id: String) -> Result<()> {
-    let mut channels = state.lock().await;
-
-    match channels.channels.remove(&id) {
-        Some((_, task)) => {
-            task.cancel();
+fn unregister_one(state: &Channels, id: String) -> Result<()> {
+    match state.remove(&id) {
+        Some(_) => {
             log::info!("Successfully unregistered channel '{}'", id);
             Ok(())
         }
@@ -111,35 +106,28 @@ pub async fn unregister(state: State<'_, Channels>, id: String) -> Result<()> {
     }
 }
 
-#[tauri::command]
-pub async fn start(state: State<'_, Channels>, id: String) -> Result<()> {
-    let channels = state.lock().await;
-
-    match channels.channels.get(&id) {
-        Some((paused, _)) => {
-            let was_paused = paused.swap(false, Ordering::Relaxed);
-            if was_paused {
-                log::info!("Started channel '{}'", id);
-                Ok(())
-            } else {
-                log::warn!("Channel '{}' was already running", id);
-                Err(Error::InvalidStateTransition { id })
-            }
+async fn start_one(state: &Channels, id: String) -> Result<()> {
+    let Some(control) = state.control(&id) else {
+        log::warn!("Attempted to start non-existent channel '{}'", id);
+        return Err(Error::ChannelNotFound { id });
+    };
+
+    match control.start().await {
+        Some(true) => {
+            log::info!("Started channel '{}'", id);
+            Ok(())
         }
-        None => {
-            log::warn!("Attempted to start non-existent channel '{}'", id);
-            Err(Error::ChannelNotFound { id })
+        Some(false) => {
+            log::warn!("Channel '{}' was already running", id);
+            Err(Error::InvalidStateTransition { id })
         }
+        None => Err(Error::ChannelStopped { id }),
     }
 }
 
-#[tauri::command]
-pub async fn stop(state: State<'_, Channels>, id: String) -> Result<()> {
-    let mut channels = state.lock().await;
-
-    match channels.channels.remove(&id) {
-        Some((_, task)) => {
-            task.cancel();
+fn stop_one(state: &Channels, id: String) -> Result<()> {
+    match state.remove(&id) {
+        Some(_) => {
             log::info!("Successfully stopped channel '{}'", id);
             Ok(())
         }
@@ -150,57 +138,251 @@ pub async fn stop(state: State<'_, Channels>, id: String) -> Result<()> {
     }
 }
 
-#[tauri::command]
-pub async fn pause(state: State<'_, Channels>, id: String) -> Result<()> {
-    let channels = state.lock().await;
-
-    match channels.channels.get(&id) {
-        Some((paused, _)) => {
-            let was_running = !paused.swap(true, Ordering::Relaxed);
-            if was_running {
-                log::info!("Paused channel '{}'", id);
-                Ok(())
-            } else {
-                log::warn!("Channel '{}' was already paused", id);
-                Err(Error::InvalidStateTransition { id })
-            }
+async fn pause_one(state: &Channels, id: String) -> Result<()> {
+    let Some(control) = state.control(&id) else {
+        log::warn!("Attempted to pause non-existent channel '{}'", id);
+        return Err(Error::ChannelNotFound { id });
+    };
+
+    match control.pause().await {
+        Some(true) => {
+            log::info!("Paused channel '{}'", id);
+            Ok(())
         }
+        Some(false) => {
+            log::warn!("Channel '{}' was already paused", id);
+            Err(Error::InvalidStateTransition { id })
+        }
+        None => Err(Error::ChannelStopped { id }),
+    }
+}
+
+async fn set_impairment_one(
+    state: &Channels,
+    id: String,
+    impairment: Option<Impairment>,
+) -> Result<()> {
+    if let Some(Err(reason)) = impairment.as_ref().map(Impairment::validate) {
+        return Err(Error::InvalidImpairment { id, reason });
+    }
+    let Some(control) = state.control(&id) else {
+        log::warn!("Attempted to impair link of non-existent channel '{}'", id);
+        return Err(Error::ChannelNotFound { id });
+    };
+
+    match control.set_impairment(impairment).await {
+        Some(_) => Ok(()),
+        None => Err(Error::ChannelStopped { id }),
+    }
+}
+
+/// Stores the global seed in the `channels.seed` setting, `None` removing it.
+/// Channels registered afterwards use it.
+#[tauri::command]
+pub async fn set_seed(
+    state: State<'_, Channels>,
+    db: State<'_, Mutex<DatabaseState>>,
+    seed: Option<u64>,
+) -> Result<()> {
+    let db = db.lock().await;
+    match seed {
+        Some(seed) => db.set_setting(SEED_SETTINGS_KEY, &seed).await?,
         None => {
-            log::warn!("Attempted to pause non-existent channel '{}'", id);
-            Err(Error::ChannelNotFound { id })
+            db.delete_setting(SEED_SETTINGS_KEY).await?;
         }
     }
+    state.set_seed(seed);
+    Ok(())
 }
 
 #[tauri::command]
-pub async fn get_status(state: State<'_, Channels>, id: String) -> Result<ChannelStatus> {
-    let channels = state.lock().await;
+pub async fn get_seed(state: State<'_, Channels>) -> Result<Option<u64>> {
+    Ok(state.seed())
+}
 
-    match channels.channels.get(&id) {
-        Some((paused, _)) => Ok(ChannelStatus {
-            id,
-            exists: true,
-            paused: Some(paused.load(Ordering::Relaxed)),
-        }),
+#[tauri::command]
+pub async fn unregister(state: State<'_, Channels>, id: String) -> Result<()> {
+    unregister_one(&state, id)
+}
+
+#[tauri::command]
+pub async fn start(state: State<'_, Channels>, id: String) -> Result<()> {
+    start_one(&state, id).await
+}
+
+#[tauri::command]
+pub async fn stop(state: State<'_, Channels>, id: String) -> Result<()> {
+    stop_one(&state, id)
+}
+
+#[tauri::command]
+pub async fn pause(state: State<'_, Channels>, id: String) -> Result<()> {
+    pause_one(&state, id).await
+}
+
+/// Replaces the faults injected into a running channel, an empty list
+/// restores a healthy sensor.
+#[tauri::command]
+pub async fn set_faults(state: State<'_, Channels>, id: String, faults: Vec<Fault>) -> Result<()> {
+    if let Err(reason) = fault::validate(&faults) {
+        return Err(Error::InvalidFault { id, reason });
+    }
+    let Some(control) = state.control(&id) else {
+        log::warn!("Attempted to set faults of non-existent channel '{}'", id);
+        return Err(Error::ChannelNotFound { id });
+    };
+
+    match control.set_faults(faults).await {
+        Some(_) => Ok(()),
+        None => Err(Error::ChannelStopped { id }),
+    }
+}
+
+/// Replaces the impairment of the link between a channel and its
+/// subscribers, `None` restores a perfect link.
+#[tauri::command]
+pub async fn set_impairment(
+    state: State<'_, Channels>,
+    id: String,
+    impairment: Option<Impairment>,
+) -> Result<()> {
+    set_impairment_one(&state, id, impairment).await
+}
+
+#[tauri::command]
+pub async fn get_status(state: State<'_, Channels>, id: String) -> Result<ChannelStatus> {
+    match state.snapshot(&id) {
+        Some(snapshot) => Ok(snapshot.into()),
         None => Ok(ChannelStatus {
             id,
             exists: false,
             paused: None,
+            tags: Vec::new(),
+            unit: None,
+            faults: Vec::new(),
+            impairment: None,
+            delivery: None,
+            supervisor: None,
         }),
     }
 }
 
 #[tauri::command]
 pub async fn list_channels(state: State<'_, Channels>) -> Result<Vec<ChannelStatus>> {
-    let channels = state.lock().await;
+    let statuses: Vec<ChannelStatus> = state.snapshots().into_iter().map(Into::into).collect();
 
-    let statuses: Vec<ChannelStatus> = channels
-        .channels
-        .iter()
-        .map(|(id, (paused, _))| ChannelStatus {
-            id: id.clone(),
-            exists: true,
-            paused: Some(paused.load(Ordering::Relaxed)),
+    Ok(statuses)
+}
+
+#[tauri::command]
+pub async fn start_matching(
+    state: State<'_, Channels>,
+    selector: Selector,
+) -> Result<Vec<BulkResult>> {
+    let ids = state.select(&selector.compile()?);
+
+    let mut results = Vec::with_capacity(ids.len());
+    for id in ids {
+        let result = start_one(&state, id.clone()).await;
+        results.push(BulkResult::new(id, result));
+    }
+    Ok(results)
+}
+
+#[tauri::command]
+pub async fn pause_matching(
+    state: State<'_, Channels>,
+    selector: Selector,
+) -> Result<Vec<BulkResult>> {
+    let ids = state.select(&selector.compile()?);
+
+    let mut results = Vec::with_capacity(ids.len());
+    for id in ids {
+        let result = pause_one(&state, id.clone()).await;
+        results.push(BulkResult::new(id, result));
+    }
+    Ok(results)
+}
+
+#[tauri::command]
+pub async fn set_impairment_matching(
+    state: State<'_, Channels>,
+    selector: Selector,
+    impairment: Option<Impairment>,
+) -> Result<Vec<BulkResult>> {
+    let ids = state.select(&selector.compile()?);
+
+    let mut results = Vec::with_capacity(ids.len());
+    for id in ids {
+        let result = set_impairment_one(&state, id.clone(), impairment.clone()).await;
+        results.push(BulkResult::new(id, result));
+    }
+    Ok(results)
+}
+
+#[tauri::command]
+pub async fn stop_matching(
+    state: State<'_, Channels>,
+    selector: Selector,
+) -> Result<Vec<BulkResult>> {
+    let ids = state.select(&selector.compile()?);
+
+    Ok(ids
+        .into_iter()
+        .map(|id| BulkResult::new(id.clone(), stop_one(&state, id)))
+        .collect())
+}
+
+#[tauri::command]
+pub async fn unregister_matching(
+    state: State<'_, Channels>,
+    selector: Selector,
+) -> Result<Vec<BulkResult>> {
+    let ids = state.select(&selector.compile()?);
+
+    Ok(ids
+        .into_iter()
+        .map(|id| BulkResult::new(id.clone(), unregister_one(&state, id)))
+        .collect())
+}
+
+/// Forwards the events of every current and future channel whose id matches
+/// `pattern` to `channel`, returns the subscription id.
+#[tauri::command]
+pub async fn subscribe(
+    state: State<'_, Channels>,
+    pattern: String,
+    channel: Channel<Event>,
+    backpressure: Option<Backpressure>,
+) -> Result<String> {
+    let pattern = Pattern::new(&pattern)?;
+    let id = state.subscribe(pattern.clone(), channel, backpressure.unwrap_or_default());
+    log::info!(
+        "Subscribed '{}' to channels matching '{}'",
+        id,
+        pattern.as_str()
+    );
+
+    Ok(id)
+}
+
+#[tauri::command]
+pub async fn unsubscribe(state: State<'_, Channels>, id: String) -> Result<()> {
+    state.unsubscribe(&id)?;
+    log::info!("Successfully removed subscription '{}'", id);
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn list_subscriptions(state: State<'_, Channels>) -> Result<Vec<SubscriptionStatus>> {
+    let statuses = state
+        .subscriptions()
+        .into_iter()
+        .map(|(id, pattern, delivery)| SubscriptionStatus {
+            id,
+            pattern,
+            delivery,
         })
         .collect();
 
@@ -1,6 +1,8 @@
 use serde::Serialize;
 use thiserror::Error;
 
+use crate::settings::database::error::Error as DatabaseError;
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("{0}")]
@@ -18,6 +20,27 @@ pub enum Error {
     #[error("Channel with id '{id}' is already in the requested state")]
     InvalidStateTransition { id: String },
 
+    #[error("Channel with id '{id}' is no longer running")]
+    ChannelStopped { id: String },
+
+    #[error("Invalid filter chain for channel '{id}': {reason}")]
+    InvalidFilter { id: String, reason: String },
+
+    #[error("Subscription with id '{id}' not found")]
+    SubscriptionNotFound { id: String },
+
+    #[error("Invalid faults for channel '{id}': {reason}")]
+    InvalidFault { id: String, reason: String },
+
+    #[error("Invalid link impairment for channel '{id}': {reason}")]
+    InvalidImpairment { id: String, reason: String },
+
+    #[error("Invalid pattern '{pattern}': {reason}")]
+    InvalidPattern { pattern: String, reason: String },
+
+    #[error("Settings error: {0}")]
+    Settings(#[from] DatabaseError),
+
     #[error("Lock acquisition failed: {0}")]
     LockError(String),
 
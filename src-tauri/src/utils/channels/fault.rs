@@ -0,0 +1,255 @@
+//! Measurement errors and sensor faults injected into the samples of a
+//! channel, before its filters.
+
+use chrono::{DateTime, Utc};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use super::source::Sample;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Fault {
+    /// Gaussian noise with standard deviation `sigma`.
+    Noise {
+        sigma: f64,
+    },
+    Bias {
+        offset: f64,
+    },
+    /// Error growing by `rate` units per second from the moment the fault is
+    /// applied.
+    Drift {
+        rate: f64,
+    },
+    Quantization {
+        step: f64,
+    },
+    /// Sensor frozen at `value`, or at the first value seen when omitted.
+    StuckAt {
+        #[serde(default)]
+        value: Option<f64>,
+    },
+    /// Samples lost with the given probability.
+    Dropout {
+        probability: f64,
+    },
+    /// Random spikes of `magnitude`, in either direction.
+    Spike {
+        probability: f64,
+        magnitude: f64,
+    },
+}
+
+impl Fault {
+    fn validate(&self) -> Result<(), String> {
+        let valid = match self {
+            Fault::Noise { sigma } => *sigma >= 0.0,
+            Fault::Bias { offset } => offset.is_finite(),
+            Fault::Drift { rate } => rate.is_finite(),
+            Fault::Quantization { step } => *step > 0.0,
+            Fault::StuckAt { value } => value.is_none_or(f64::is_finite),
+            Fault::Dropout { probability } => (0.0..=1.0).contains(probability),
+            Fault::Spike {
+                probability,
+                magnitude,
+            } => (0.0..=1.0).contains(probability) && magnitude.is_finite(),
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            Err(format!("invalid parameters in {:?}", self))
+        }
+    }
+}
+
+pub fn validate(faults: &[Fault]) -> Result<(), String> {
+    faults.iter().try_for_each(Fault::validate)
+}
+
+/// Applies a set of faults, replaceable at runtime.
+pub struct FaultInjector {
+    faults: Vec<Fault>,
+    rng: ChaCha8Rng,
+    /// Timestamp of the first sample since the faults were set.
+    since: Option<DateTime<Utc>>,
+    stuck: Option<f64>,
+}
+
+impl FaultInjector {
+    /// Random draws are reproducible when `seed` is set.
+    pub fn new(faults: Vec<Fault>, seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+            None => ChaCha8Rng::from_rng(&mut rand::rng()),
+        };
+
+        Self {
+            faults,
+            rng,
+            since: None,
+            stuck: None,
+        }
+    }
+
+    pub fn set(&mut self, faults: Vec<Fault>) {
+        self.faults = faults;
+        self.since = None;
+        self.stuck = None;
+    }
+
+    pub fn faults(&self) -> &[Fault] {
+        &self.faults
+    }
+
+    /// Standard normal draw, Box-Muller.
+    fn gaussian(&mut self) -> f64 {
+        let u1: f64 = 1.0 - self.rng.random::<f64>();
+        let u2: f64 = self.rng.random();
+        (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos()
+    }
+
+    /// Applies the faults in order, `None` if the sample is dropped.
+    pub fn apply(&mut self, mut sample: Sample) -> Option<Sample> {
+        if self.faults.is_empty() {
+            return Some(sample);
+        }
+        let since = *self.since.get_or_insert(sample.timestamp);
+
+        for i in 0..self.faults.len() {
+            match self.faults[i] {
+                Fault::Noise { sigma } => sample.value += sigma * self.gaussian(),
+                Fault::Bias { offset } => sample.value += offset,
+                Fault::Drift { rate } => {
+                    let elapsed = (sample.timestamp - since).num_milliseconds() as f64 / 1000.0;
+                    sample.value += rate * elapsed.max(0.0);
+                }
+                Fault::Quantization { step } => {
+                    sample.value = (sample.value / step).round() * step;
+                }
+                Fault::StuckAt { value } => {
+                    sample.value = *self.stuck.get_or_insert(value.unwrap_or(sample.value));
+                }
+                Fault::Dropout { probability } => {
+                    if self.rng.random_bool(probability) {
+                        return None;
+                    }
+                }
+                Fault::Spike {
+                    probability,
+                    magnitude,
+                } => {
+                    if self.rng.random_bool(probability) {
+                        let sign = if self.rng.random_bool(0.5) { 1.0 } else { -1.0 };
+                        sample.value += sign * magnitude;
+                    }
+                }
+            }
+        }
+
+        Some(sample)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn samples(values: &[f64]) -> Vec<Sample> {
+        let start = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| Sample {
+                value: *value,
+                quality: Default::default(),
+                timestamp: start + Duration::milliseconds(500 * i as i64),
+            })
+            .collect()
+    }
+
+    fn run(faults: Vec<Fault>, values: &[f64]) -> Vec<f64> {
+        let mut injector = FaultInjector::new(faults, Some(1));
+        samples(values)
+            .into_iter()
+            .filter_map(|s| injector.apply(s))
+            .map(|s| s.value)
+            .collect()
+    }
+
+    #[test]
+    fn test_deterministic_faults() {
+        let faults = vec![
+            Fault::Bias { offset: 1.0 },
+            Fault::Drift { rate: 2.0 },
+            Fault::Quantization { step: 0.5 },
+        ];
+        assert_eq!(run(faults, &[0.1, 0.1, 0.1]), vec![1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn test_stuck_at() {
+        assert_eq!(
+            run(vec![Fault::StuckAt { value: None }], &[4.0, 5.0, 6.0]),
+            vec![4.0, 4.0, 4.0]
+        );
+        assert_eq!(
+            run(vec![Fault::StuckAt { value: Some(0.0) }], &[4.0, 5.0]),
+            vec![0.0, 0.0]
+        );
+    }
+
+    #[test]
+    fn test_noise_statistics() {
+        let values = run(vec![Fault::Noise { sigma: 2.0 }], &[10.0; 5000]);
+
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64;
+        assert!((mean - 10.0).abs() < 0.1, "mean {}", mean);
+        assert!(
+            (variance.sqrt() - 2.0).abs() < 0.1,
+            "sigma {}",
+            variance.sqrt()
+        );
+    }
+
+    #[test]
+    fn test_dropout_and_spikes() {
+        assert!(run(vec![Fault::Dropout { probability: 1.0 }], &[1.0, 2.0]).is_empty());
+        assert_eq!(
+            run(vec![Fault::Dropout { probability: 0.0 }], &[1.0, 2.0]),
+            vec![1.0, 2.0]
+        );
+
+        let spiked = run(
+            vec![Fault::Spike {
+                probability: 1.0,
+                magnitude: 50.0,
+            }],
+            &[0.0; 20],
+        );
+        assert!(spiked.iter().all(|v| v.abs() == 50.0));
+    }
+
+    #[test]
+    fn test_set_resets_state() {
+        let mut injector = FaultInjector::new(vec![Fault::StuckAt { value: None }], None);
+        let samples = samples(&[1.0, 2.0, 3.0]);
+
+        assert_eq!(injector.apply(samples[0].clone()).unwrap().value, 1.0);
+        injector.set(vec![Fault::StuckAt { value: None }]);
+        assert_eq!(injector.apply(samples[1].clone()).unwrap().value, 2.0);
+        injector.set(Vec::new());
+        assert_eq!(injector.apply(samples[2].clone()).unwrap().value, 3.0);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&[Fault::Dropout { probability: 1.5 }]).is_err());
+        assert!(validate(&[Fault::Quantization { step: 0.0 }]).is_err());
+        assert!(validate(&[Fault::Noise { sigma: 0.5 }]).is_ok());
+    }
+}
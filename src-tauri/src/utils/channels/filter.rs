@@ -0,0 +1,277 @@
+//! Signal-processing filters applied to the samples of a channel before they
+//! are emitted.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use super::source::{Quality, Sample};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Filter {
+    /// Drops samples within `width` of the last emitted value.
+    Deadband { width: f64 },
+    /// First-order low-pass with time constant `tau_ms`.
+    LowPass { tau_ms: f64 },
+    /// Mean of the last `window` samples.
+    MovingAverage { window: usize },
+    /// Median of the last `window` samples. With a `threshold`, only samples
+    /// further than it from the median are replaced.
+    Median {
+        window: usize,
+        #[serde(default)]
+        threshold: Option<f64>,
+    },
+    /// Limits the slope to `max_rate` units per second.
+    RateLimit { max_rate: f64 },
+    Clamp {
+        #[serde(default)]
+        min: Option<f64>,
+        #[serde(default)]
+        max: Option<f64>,
+    },
+}
+
+impl Filter {
+    fn validate(&self) -> Result<(), String> {
+        let valid = match self {
+            Filter::Deadband { width } => *width >= 0.0,
+            Filter::LowPass { tau_ms } => *tau_ms > 0.0,
+            Filter::MovingAverage { window } | Filter::Median { window, .. } => *window > 0,
+            Filter::RateLimit { max_rate } => *max_rate > 0.0,
+            Filter::Clamp {
+                min: Some(min),
+                max: Some(max),
+            } => min <= max,
+            Filter::Clamp { .. } => true,
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            Err(format!("invalid parameters in {:?}", self))
+        }
+    }
+}
+
+/// A filter with its running state.
+struct Stage {
+    filter: Filter,
+    window: VecDeque<f64>,
+    last: Option<Sample>,
+}
+
+impl Stage {
+    fn apply(&mut self, mut sample: Sample) -> Option<Sample> {
+        match self.filter {
+            Filter::Deadband { width } => {
+                if let Some(last) = &self.last {
+                    if last.quality == sample.quality && (sample.value - last.value).abs() < width {
+                        return None;
+                    }
+                }
+            }
+            Filter::LowPass { tau_ms } => {
+                if let Some(last) = &self.last {
+                    let dt = (sample.timestamp - last.timestamp)
+                        .num_microseconds()
+                        .unwrap_or(0) as f64
+                        / 1000.0;
+                    let alpha = dt.max(0.0) / (tau_ms + dt.max(0.0));
+                    sample.value = last.value + alpha * (sample.value - last.value);
+                }
+            }
+            Filter::MovingAverage { window } => {
+                self.push(sample.value, window);
+                sample.value = self.window.iter().sum::<f64>() / self.window.len() as f64;
+            }
+            Filter::Median { window, threshold } => {
+                self.push(sample.value, window);
+                let mut sorted: Vec<f64> = self.window.iter().copied().collect();
+                sorted.sort_by(f64::total_cmp);
+                let mid = sorted.len() / 2;
+                let median = if sorted.len().is_multiple_of(2) {
+                    (sorted[mid - 1] + sorted[mid]) / 2.0
+                } else {
+                    sorted[mid]
+                };
+                if threshold.is_none_or(|t| (sample.value - median).abs() > t) {
+                    sample.value = median;
+                }
+            }
+            Filter::RateLimit { max_rate } => {
+                if let Some(last) = &self.last {
+                    let dt = (sample.timestamp - last.timestamp)
+                        .num_microseconds()
+                        .unwrap_or(0) as f64
+                        / 1e6;
+                    let step = max_rate * dt.max(0.0);
+                    sample.value = sample.value.clamp(last.value - step, last.value + step);
+                }
+            }
+            Filter::Clamp { min, max } => {
+                sample.value = sample
+                    .value
+                    .max(min.unwrap_or(f64::NEG_INFINITY))
+                    .min(max.unwrap_or(f64::INFINITY));
+            }
+        }
+
+        self.last = Some(sample.clone());
+        Some(sample)
+    }
+
+    fn push(&mut self, value: f64, window: usize) {
+        if self.window.len() == window {
+            self.window.pop_front();
+        }
+        self.window.push_back(value);
+    }
+}
+
+/// Filters run in order, a stage dropping a sample stops the chain.
+///
+/// Invalid samples bypass the chain so they neither get smoothed nor disturb
+/// the state of the filters.
+#[derive(Default)]
+pub struct FilterChain {
+    stages: Vec<Stage>,
+}
+
+impl FilterChain {
+    pub fn new(filters: &[Filter]) -> Result<Self, String> {
+        let stages = filters
+            .iter()
+            .map(|filter| {
+                filter.validate()?;
+                Ok(Stage {
+                    filter: filter.clone(),
+                    window: VecDeque::new(),
+                    last: None,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(Self { stages })
+    }
+
+    /// Runs `sample` through the chain, `None` if a stage dropped it.
+    pub fn apply(&mut self, sample: Sample) -> Option<Sample> {
+        if sample.quality == Quality::Invalid {
+            return Some(sample);
+        }
+
+        self.stages
+            .iter_mut()
+            .try_fold(sample, |sample, stage| stage.apply(sample))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Duration, Utc};
+
+    fn samples(values: &[f64], step_ms: i64) -> Vec<Sample> {
+        let start = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| Sample {
+                value: *value,
+                quality: Quality::Good,
+                timestamp: start + Duration::milliseconds(step_ms * i as i64),
+            })
+            .collect()
+    }
+
+    fn run(filters: &[Filter], values: &[f64], step_ms: i64) -> Vec<f64> {
+        let mut chain = FilterChain::new(filters).unwrap();
+        samples(values, step_ms)
+            .into_iter()
+            .filter_map(|s| chain.apply(s))
+            .map(|s| s.value)
+            .collect()
+    }
+
+    #[test]
+    fn test_deadband() {
+        let filters = [Filter::Deadband { width: 1.0 }];
+        assert_eq!(
+            run(&filters, &[0.0, 0.5, 0.9, 1.2, 1.5, -0.1], 10),
+            vec![0.0, 1.2, -0.1]
+        );
+    }
+
+    #[test]
+    fn test_low_pass() {
+        // dt equal to tau gives alpha = 0.5.
+        let filters = [Filter::LowPass { tau_ms: 100.0 }];
+        assert_eq!(
+            run(&filters, &[0.0, 8.0, 8.0, 8.0], 100),
+            vec![0.0, 4.0, 6.0, 7.0]
+        );
+    }
+
+    #[test]
+    fn test_moving_average() {
+        let filters = [Filter::MovingAverage { window: 3 }];
+        assert_eq!(
+            run(&filters, &[3.0, 6.0, 9.0, 12.0], 10),
+            vec![3.0, 4.5, 6.0, 9.0]
+        );
+    }
+
+    #[test]
+    fn test_median_spike() {
+        let filters = [Filter::Median {
+            window: 3,
+            threshold: Some(5.0),
+        }];
+        assert_eq!(
+            run(&filters, &[10.0, 11.0, 100.0, 12.0, 13.0], 10),
+            vec![10.0, 11.0, 11.0, 12.0, 13.0]
+        );
+    }
+
+    #[test]
+    fn test_rate_limit_and_clamp() {
+        let filters = [
+            Filter::RateLimit { max_rate: 10.0 },
+            Filter::Clamp {
+                min: None,
+                max: Some(1.5),
+            },
+        ];
+        // 100 ms steps allow 1.0 per sample.
+        assert_eq!(
+            run(&filters, &[0.0, 5.0, 5.0, -5.0], 100),
+            vec![0.0, 1.0, 1.5, 1.0]
+        );
+    }
+
+    #[test]
+    fn test_invalid_samples_bypass() {
+        let mut chain = FilterChain::new(&[Filter::Deadband { width: 10.0 }]).unwrap();
+        let mut samples = samples(&[0.0, 1.0, 2.0], 10);
+        samples[1].quality = Quality::Invalid;
+
+        let values: Vec<f64> = samples
+            .into_iter()
+            .filter_map(|s| chain.apply(s))
+            .map(|s| s.value)
+            .collect();
+        assert_eq!(values, vec![0.0, 1.0]);
+    }
+
+    #[test]
+    fn test_rejects_invalid_parameters() {
+        assert!(FilterChain::new(&[Filter::MovingAverage { window: 0 }]).is_err());
+        assert!(FilterChain::new(&[Filter::Clamp {
+            min: Some(1.0),
+            max: Some(0.0),
+        }])
+        .is_err());
+    }
+}
@@ -0,0 +1,100 @@
+//! Ring buffer of the samples delivered by a channel, read by the exports.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+
+use super::source::Quality;
+
+/// Samples kept per channel unless its spec says otherwise.
+pub const HISTORY_CAPACITY: usize = 100_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub sequence: u64,
+    pub value: f64,
+    pub quality: Quality,
+    pub timestamp: DateTime<Utc>,
+}
+
+pub struct History {
+    records: Mutex<VecDeque<Record>>,
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: Mutex::new(VecDeque::new()),
+            capacity,
+        }
+    }
+
+    pub fn push(&self, record: Record) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut records = self.records.lock().unwrap();
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    /// Records timestamped within `from..=to`, in delivery order.
+    pub fn range(&self, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Vec<Record> {
+        self.records
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|r| from.is_none_or(|from| r.timestamp >= from))
+            .filter(|r| to.is_none_or(|to| r.timestamp <= to))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn record(sequence: u64, timestamp: DateTime<Utc>) -> Record {
+        Record {
+            sequence,
+            value: sequence as f64,
+            quality: Quality::Good,
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn test_ring_buffer() {
+        let history = History::new(3);
+        let start = Utc::now();
+        for i in 0..5 {
+            history.push(record(i, start + Duration::seconds(i as i64)));
+        }
+
+        let all = history.range(None, None);
+        assert_eq!(
+            all.iter().map(|r| r.sequence).collect::<Vec<_>>(),
+            vec![2, 3, 4]
+        );
+
+        let window = history.range(
+            Some(start + Duration::seconds(3)),
+            Some(start + Duration::seconds(3)),
+        );
+        assert_eq!(window, vec![record(3, start + Duration::seconds(3))]);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let history = History::new(0);
+        history.push(record(1, Utc::now()));
+        assert!(history.range(None, None).is_empty());
+    }
+}
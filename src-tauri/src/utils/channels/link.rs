@@ -0,0 +1,260 @@
+//! Impaired communication link between a channel and its subscribers.
+
+use std::collections::BTreeMap;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use tokio::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Outage {
+    /// Chance for each sample to start an outage.
+    pub probability: f64,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Reorder {
+    /// Chance for each sample to be held back.
+    pub probability: f64,
+    pub delay_ms: u64,
+}
+
+/// Degradation of the telemetry path, applied after the filters.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Impairment {
+    #[serde(default)]
+    pub latency_ms: u64,
+    /// Uniform extra latency in `0..=jitter_ms`, which also reorders samples
+    /// closer than the jitter.
+    #[serde(default)]
+    pub jitter_ms: u64,
+    /// Chance for each sample to be lost.
+    #[serde(default)]
+    pub loss: f64,
+    #[serde(default)]
+    pub outage: Option<Outage>,
+    #[serde(default)]
+    pub reorder: Option<Reorder>,
+}
+
+impl Impairment {
+    pub fn validate(&self) -> Result<(), String> {
+        let probability = |p: f64| (0.0..=1.0).contains(&p);
+        let valid = probability(self.loss)
+            && self.outage.is_none_or(|o| probability(o.probability))
+            && self.reorder.is_none_or(|r| probability(r.probability));
+
+        if valid {
+            Ok(())
+        } else {
+            Err(format!("invalid probabilities in {:?}", self))
+        }
+    }
+}
+
+/// Delay line applying an `Impairment` to the items sent through it.
+pub struct Link<T> {
+    impairment: Option<Impairment>,
+    rng: ChaCha8Rng,
+    outage_until: Option<Instant>,
+    in_flight: BTreeMap<(Instant, u64), T>,
+    sequence: u64,
+}
+
+impl<T> Link<T> {
+    /// Random draws are reproducible when `seed` is set.
+    pub fn new(impairment: Option<Impairment>, seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+            None => ChaCha8Rng::from_rng(&mut rand::rng()),
+        };
+
+        Self {
+            impairment,
+            rng,
+            outage_until: None,
+            in_flight: BTreeMap::new(),
+            sequence: 0,
+        }
+    }
+
+    /// Replaces the impairment, items in flight keep their arrival time.
+    pub fn set(&mut self, impairment: Option<Impairment>) {
+        self.impairment = impairment;
+        self.outage_until = None;
+    }
+
+    pub fn impairment(&self) -> Option<&Impairment> {
+        self.impairment.as_ref()
+    }
+
+    /// Sends `item` at `now`, returns `false` if it is lost.
+    pub fn send(&mut self, item: T, now: Instant) -> bool {
+        let mut delay = Duration::ZERO;
+
+        if let Some(impairment) = &self.impairment {
+            if self.outage_until.is_some_and(|until| now < until) {
+                return false;
+            }
+            if let Some(outage) = impairment.outage {
+                if self.rng.random_bool(outage.probability) {
+                    self.outage_until = Some(now + Duration::from_millis(outage.duration_ms));
+                    return false;
+                }
+            }
+            if self.rng.random_bool(impairment.loss) {
+                return false;
+            }
+
+            delay = Duration::from_millis(
+                impairment.latency_ms + self.rng.random_range(0..=impairment.jitter_ms),
+            );
+            if let Some(reorder) = impairment.reorder {
+                if self.rng.random_bool(reorder.probability) {
+                    delay += Duration::from_millis(reorder.delay_ms);
+                }
+            }
+        }
+
+        self.sequence += 1;
+        self.in_flight.insert((now + delay, self.sequence), item);
+        true
+    }
+
+    /// Arrival time of the next item in flight.
+    pub fn next_arrival(&self) -> Option<Instant> {
+        self.in_flight.keys().next().map(|(at, _)| *at)
+    }
+
+    /// Next item arrived by `now`.
+    pub fn receive(&mut self, now: Instant) -> Option<T> {
+        match self.in_flight.first_entry() {
+            Some(entry) if entry.key().0 <= now => Some(entry.remove()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arrivals(link: &mut Link<u32>, start: Instant, until_ms: u64) -> Vec<(u64, u32)> {
+        let mut arrivals = Vec::new();
+        for ms in 0..=until_ms {
+            let now = start + Duration::from_millis(ms);
+            while let Some(item) = link.receive(now) {
+                arrivals.push((ms, item));
+            }
+        }
+        arrivals
+    }
+
+    #[test]
+    fn test_unimpaired_link_is_immediate() {
+        let mut link = Link::new(None, Some(1));
+        let now = Instant::now();
+
+        assert!(link.send(1, now));
+        assert!(link.send(2, now));
+        assert_eq!(link.receive(now), Some(1));
+        assert_eq!(link.receive(now), Some(2));
+        assert_eq!(link.receive(now), None);
+    }
+
+    #[test]
+    fn test_latency() {
+        let impairment = Impairment {
+            latency_ms: 50,
+            ..Default::default()
+        };
+        let mut link = Link::new(Some(impairment), Some(1));
+        let start = Instant::now();
+
+        link.send(1, start);
+        link.send(2, start + Duration::from_millis(10));
+        assert_eq!(link.next_arrival(), Some(start + Duration::from_millis(50)));
+        assert_eq!(arrivals(&mut link, start, 100), vec![(50, 1), (60, 2)]);
+    }
+
+    #[test]
+    fn test_reorder() {
+        let impairment = Impairment {
+            reorder: Some(Reorder {
+                probability: 1.0,
+                delay_ms: 30,
+            }),
+            ..Default::default()
+        };
+        let mut link = Link::new(Some(impairment), Some(1));
+        let start = Instant::now();
+
+        link.send(1, start);
+        link.set(None);
+        link.send(2, start + Duration::from_millis(10));
+        assert_eq!(arrivals(&mut link, start, 50), vec![(10, 2), (30, 1)]);
+    }
+
+    #[test]
+    fn test_loss_and_outage() {
+        let lossy = Impairment {
+            loss: 1.0,
+            ..Default::default()
+        };
+        let mut link = Link::new(Some(lossy), Some(1));
+        assert!(!link.send(1, Instant::now()));
+
+        let outage = Impairment {
+            outage: Some(Outage {
+                probability: 1.0,
+                duration_ms: 100,
+            }),
+            ..Default::default()
+        };
+        let mut link = Link::new(Some(outage), Some(1));
+        let start = Instant::now();
+        assert!(!link.send(1, start));
+
+        // Further samples are lost for the duration of the outage, whatever
+        // the probability.
+        link.impairment = Some(Impairment {
+            outage: Some(Outage {
+                probability: 0.0,
+                duration_ms: 100,
+            }),
+            ..Default::default()
+        });
+        assert!(!link.send(2, start + Duration::from_millis(50)));
+        assert!(link.send(3, start + Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn test_jitter_is_bounded() {
+        let impairment = Impairment {
+            latency_ms: 10,
+            jitter_ms: 20,
+            ..Default::default()
+        };
+        let mut link = Link::new(Some(impairment), Some(7));
+        let start = Instant::now();
+        for i in 0..100 {
+            link.send(i, start);
+        }
+
+        let arrivals = arrivals(&mut link, start, 40);
+        assert_eq!(arrivals.len(), 100);
+        assert!(arrivals.iter().all(|(ms, _)| (10..=30).contains(ms)));
+    }
+
+    #[test]
+    fn test_validate() {
+        let invalid = Impairment {
+            loss: 2.0,
+            ..Default::default()
+        };
+        assert!(invalid.validate().is_err());
+        assert!(Impairment::default().validate().is_ok());
+    }
+}
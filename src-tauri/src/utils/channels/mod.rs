@@ -1,3 +1,12 @@
 pub mod commands;
 pub mod error;
+pub mod fault;
+pub mod filter;
+pub mod history;
+pub mod link;
+pub mod outbox;
+pub mod selector;
+pub mod source;
 pub mod state;
+pub mod subscription;
+pub mod worker;
@@ -0,0 +1,252 @@
+//! Bounded queue between a channel and one subscriber, so a slow webview
+//! degrades according to a drop policy instead of stalling the channel.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+/// What to do with a new event when the queue is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DropPolicy {
+    #[default]
+    DropOldest,
+    DropNewest,
+    /// Keeps only the latest event, whatever the capacity.
+    Coalesce,
+    /// Waits for room, slowing the source down.
+    Block,
+}
+
+fn default_capacity() -> usize {
+    256
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Backpressure {
+    #[serde(default)]
+    pub policy: DropPolicy,
+    #[serde(default = "default_capacity")]
+    pub capacity: usize,
+}
+
+impl Default for Backpressure {
+    fn default() -> Self {
+        Self {
+            policy: DropPolicy::default(),
+            capacity: default_capacity(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct DeliveryStats {
+    pub delivered: u64,
+    pub dropped: u64,
+    pub failed: u64,
+    pub queued: usize,
+}
+
+struct Queue<T> {
+    items: VecDeque<T>,
+    closed: bool,
+}
+
+/// Single producer, single consumer queue applying a `Backpressure` policy.
+pub struct Outbox<T> {
+    config: Backpressure,
+    queue: Mutex<Queue<T>>,
+    readable: Notify,
+    writable: Notify,
+    delivered: AtomicU64,
+    dropped: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl<T> Outbox<T> {
+    pub fn new(config: Backpressure) -> Self {
+        Self {
+            config,
+            queue: Mutex::new(Queue {
+                items: VecDeque::new(),
+                closed: false,
+            }),
+            readable: Notify::new(),
+            writable: Notify::new(),
+            delivered: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        match self.config.policy {
+            DropPolicy::Coalesce => 1,
+            _ => self.config.capacity.max(1),
+        }
+    }
+
+    /// Queues `item`, waiting for room only with `DropPolicy::Block`.
+    pub async fn push(&self, mut item: T) {
+        loop {
+            let writable = self.writable.notified();
+            match self.try_push(item) {
+                Some(rejected) => item = rejected,
+                None => return,
+            }
+            writable.await;
+        }
+    }
+
+    /// Queues `item` following the policy, gives it back when the queue is
+    /// full and the policy is `DropPolicy::Block`.
+    fn try_push(&self, item: T) -> Option<T> {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.closed {
+            return None;
+        }
+        if queue.items.len() >= self.capacity() {
+            match self.config.policy {
+                DropPolicy::DropOldest | DropPolicy::Coalesce => {
+                    queue.items.pop_front();
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                DropPolicy::DropNewest => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return None;
+                }
+                DropPolicy::Block => return Some(item),
+            }
+        }
+        queue.items.push_back(item);
+        drop(queue);
+
+        self.readable.notify_one();
+        None
+    }
+
+    /// Next item, `None` once the outbox is closed and drained.
+    pub async fn pop(&self) -> Option<T> {
+        loop {
+            let readable = self.readable.notified();
+            {
+                let mut queue = self.queue.lock().unwrap();
+                if let Some(item) = queue.items.pop_front() {
+                    drop(queue);
+                    self.writable.notify_one();
+                    return Some(item);
+                }
+                if queue.closed {
+                    return None;
+                }
+            }
+            readable.await;
+        }
+    }
+
+    /// Stops accepting items, the queued ones can still be popped.
+    pub fn close(&self) {
+        self.queue.lock().unwrap().closed = true;
+        self.readable.notify_one();
+        self.writable.notify_one();
+    }
+
+    pub fn record(&self, delivered: bool) {
+        let counter = if delivered {
+            &self.delivered
+        } else {
+            &self.failed
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> DeliveryStats {
+        DeliveryStats {
+            delivered: self.delivered.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            queued: self.queue.lock().unwrap().items.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tokio::time::{sleep, timeout, Duration};
+
+    fn outbox(policy: DropPolicy, capacity: usize) -> Outbox<u32> {
+        Outbox::new(Backpressure { policy, capacity })
+    }
+
+    async fn drain(outbox: &Outbox<u32>) -> Vec<u32> {
+        outbox.close();
+        let mut items = Vec::new();
+        while let Some(item) = outbox.pop().await {
+            items.push(item);
+        }
+        items
+    }
+
+    #[tokio::test]
+    async fn test_drop_policies() {
+        let cases = [
+            (DropPolicy::DropOldest, vec![3, 4, 5]),
+            (DropPolicy::DropNewest, vec![1, 2, 3]),
+            (DropPolicy::Coalesce, vec![5]),
+        ];
+
+        for (policy, expected) in cases {
+            let outbox = outbox(policy, 3);
+            for i in 1..=5 {
+                outbox.push(i).await;
+            }
+            let dropped = outbox.stats().dropped;
+            assert_eq!(dropped as usize, 5 - expected.len(), "{:?}", policy);
+            assert_eq!(drain(&outbox).await, expected, "{:?}", policy);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_block_waits_for_room() {
+        let outbox = Arc::new(outbox(DropPolicy::Block, 2));
+        outbox.push(1).await;
+        outbox.push(2).await;
+
+        let blocked = timeout(Duration::from_millis(20), outbox.push(3)).await;
+        assert!(blocked.is_err());
+
+        let producer = {
+            let outbox = outbox.clone();
+            tokio::spawn(async move { outbox.push(3).await })
+        };
+        sleep(Duration::from_millis(10)).await;
+        assert_eq!(outbox.pop().await, Some(1));
+        producer.await.unwrap();
+
+        assert_eq!(outbox.stats().dropped, 0);
+        assert_eq!(drain(&outbox).await, vec![2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_pop_waits_for_push() {
+        let outbox = Arc::new(outbox(DropPolicy::DropOldest, 4));
+
+        let consumer = {
+            let outbox = outbox.clone();
+            tokio::spawn(async move { outbox.pop().await })
+        };
+        sleep(Duration::from_millis(10)).await;
+        outbox.push(7).await;
+
+        assert_eq!(consumer.await.unwrap(), Some(7));
+    }
+}
@@ -0,0 +1,97 @@
+use globset::{Glob, GlobMatcher};
+use serde::{Deserialize, Serialize};
+
+use super::error::{Error, Result};
+
+/// Compiled id glob such as `S1.*` or `S?.CB[12]`.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    source: String,
+    matcher: GlobMatcher,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Result<Self> {
+        let glob = Glob::new(pattern).map_err(|e| Error::InvalidPattern {
+            pattern: pattern.to_string(),
+            reason: e.kind().to_string(),
+        })?;
+
+        Ok(Self {
+            source: pattern.to_string(),
+            matcher: glob.compile_matcher(),
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    pub fn is_match(&self, id: &str) -> bool {
+        self.matcher.is_match(id)
+    }
+}
+
+/// Set of channels targeted by a bulk operation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Selector {
+    Tag(String),
+    Glob(String),
+}
+
+/// Compiled `Selector`.
+pub enum Matcher {
+    Tag(String),
+    Glob(Pattern),
+}
+
+impl Selector {
+    pub fn compile(&self) -> Result<Matcher> {
+        match self {
+            Selector::Tag(tag) => Ok(Matcher::Tag(tag.clone())),
+            Selector::Glob(pattern) => Pattern::new(pattern).map(Matcher::Glob),
+        }
+    }
+}
+
+impl Matcher {
+    pub fn is_match(&self, id: &str, tags: &[String]) -> bool {
+        match self {
+            Matcher::Tag(tag) => tags.contains(tag),
+            Matcher::Glob(pattern) => pattern.is_match(id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob() {
+        let matcher = Selector::Glob("S1.*".to_string()).compile().unwrap();
+
+        assert!(matcher.is_match("S1.P", &[]));
+        assert!(matcher.is_match("S1.CB1.status", &[]));
+        assert!(!matcher.is_match("S10.P", &[]));
+        assert!(!matcher.is_match("S2.P", &[]));
+    }
+
+    #[test]
+    fn test_tag() {
+        let matcher = Selector::Tag("substation-1".to_string()).compile().unwrap();
+        let tags = vec!["feeder".to_string(), "substation-1".to_string()];
+
+        assert!(matcher.is_match("anything", &tags));
+        assert!(!matcher.is_match("S1.P", &[]));
+    }
+
+    #[test]
+    fn test_invalid_glob() {
+        assert!(matches!(
+            Selector::Glob("S1.[".to_string()).compile(),
+            Err(Error::InvalidPattern { .. })
+        ));
+    }
+}
@@ -0,0 +1,311 @@
+use std::fmt;
+use std::sync::Arc;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::mpsc,
+    time::{sleep_until, Duration, Instant},
+};
+
+use crate::utils::clock::SimClock;
+
+/// Capacity of the feed used by external sources to push samples into a channel.
+pub const FEED_CAPACITY: usize = 1024;
+
+/// Interval between two samples of a random source.
+const RANDOM_PERIOD: Duration = Duration::from_millis(10);
+
+/// Settings key of the global seed from which unseeded random channels derive
+/// their own, written through the `set_seed` command.
+pub const SEED_SETTINGS_KEY: &str = "channels.seed";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Quality {
+    #[default]
+    Good,
+    Suspect,
+    Invalid,
+}
+
+impl Quality {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Quality::Good => "good",
+            Quality::Suspect => "suspect",
+            Quality::Invalid => "invalid",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub value: f64,
+    pub quality: Quality,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl Sample {
+    pub fn now(value: f64) -> Self {
+        Self {
+            value,
+            quality: Quality::Good,
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+/// Samples of an imported file, sorted by timestamp.
+#[derive(Clone)]
+pub struct Recording(Arc<[Sample]>);
+
+impl Recording {
+    pub fn new(mut samples: Vec<Sample>) -> Self {
+        samples.sort_by_key(|s| s.timestamp);
+        Self(samples.into())
+    }
+
+    pub fn samples(&self) -> &[Sample] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Recording {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Recording({} samples)", self.0.len())
+    }
+}
+
+/// Where the samples of a channel come from.
+///
+/// `External` channels are fed by protocol clients (PMU, Modbus, ...) through
+/// `Channels::publish`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Source {
+    /// Uniform values, reproducible when `seed` is set.
+    Random {
+        #[serde(default)]
+        seed: Option<u64>,
+    },
+    External,
+    /// Imported samples replayed on the simulation clock, from its time at
+    /// the start of the channel.
+    #[serde(skip)]
+    Replay(Recording),
+}
+
+impl Default for Source {
+    fn default() -> Self {
+        Source::Random { seed: None }
+    }
+}
+
+impl Source {
+    /// Seeds an unseeded random source of channel `id` from `global`.
+    pub fn with_global_seed(self, global: Option<u64>, id: &str) -> Self {
+        match (self, global) {
+            (Source::Random { seed: None }, Some(global)) => Source::Random {
+                seed: Some(derive_seed(global, id)),
+            },
+            (source, _) => source,
+        }
+    }
+}
+
+/// Stable per-channel seed, FNV-1a of `id` mixed with `global` through
+/// SplitMix64 so that it does not depend on the Rust version.
+pub fn derive_seed(global: u64, id: &str) -> u64 {
+    let hash = id.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+
+    let mut z = (global ^ hash).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+pub enum Producer {
+    Random {
+        rng: Box<ChaCha8Rng>,
+        /// Deadline of the next sample, kept when `next` is dropped so that
+        /// the rate does not depend on how often it is polled.
+        next: Option<Instant>,
+    },
+    External(mpsc::Receiver<Sample>),
+    Replay {
+        recording: Recording,
+        next: usize,
+        clock: SimClock,
+        /// From recorded to simulated time, set by the first sample.
+        offset: Option<TimeDelta>,
+    },
+}
+
+impl Producer {
+    pub fn new(source: &Source, clock: &SimClock) -> (Self, Option<mpsc::Sender<Sample>>) {
+        match source {
+            Source::Random { seed } => {
+                let rng = match seed {
+                    Some(seed) => ChaCha8Rng::seed_from_u64(*seed),
+                    None => ChaCha8Rng::from_rng(&mut rand::rng()),
+                };
+                let producer = Producer::Random {
+                    rng: Box::new(rng),
+                    next: None,
+                };
+                (producer, None)
+            }
+            Source::External => {
+                let (tx, rx) = mpsc::channel(FEED_CAPACITY);
+                (Producer::External(rx), Some(tx))
+            }
+            Source::Replay(recording) => (
+                Producer::Replay {
+                    recording: recording.clone(),
+                    next: 0,
+                    clock: clock.clone(),
+                    offset: None,
+                },
+                None,
+            ),
+        }
+    }
+
+    /// Waits for the next sample, `None` once the source is exhausted. It can
+    /// be dropped and called again without losing a sample.
+    pub async fn next(&mut self) -> Option<Sample> {
+        match self {
+            Producer::Random { rng, next } => {
+                let at = *next.get_or_insert_with(|| Instant::now() + RANDOM_PERIOD);
+                sleep_until(at).await;
+                // Late samples are not caught up on.
+                *next = Some((at + RANDOM_PERIOD).max(Instant::now()));
+                Some(Sample::now(generate_random_float(rng)))
+            }
+            Producer::External(rx) => rx.recv().await,
+            Producer::Replay {
+                recording,
+                next,
+                clock,
+                offset,
+            } => {
+                let sample = recording.samples().get(*next)?;
+                let offset = *offset.get_or_insert_with(|| clock.now() - sample.timestamp);
+                let at = sample.timestamp + offset;
+
+                clock.sleep_until(at).await;
+                *next += 1;
+                Some(Sample {
+                    timestamp: at,
+                    ..sample.clone()
+                })
+            }
+        }
+    }
+}
+
+fn generate_random_float(rng: &mut impl Rng) -> f64 {
+    let int_value = rng.random_range(-100000..=100000);
+    int_value as f64 / 10000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::timeout;
+
+    async fn values(source: &Source, count: usize) -> Vec<f64> {
+        let (mut producer, _) = Producer::new(source, &SimClock::default());
+        let mut values = Vec::with_capacity(count);
+        for _ in 0..count {
+            values.push(producer.next().await.unwrap().value);
+        }
+        values
+    }
+
+    #[tokio::test]
+    async fn test_seeded_random_is_reproducible() {
+        let seeded = Source::Random { seed: Some(42) };
+
+        assert_eq!(values(&seeded, 5).await, values(&seeded, 5).await);
+        assert_ne!(
+            values(&seeded, 5).await,
+            values(&Source::Random { seed: Some(43) }, 5).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_random_rate_survives_drops() {
+        let (mut producer, _) = Producer::new(&Source::default(), &SimClock::default());
+
+        // Every wait is shorter than the period, a sample still comes.
+        let mut samples = 0;
+        for _ in 0..20 {
+            if timeout(Duration::from_millis(2), producer.next())
+                .await
+                .is_ok()
+            {
+                samples += 1;
+            }
+        }
+        assert!(samples > 0);
+    }
+
+    #[test]
+    fn test_global_seed() {
+        let derived = |id| match Source::default().with_global_seed(Some(7), id) {
+            Source::Random { seed } => seed.unwrap(),
+            _ => unreachable!(),
+        };
+
+        assert_eq!(derived("S1.P"), derive_seed(7, "S1.P"));
+        assert_ne!(derived("S1.P"), derived("S1.Q"));
+        assert!(matches!(
+            Source::Random { seed: Some(1) }.with_global_seed(Some(7), "S1.P"),
+            Source::Random { seed: Some(1) }
+        ));
+        assert!(matches!(
+            Source::default().with_global_seed(None, "S1.P"),
+            Source::Random { seed: None }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_replay_follows_clock() {
+        let recorded = DateTime::from_timestamp(1_000, 0).unwrap();
+        let recording = Recording::new(
+            [3.0, 1.0, 2.0]
+                .into_iter()
+                .map(|value| Sample {
+                    value,
+                    quality: Quality::Good,
+                    timestamp: recorded + TimeDelta::seconds(value as i64),
+                })
+                .collect(),
+        );
+        let clock = SimClock::starting_at(DateTime::from_timestamp(0, 0).unwrap());
+        clock.set_speed(100.0).unwrap();
+        let (mut producer, _) = Producer::new(&Source::Replay(recording), &clock);
+
+        let first = producer.next().await.unwrap();
+        let mut samples = vec![first.clone()];
+        while let Some(sample) = producer.next().await {
+            samples.push(sample);
+        }
+
+        assert_eq!(
+            samples.iter().map(|s| s.value).collect::<Vec<_>>(),
+            vec![1.0, 2.0, 3.0]
+        );
+        assert_eq!(
+            samples[2].timestamp - first.timestamp,
+            TimeDelta::seconds(2)
+        );
+        assert!(clock.now() >= samples[2].timestamp);
+    }
+}
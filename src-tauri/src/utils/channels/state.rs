@@ -1,14 +1,795 @@
-use tokio::sync::Mutex;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, RwLock,
+};
+
+use chrono::{DateTime, Utc};
+use dashmap::{mapref::entry::Entry, DashMap};
+use serde::Deserialize;
+use tauri::ipc::Channel;
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio::time::{Duration, Instant};
 
-use crate::utils::tasks::CancellableTask;
-use std::{
-    collections::HashMap,
-    sync::{atomic::AtomicBool, Arc},
+use crate::utils::clock::SimClock;
+use crate::utils::soe::state::{EventKind, Recorder};
+use crate::utils::tasks::{
+    join_all,
+    supervisor::{Supervised, Supervision, SupervisorStatus},
+    CancelReason, CancellableTask,
 };
 
-#[derive(Default)]
-pub struct ChannelsInner {
-    pub channels: HashMap<String, (Arc<AtomicBool>, CancellableTask<()>)>,
+use super::error::{Error, Result};
+use super::fault::Fault;
+use super::filter::Filter;
+use super::history::{History, Record};
+use super::link::Impairment;
+use super::outbox::{Backpressure, DeliveryStats, Outbox};
+use super::selector::{Matcher, Pattern};
+use super::source::{Sample, Source};
+use super::subscription::{self, Subscription};
+use super::worker::{self, Command, Event};
+
+/// Events buffered for the pattern subscriptions before the slowest one lags.
+const BUS_CAPACITY: usize = 4096;
+
+/// Handle used to control a channel without touching the registry.
+#[derive(Clone)]
+pub struct ChannelControl {
+    mailbox: mpsc::Sender<Command>,
+    paused: watch::Receiver<bool>,
+    faults: watch::Receiver<Vec<Fault>>,
+    impairment: watch::Receiver<Option<Impairment>>,
+}
+
+impl ChannelControl {
+    pub fn new(
+        mailbox: mpsc::Sender<Command>,
+        paused: watch::Receiver<bool>,
+        faults: watch::Receiver<Vec<Fault>>,
+        impairment: watch::Receiver<Option<Impairment>>,
+    ) -> Self {
+        Self {
+            mailbox,
+            paused,
+            faults,
+            impairment,
+        }
+    }
+
+    async fn request(
+        &self,
+        command: impl FnOnce(oneshot::Sender<bool>) -> Command,
+    ) -> Option<bool> {
+        let (reply, changed) = oneshot::channel();
+        self.mailbox.send(command(reply)).await.ok()?;
+        changed.await.ok()
+    }
+
+    /// Resumes the channel, `Some(false)` if it was running and `None` once
+    /// its task is gone.
+    pub async fn start(&self) -> Option<bool> {
+        self.request(Command::Start).await
+    }
+
+    /// Pauses the channel, `Some(false)` if it was paused and `None` once its
+    /// task is gone.
+    pub async fn pause(&self) -> Option<bool> {
+        self.request(Command::Pause).await
+    }
+
+    /// Replaces the injected faults, `Some(false)` if they were already set
+    /// and `None` once the task is gone.
+    pub async fn set_faults(&self, faults: Vec<Fault>) -> Option<bool> {
+        self.request(|reply| Command::SetFaults(faults, reply))
+            .await
+    }
+
+    /// Replaces the impairment of the link to the subscribers, `None`
+    /// restores a perfect link.
+    pub async fn set_impairment(&self, impairment: Option<Impairment>) -> Option<bool> {
+        self.request(|reply| Command::SetImpairment(impairment, reply))
+            .await
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    pub fn faults(&self) -> Vec<Fault> {
+        self.faults.borrow().clone()
+    }
+
+    pub fn impairment(&self) -> Option<Impairment> {
+        self.impairment.borrow().clone()
+    }
+}
+
+/// Declarative description of a channel.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ChannelSpec {
+    #[serde(default)]
+    pub source: Source,
+    /// Engineering unit of the values, e.g. `kV`.
+    #[serde(default)]
+    pub unit: Option<String>,
+    #[serde(default)]
+    pub filters: Vec<Filter>,
+    /// Free-form labels used to select channels in bulk, e.g. `substation-1`.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Queueing towards the subscriber when it falls behind.
+    #[serde(default)]
+    pub backpressure: Backpressure,
+    /// Measurement errors injected from the start, see `set_faults`.
+    #[serde(default)]
+    pub faults: Vec<Fault>,
+    /// Degradation of the link to the subscribers, see `set_impairment`.
+    #[serde(default)]
+    pub impairment: Option<Impairment>,
+    /// Records every change of value in the sequence of events, for the
+    /// discrete channels such as breaker positions.
+    #[serde(default)]
+    pub soe: Option<EventKind>,
+    /// Samples kept for the exports, `HISTORY_CAPACITY` by default and 0 to
+    /// keep none.
+    #[serde(default)]
+    pub history: Option<usize>,
+    /// Restarts of the task of the channel, e.g. after a panic in a filter.
+    #[serde(default)]
+    pub supervision: Supervision,
+}
+
+pub struct ChannelHandle {
+    pub control: ChannelControl,
+    pub tags: Vec<String>,
+    pub unit: Option<String>,
+    pub feed: Option<mpsc::Sender<Sample>>,
+    /// Last sample sent to the subscriber.
+    pub latest: watch::Receiver<Option<Sample>>,
+    pub outbox: Arc<Outbox<Event>>,
+    pub history: Arc<History>,
+    pub supervised: Supervised,
+}
+
+/// Point-in-time view of a channel.
+pub struct Snapshot {
+    pub id: String,
+    pub paused: bool,
+    pub tags: Vec<String>,
+    pub unit: Option<String>,
+    pub faults: Vec<Fault>,
+    pub impairment: Option<Impairment>,
+    pub delivery: DeliveryStats,
+    pub supervisor: SupervisorStatus,
+}
+
+impl Snapshot {
+    fn new(id: &str, handle: &ChannelHandle) -> Self {
+        Self {
+            id: id.to_string(),
+            paused: handle.control.is_paused(),
+            tags: handle.tags.clone(),
+            unit: handle.unit.clone(),
+            faults: handle.control.faults(),
+            impairment: handle.control.impairment(),
+            delivery: handle.outbox.stats(),
+            supervisor: handle.supervised.status(),
+        }
+    }
+}
+
+/// Registry of the running channels.
+///
+/// Lookups only lock one shard of the map for the time of a clone, and the
+/// channels are then driven through their own `ChannelControl`.
+pub struct Channels {
+    channels: DashMap<String, ChannelHandle>,
+    subscriptions: Arc<DashMap<String, Subscription>>,
+    next_subscription: AtomicU64,
+    bus: broadcast::Sender<Event>,
+    soe: Recorder,
+    clock: SimClock,
+    /// Global seed, loaded from the settings at startup.
+    seed: RwLock<Option<u64>>,
+}
+
+impl Default for Channels {
+    fn default() -> Self {
+        Self::new(Recorder::default(), SimClock::default())
+    }
+}
+
+impl Channels {
+    pub fn new(soe: Recorder, clock: SimClock) -> Self {
+        Self {
+            channels: DashMap::new(),
+            subscriptions: Arc::new(DashMap::new()),
+            next_subscription: AtomicU64::new(1),
+            bus: broadcast::channel(BUS_CAPACITY).0,
+            soe,
+            clock,
+            seed: RwLock::new(None),
+        }
+    }
+
+    /// Seed from which unseeded random sources derive their own.
+    pub fn seed(&self) -> Option<u64> {
+        *self.seed.read().unwrap()
+    }
+
+    pub fn set_seed(&self, seed: Option<u64>) {
+        *self.seed.write().unwrap() = seed;
+    }
+
+    pub fn register(&self, id: String, channel: Channel<Event>, spec: ChannelSpec) -> Result<()> {
+        match self.channels.entry(id) {
+            Entry::Occupied(entry) => Err(Error::ChannelAlreadyExists {
+                id: entry.key().clone(),
+            }),
+            Entry::Vacant(entry) => {
+                let handle = worker::spawn(
+                    entry.key().clone(),
+                    channel,
+                    spec,
+                    self.bus.clone(),
+                    self.soe.clone(),
+                    self.clock.clone(),
+                )?;
+                entry.insert(handle);
+                Ok(())
+            }
+        }
+    }
+
+    /// Removes a channel and cancels its task.
+    pub fn remove(&self, id: &str) -> Option<ChannelHandle> {
+        let (_, handle) = self.channels.remove(id)?;
+        handle.supervised.cancel();
+        Some(handle)
+    }
+
+    pub fn control(&self, id: &str) -> Option<ChannelControl> {
+        self.channels.get(id).map(|c| c.control.clone())
+    }
+
+    pub fn snapshot(&self, id: &str) -> Option<Snapshot> {
+        self.channels.get(id).map(|c| Snapshot::new(id, &c))
+    }
+
+    pub fn snapshots(&self) -> Vec<Snapshot> {
+        self.channels
+            .iter()
+            .map(|c| Snapshot::new(c.key(), &c))
+            .collect()
+    }
+
+    /// Sorted ids of the channels matching `matcher`.
+    pub fn select(&self, matcher: &Matcher) -> Vec<String> {
+        let mut ids: Vec<String> = self
+            .channels
+            .iter()
+            .filter(|c| matcher.is_match(c.key(), &c.tags))
+            .map(|c| c.key().clone())
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    /// Forwards the events of every current and future channel matching
+    /// `pattern` to `channel`, returns the subscription id. The subscription
+    /// is removed once a send to `channel` fails.
+    pub fn subscribe(
+        &self,
+        pattern: Pattern,
+        channel: Channel<Event>,
+        backpressure: Backpressure,
+    ) -> String {
+        let id = format!(
+            "subscription-{}",
+            self.next_subscription.fetch_add(1, Ordering::Relaxed)
+        );
+        let subscriptions = Arc::downgrade(&self.subscriptions);
+        let remove = {
+            let id = id.clone();
+            move || {
+                if let Some(subscriptions) = subscriptions.upgrade() {
+                    subscriptions.remove(&id);
+                }
+            }
+        };
+
+        // Spawned under the lock of the entry, so that a failure can only
+        // remove it once inserted.
+        self.subscriptions.entry(id.clone()).or_insert_with(|| {
+            subscription::spawn(
+                id.clone(),
+                pattern,
+                channel,
+                backpressure,
+                self.bus.subscribe(),
+                remove,
+            )
+        });
+        id
+    }
+
+    pub fn unsubscribe(&self, id: &str) -> Result<()> {
+        match self.subscriptions.remove(id) {
+            Some((_, subscription)) => {
+                subscription.task.cancel();
+                Ok(())
+            }
+            None => Err(Error::SubscriptionNotFound { id: id.to_string() }),
+        }
+    }
+
+    /// Pattern and delivery counters of every subscription.
+    pub fn subscriptions(&self) -> Vec<(String, String, DeliveryStats)> {
+        self.subscriptions
+            .iter()
+            .map(|s| {
+                (
+                    s.key().clone(),
+                    s.pattern.as_str().to_string(),
+                    s.outbox.stats(),
+                )
+            })
+            .collect()
+    }
+
+    /// Pushes a sample into an external channel, returns `false` if the channel
+    /// does not exist, is not external or its feed is full.
+    pub fn publish(&self, id: &str, sample: Sample) -> bool {
+        match self.channels.get(id) {
+            Some(channel) => channel
+                .feed
+                .as_ref()
+                .is_some_and(|feed| feed.try_send(sample).is_ok()),
+            None => false,
+        }
+    }
+
+    /// Unit and recorded samples of a channel within `from..=to`.
+    pub fn history(
+        &self,
+        id: &str,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Option<(Option<String>, Vec<Record>)> {
+        self.channels
+            .get(id)
+            .map(|c| (c.unit.clone(), c.history.range(from, to)))
+    }
+
+    pub fn latest(&self, id: &str) -> Option<Sample> {
+        self.channels
+            .get(id)
+            .and_then(|c| c.latest.borrow().clone())
+    }
+
+    /// Stops every channel and subscription for the app exit, within
+    /// `timeout` overall. Returns the ids of the ones that did not end in
+    /// time.
+    ///
+    /// Dropping its mailbox stops the source of a channel while the events
+    /// already queued are still delivered and recorded, the subscriptions are
+    /// cancelled once no channel is left to feed them.
+    pub async fn shutdown(&self, timeout: Duration) -> Vec<String> {
+        let deadline = Instant::now() + timeout;
+        let ids: Vec<String> = self.channels.iter().map(|c| c.key().clone()).collect();
+        let channels: Vec<(String, CancellableTask<()>)> = ids
+            .iter()
+            .filter_map(|id| self.channels.remove(id))
+            .map(|(id, handle)| (id, handle.supervised.task))
+            .collect();
+        let mut late = join_all(channels, timeout).await;
+
+        let ids: Vec<String> = self.subscriptions.iter().map(|s| s.key().clone()).collect();
+        let subscriptions: Vec<(String, CancellableTask<()>)> = ids
+            .iter()
+            .filter_map(|id| self.subscriptions.remove(id))
+            .map(|(id, subscription)| {
+                subscription.task.cancel_with(CancelReason::Shutdown);
+                (id, subscription.task)
+            })
+            .collect();
+        late.extend(
+            join_all(
+                subscriptions,
+                deadline.saturating_duration_since(Instant::now()),
+            )
+            .await,
+        );
+        late
+    }
 }
 
-pub type Channels = Mutex<ChannelsInner>;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::channels::{outbox::DropPolicy, selector::Selector, source::Recording};
+    use crate::utils::tasks::supervisor::SupervisorState;
+    use std::sync::{Arc, Mutex};
+    use tauri::ipc::InvokeResponseBody;
+    use tokio::time::{sleep, Duration};
+
+    fn decode(body: InvokeResponseBody) -> Event {
+        match body {
+            InvokeResponseBody::Json(json) => serde_json::from_str(&json).unwrap(),
+            InvokeResponseBody::Raw(_) => panic!("expected a JSON event"),
+        }
+    }
+
+    fn sink() -> (Channel<Event>, Arc<Mutex<Vec<f64>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received_clone = received.clone();
+        let channel = Channel::new(move |body| {
+            received_clone.lock().unwrap().push(decode(body).value);
+            Ok(())
+        });
+        (channel, received)
+    }
+
+    fn external() -> ChannelSpec {
+        ChannelSpec {
+            source: Source::External,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_register_twice() {
+        let channels = Channels::default();
+
+        channels
+            .register("a".to_string(), sink().0, external())
+            .unwrap();
+        assert!(matches!(
+            channels.register("a".to_string(), sink().0, external()),
+            Err(Error::ChannelAlreadyExists { .. })
+        ));
+        assert!(channels.remove("a").is_some());
+        assert!(channels.remove("a").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_control_mailbox() {
+        let channels = Channels::default();
+        let (channel, received) = sink();
+        channels
+            .register("a".to_string(), channel, external())
+            .unwrap();
+        let control = channels.control("a").unwrap();
+
+        assert_eq!(control.start().await, Some(false));
+        assert_eq!(control.pause().await, Some(true));
+        assert!(control.is_paused());
+        assert_eq!(control.pause().await, Some(false));
+
+        assert!(channels.publish("a", Sample::now(1.0)));
+        sleep(Duration::from_millis(20)).await;
+        assert_eq!(control.start().await, Some(true));
+        assert!(channels.publish("a", Sample::now(2.0)));
+        sleep(Duration::from_millis(20)).await;
+
+        assert_eq!(*received.lock().unwrap(), vec![2.0]);
+        assert_eq!(channels.latest("a").map(|s| s.value), Some(2.0));
+
+        channels.remove("a");
+        assert_eq!(control.start().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let channels = Channels::default();
+        let (channel, received) = sink();
+        channels
+            .register("a".to_string(), channel, external())
+            .unwrap();
+        let (subscriber, forwarded) = sink();
+        channels.subscribe(
+            Pattern::new("*").unwrap(),
+            subscriber,
+            Backpressure::default(),
+        );
+
+        assert!(channels.publish("a", Sample::now(1.0)));
+        sleep(Duration::from_millis(20)).await;
+
+        let late = channels.shutdown(Duration::from_millis(200)).await;
+        assert!(late.is_empty());
+        assert!(channels.snapshots().is_empty());
+        assert!(channels.subscriptions().is_empty());
+        assert_eq!(*received.lock().unwrap(), vec![1.0]);
+        assert_eq!(*forwarded.lock().unwrap(), vec![1.0]);
+    }
+
+    #[tokio::test]
+    async fn test_select() {
+        let channels = Channels::default();
+        for (id, tags) in [
+            ("S1.P", vec!["substation-1"]),
+            ("S1.Q", vec!["substation-1", "reactive"]),
+            ("S2.Q", vec!["reactive"]),
+        ] {
+            let spec = ChannelSpec {
+                tags: tags.into_iter().map(String::from).collect(),
+                ..external()
+            };
+            channels.register(id.to_string(), sink().0, spec).unwrap();
+        }
+
+        let reactive = Selector::Tag("reactive".to_string()).compile().unwrap();
+        assert_eq!(channels.select(&reactive), vec!["S1.Q", "S2.Q"]);
+
+        let substation = Selector::Glob("S1.*".to_string()).compile().unwrap();
+        assert_eq!(channels.select(&substation), vec!["S1.P", "S1.Q"]);
+    }
+
+    #[tokio::test]
+    async fn test_pattern_subscription() {
+        let channels = Channels::default();
+        channels
+            .register("S1.P".to_string(), sink().0, external())
+            .unwrap();
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received_clone = received.clone();
+        let subscription = channels.subscribe(
+            Pattern::new("S1.*").unwrap(),
+            Channel::new(move |body| {
+                received_clone.lock().unwrap().push(decode(body).id);
+                Ok(())
+            }),
+            Backpressure::default(),
+        );
+
+        // Channels registered after the subscription are matched too.
+        channels
+            .register("S1.Q".to_string(), sink().0, external())
+            .unwrap();
+        channels
+            .register("S2.P".to_string(), sink().0, external())
+            .unwrap();
+        for id in ["S1.P", "S2.P", "S1.Q"] {
+            assert!(channels.publish(id, Sample::now(1.0)));
+            sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(*received.lock().unwrap(), vec!["S1.P", "S1.Q"]);
+
+        channels.unsubscribe(&subscription).unwrap();
+        assert!(channels.publish("S1.P", Sample::now(2.0)));
+        sleep(Duration::from_millis(20)).await;
+        assert_eq!(received.lock().unwrap().len(), 2);
+        assert!(matches!(
+            channels.unsubscribe(&subscription),
+            Err(Error::SubscriptionNotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_failed_subscription_removed() {
+        let channels = Channels::default();
+        channels
+            .register("S1.P".to_string(), sink().0, external())
+            .unwrap();
+        let subscription = channels.subscribe(
+            Pattern::new("S1.*").unwrap(),
+            Channel::new(|_: InvokeResponseBody| Err(tauri::Error::FailedToReceiveMessage)),
+            Backpressure::default(),
+        );
+        assert_eq!(channels.subscriptions().len(), 1);
+
+        assert!(channels.publish("S1.P", Sample::now(1.0)));
+        sleep(Duration::from_millis(20)).await;
+        assert!(channels.subscriptions().is_empty());
+        assert!(matches!(
+            channels.unsubscribe(&subscription),
+            Err(Error::SubscriptionNotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_failed_blocking_subscriptions_removed() {
+        let channels = Channels::default();
+        channels
+            .register("S1.P".to_string(), sink().0, external())
+            .unwrap();
+        let backpressure = Backpressure {
+            policy: DropPolicy::Block,
+            capacity: 1,
+        };
+        // Whether the forwarding picks the next event or the stop once the
+        // send failed is random, hence several subscriptions.
+        for _ in 0..16 {
+            channels.subscribe(
+                Pattern::new("S1.*").unwrap(),
+                Channel::new(|_: InvokeResponseBody| Err(tauri::Error::FailedToReceiveMessage)),
+                backpressure,
+            );
+        }
+
+        for value in 0..10 {
+            assert!(channels.publish("S1.P", Sample::now(value as f64)));
+        }
+        sleep(Duration::from_millis(20)).await;
+        assert!(channels.subscriptions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_channel_restarted_after_panic() {
+        let channels = Channels::default();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received_clone = received.clone();
+        let channel = Channel::new(move |body| {
+            let value = decode(body).value;
+            if value == 1.0 {
+                panic!("broken subscriber");
+            }
+            received_clone.lock().unwrap().push(value);
+            Ok(())
+        });
+        let spec = ChannelSpec {
+            supervision: Supervision {
+                initial_backoff_ms: 1,
+                ..Default::default()
+            },
+            ..external()
+        };
+        channels.register("a".to_string(), channel, spec).unwrap();
+
+        for value in [1.0, 2.0] {
+            assert!(channels.publish("a", Sample::now(value)));
+            sleep(Duration::from_millis(20)).await;
+        }
+
+        assert_eq!(*received.lock().unwrap(), vec![2.0]);
+        let supervisor = channels.snapshot("a").unwrap().supervisor;
+        assert_eq!(supervisor.restarts, 1);
+        assert_eq!(supervisor.state, SupervisorState::Running);
+        assert_eq!(
+            supervisor.last_panic.map(|panic| panic.message),
+            Some("broken subscriber".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_sends_keep_channel() {
+        let channels = Channels::default();
+        let spec = ChannelSpec {
+            backpressure: Backpressure {
+                policy: DropPolicy::DropNewest,
+                capacity: 1,
+            },
+            ..external()
+        };
+        let failing =
+            Channel::new(|_: InvokeResponseBody| Err(tauri::Error::FailedToReceiveMessage));
+        channels.register("a".to_string(), failing, spec).unwrap();
+
+        for value in [1.0, 2.0] {
+            assert!(channels.publish("a", Sample::now(value)));
+            sleep(Duration::from_millis(20)).await;
+        }
+
+        let snapshot = channels.snapshot("a").unwrap();
+        assert_eq!(snapshot.delivery.failed, 2);
+        assert_eq!(channels.latest("a").map(|s| s.value), Some(2.0));
+        assert_eq!(channels.control("a").unwrap().pause().await, Some(true));
+    }
+
+    #[tokio::test]
+    async fn test_set_faults_at_runtime() {
+        let channels = Channels::default();
+        let (channel, received) = sink();
+        channels
+            .register("a".to_string(), channel, external())
+            .unwrap();
+        let control = channels.control("a").unwrap();
+
+        let faults = vec![Fault::StuckAt { value: Some(7.0) }];
+        assert_eq!(control.set_faults(faults.clone()).await, Some(true));
+        assert_eq!(control.set_faults(faults.clone()).await, Some(false));
+        assert_eq!(channels.snapshot("a").unwrap().faults, faults);
+
+        assert!(channels.publish("a", Sample::now(1.0)));
+        sleep(Duration::from_millis(20)).await;
+        assert_eq!(control.set_faults(Vec::new()).await, Some(true));
+        assert!(channels.publish("a", Sample::now(2.0)));
+        sleep(Duration::from_millis(20)).await;
+
+        assert_eq!(*received.lock().unwrap(), vec![7.0, 2.0]);
+    }
+
+    #[tokio::test]
+    async fn test_impaired_link() {
+        let channels = Channels::default();
+        let (channel, received) = sink();
+        let spec = ChannelSpec {
+            impairment: Some(Impairment {
+                latency_ms: 50,
+                ..Default::default()
+            }),
+            ..external()
+        };
+        channels.register("a".to_string(), channel, spec).unwrap();
+        let control = channels.control("a").unwrap();
+
+        assert!(channels.publish("a", Sample::now(1.0)));
+        sleep(Duration::from_millis(20)).await;
+        assert!(received.lock().unwrap().is_empty());
+        assert_eq!(channels.latest("a"), None);
+        sleep(Duration::from_millis(60)).await;
+        assert_eq!(*received.lock().unwrap(), vec![1.0]);
+
+        let lossy = Impairment {
+            loss: 1.0,
+            ..Default::default()
+        };
+        assert_eq!(
+            control.set_impairment(Some(lossy.clone())).await,
+            Some(true)
+        );
+        assert_eq!(channels.snapshot("a").unwrap().impairment, Some(lossy));
+        assert!(channels.publish("a", Sample::now(2.0)));
+        sleep(Duration::from_millis(20)).await;
+        assert_eq!(*received.lock().unwrap(), vec![1.0]);
+
+        assert_eq!(control.set_impairment(None).await, Some(true));
+        assert!(channels.publish("a", Sample::now(3.0)));
+        sleep(Duration::from_millis(20)).await;
+        assert_eq!(*received.lock().unwrap(), vec![1.0, 3.0]);
+    }
+
+    #[tokio::test]
+    async fn test_exhausted_source_drains_link() {
+        let channels = Channels::default();
+        let (channel, received) = sink();
+        let spec = ChannelSpec {
+            source: Source::Replay(Recording::new(vec![Sample::now(5.0)])),
+            impairment: Some(Impairment {
+                latency_ms: 50,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        channels.register("a".to_string(), channel, spec).unwrap();
+
+        sleep(Duration::from_millis(20)).await;
+        assert!(received.lock().unwrap().is_empty());
+        sleep(Duration::from_millis(60)).await;
+        assert_eq!(*received.lock().unwrap(), vec![5.0]);
+    }
+
+    #[tokio::test]
+    async fn test_sequence_and_soe() {
+        let soe = Recorder::default();
+        let channels = Channels::new(soe.clone(), SimClock::default());
+        let sequences = Arc::new(Mutex::new(Vec::new()));
+        let sequences_clone = sequences.clone();
+        let channel = Channel::new(move |body| {
+            sequences_clone.lock().unwrap().push(decode(body).sequence);
+            Ok(())
+        });
+        let spec = ChannelSpec {
+            soe: Some(EventKind::Position),
+            ..external()
+        };
+        channels
+            .register("S1.CB1".to_string(), channel, spec)
+            .unwrap();
+
+        for value in [0.0, 0.0, 1.0] {
+            assert!(channels.publish("S1.CB1", Sample::now(value)));
+            sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(*sequences.lock().unwrap(), vec![1, 2, 3]);
+
+        let events = soe.query(&Default::default()).unwrap();
+        assert_eq!(
+            events.iter().map(|e| e.value).collect::<Vec<_>>(),
+            vec![0.0, 1.0]
+        );
+        assert!(events.iter().all(|e| e.kind == EventKind::Position));
+    }
+}
@@ -0,0 +1,87 @@
+use std::sync::Arc;
+
+use tauri::ipc::Channel;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::utils::tasks::CancellableTask;
+
+use super::outbox::{Backpressure, Outbox};
+use super::selector::Pattern;
+use super::worker::{self, Event, OnFailure};
+
+/// Forwarding of the events of every channel matching a pattern, including
+/// channels registered after the subscription. It ends at the first failed
+/// send, the webview being gone.
+pub struct Subscription {
+    pub pattern: Pattern,
+    pub outbox: Arc<Outbox<Event>>,
+    pub task: CancellableTask<()>,
+}
+
+/// Spawns the forwarding task, which calls `remove` once `channel` failed.
+pub fn spawn(
+    id: String,
+    pattern: Pattern,
+    channel: Channel<Event>,
+    backpressure: Backpressure,
+    mut bus: broadcast::Receiver<Event>,
+    remove: impl FnOnce() + Send + 'static,
+) -> Subscription {
+    let pattern_clone = pattern.clone();
+    let outbox = Arc::new(Outbox::new(backpressure));
+    let outbox_clone = outbox.clone();
+
+    let task = CancellableTask::new(move |token| {
+        let pattern = pattern_clone;
+        let outbox = outbox_clone;
+
+        async move {
+            let stop = token.child_token();
+            let forward = async {
+                loop {
+                    tokio::select! {
+                        event = bus.recv() => match event {
+                            Ok(event) => {
+                                if pattern.is_match(&event.id) {
+                                    tokio::select! {
+                                        _ = outbox.push(event) => {}
+                                        _ = stop.cancelled() => break,
+                                    }
+                                }
+                            }
+                            Err(RecvError::Lagged(skipped)) => {
+                                log::warn!("Subscription '{}' lagged, {} events skipped", id, skipped);
+                            }
+                            Err(RecvError::Closed) => break,
+                        },
+                        _ = stop.cancelled() => {
+                            if token.is_cancelled() {
+                                log::info!("Subscription '{}' was cancelled", id);
+                            }
+                            break;
+                        }
+                    }
+                }
+                outbox.close();
+            };
+
+            let delivery = async {
+                let delivered =
+                    worker::deliver(&id, &channel, &outbox, &stop, OnFailure::Stop).await;
+                stop.cancel();
+                delivered
+            };
+
+            if let ((), Err(e)) = tokio::join!(forward, delivery) {
+                log::warn!("Subscription '{}' removed after a failed send: {:?}", id, e);
+                remove();
+            }
+        }
+    });
+
+    Subscription {
+        pattern,
+        outbox,
+        task,
+    }
+}
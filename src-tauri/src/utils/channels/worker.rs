@@ -0,0 +1,330 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tauri::ipc::Channel;
+use tokio::sync::{broadcast, mpsc, oneshot, watch, Mutex};
+use tokio::time::{sleep_until, Instant};
+use tokio_util::sync::CancellationToken;
+
+use crate::utils::clock::SimClock;
+use crate::utils::soe::state::{EventKind, Recorder};
+use crate::utils::tasks::supervisor;
+
+use super::error::{Error, Result};
+use super::fault::{self, Fault, FaultInjector};
+use super::filter::FilterChain;
+use super::history::{History, Record, HISTORY_CAPACITY};
+use super::link::{Impairment, Link};
+use super::outbox::Outbox;
+use super::source::{derive_seed, Producer, Quality, Sample, Source};
+use super::state::{ChannelControl, ChannelHandle, ChannelSpec};
+
+/// Capacity of the command mailbox of each channel.
+const MAILBOX_CAPACITY: usize = 8;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    pub id: String,
+    /// Increases by one for each sample of the channel, a jump reveals
+    /// samples lost or dropped on the way to the subscriber.
+    pub sequence: u64,
+    pub value: f64,
+    pub quality: Quality,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Control commands handled by the task of a channel, each one answering
+/// whether the state changed.
+pub enum Command {
+    Start(oneshot::Sender<bool>),
+    Pause(oneshot::Sender<bool>),
+    SetFaults(Vec<Fault>, oneshot::Sender<bool>),
+    SetImpairment(Option<Impairment>, oneshot::Sender<bool>),
+}
+
+/// What a failed send does to a delivery.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnFailure {
+    /// Counts it and goes on, a busy webview may recover.
+    Continue,
+    /// Counts it and ends the delivery with its error.
+    Stop,
+}
+
+/// Delivers the events of `outbox` to `channel` until it is closed and
+/// drained or `token` is cancelled. Failed sends are counted, and end the
+/// delivery with `OnFailure::Stop`.
+pub async fn deliver(
+    name: &str,
+    channel: &Channel<Event>,
+    outbox: &Outbox<Event>,
+    token: &CancellationToken,
+    on_failure: OnFailure,
+) -> tauri::Result<()> {
+    let mut failing = false;
+    loop {
+        let event = tokio::select! {
+            event = outbox.pop() => match event {
+                Some(event) => event,
+                None => break,
+            },
+            _ = token.cancelled() => break,
+        };
+
+        let value = event.value;
+        match channel.send(event) {
+            Ok(()) => {
+                outbox.record(true);
+                failing = false;
+                log::info!("Sent to {} - value: {}", name, value);
+            }
+            Err(e) => {
+                outbox.record(false);
+                if on_failure == OnFailure::Stop {
+                    return Err(e);
+                }
+                if !failing {
+                    log::warn!("Failed to send event to channel '{}': {:?}", name, e);
+                }
+                failing = true;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// State of the task of a channel, kept across the restarts of its
+/// supervisor.
+struct Worker {
+    id: String,
+    channel: Channel<Event>,
+    filters: FilterChain,
+    injector: FaultInjector,
+    link: Link<(u64, Sample)>,
+    producer: Producer,
+    commands: mpsc::Receiver<Command>,
+    paused: watch::Sender<bool>,
+    faults: watch::Sender<Vec<Fault>>,
+    impairment: watch::Sender<Option<Impairment>>,
+    latest: watch::Sender<Option<Sample>>,
+    outbox: Arc<Outbox<Event>>,
+    history: Arc<History>,
+    bus: broadcast::Sender<Event>,
+    soe: Recorder,
+    soe_kind: Option<EventKind>,
+    sequence: u64,
+    recorded: Option<f64>,
+    exhausted: bool,
+}
+
+impl Worker {
+    /// Produces and delivers the samples until the source is exhausted, the
+    /// mailbox dropped or `token` cancelled.
+    async fn run(&mut self, token: &CancellationToken) {
+        let Worker {
+            ref id,
+            ref channel,
+            ref mut filters,
+            ref mut injector,
+            ref mut link,
+            ref mut producer,
+            ref mut commands,
+            ref paused,
+            ref faults,
+            ref impairment,
+            ref latest,
+            ref outbox,
+            ref history,
+            ref bus,
+            ref soe,
+            soe_kind,
+            ref mut sequence,
+            ref mut recorded,
+            ref mut exhausted,
+        } = *self;
+
+        let produce = async {
+            'produce: loop {
+                let arrival = link.next_arrival();
+
+                // A cancelled channel no longer answers its commands.
+                tokio::select! {
+                    biased;
+                    _ = token.cancelled() => {
+                        log::info!("Task for channel '{}' was cancelled", id);
+                        break;
+                    }
+                    command = commands.recv() => match command {
+                        Some(Command::Start(reply)) => {
+                            let _ = reply.send(paused.send_replace(false));
+                        }
+                        Some(Command::Pause(reply)) => {
+                            let _ = reply.send(!paused.send_replace(true));
+                        }
+                        Some(Command::SetFaults(set, reply)) => {
+                            let changed = injector.faults() != set.as_slice();
+                            log::info!("Faults of channel '{}' set to {:?}", id, set);
+                            faults.send_replace(set.clone());
+                            injector.set(set);
+                            let _ = reply.send(changed);
+                        }
+                        Some(Command::SetImpairment(set, reply)) => {
+                            let changed = link.impairment() != set.as_ref();
+                            log::info!("Link of channel '{}' set to {:?}", id, set);
+                            impairment.send_replace(set.clone());
+                            link.set(set);
+                            let _ = reply.send(changed);
+                        }
+                        None => break,
+                    },
+                    sample = producer.next(), if !*exhausted => {
+                        let Some(sample) = sample else {
+                            log::info!("Source of channel '{}' is exhausted", id);
+                            *exhausted = true;
+                            continue;
+                        };
+
+                        if *paused.borrow() {
+                            continue;
+                        }
+
+                        let Some(sample) = injector.apply(sample) else {
+                            continue;
+                        };
+
+                        let Some(sample) = filters.apply(sample) else {
+                            continue;
+                        };
+
+                        if let Some(kind) = soe_kind {
+                            if *recorded != Some(sample.value) {
+                                soe.record(id, kind, sample.value, sample.timestamp, "channel");
+                                *recorded = Some(sample.value);
+                            }
+                        }
+
+                        *sequence += 1;
+                        link.send((*sequence, sample), Instant::now());
+                    }
+                    _ = sleep_until(arrival.unwrap_or_else(Instant::now)), if arrival.is_some() => {}
+                }
+
+                while let Some((sequence, sample)) = link.receive(Instant::now()) {
+                    let event = Event {
+                        id: id.clone(),
+                        sequence,
+                        value: sample.value,
+                        quality: sample.quality,
+                        timestamp: sample.timestamp,
+                    };
+                    history.push(Record {
+                        sequence,
+                        value: sample.value,
+                        quality: sample.quality,
+                        timestamp: sample.timestamp,
+                    });
+                    latest.send_replace(Some(sample));
+
+                    if bus.receiver_count() > 0 {
+                        let _ = bus.send(event.clone());
+                    }
+
+                    tokio::select! {
+                        _ = outbox.push(event) => {}
+                        _ = token.cancelled() => break 'produce,
+                    }
+                }
+
+                // The samples still in flight on the link arrive before the
+                // end of an exhausted source.
+                if *exhausted && link.next_arrival().is_none() {
+                    break;
+                }
+            }
+            outbox.close();
+        };
+
+        let delivery = deliver(id, channel, outbox, token, OnFailure::Continue);
+        let _ = tokio::join!(produce, delivery);
+    }
+}
+
+/// Spawns the task producing the samples of channel `id` into `channel`,
+/// events are also published on `bus` for the pattern subscriptions and
+/// discrete changes recorded in `soe`. Replays follow `clock`. The task is
+/// restarted, e.g. after a panic, as `spec.supervision` says.
+pub fn spawn(
+    id: String,
+    channel: Channel<Event>,
+    spec: ChannelSpec,
+    bus: broadcast::Sender<Event>,
+    soe: Recorder,
+    clock: SimClock,
+) -> Result<ChannelHandle> {
+    let filters = match FilterChain::new(&spec.filters) {
+        Ok(filters) => filters,
+        Err(reason) => return Err(Error::InvalidFilter { id, reason }),
+    };
+    if let Err(reason) = fault::validate(&spec.faults) {
+        return Err(Error::InvalidFault { id, reason });
+    }
+    if let Some(Err(reason)) = spec.impairment.as_ref().map(Impairment::validate) {
+        return Err(Error::InvalidImpairment { id, reason });
+    }
+    let seed = match spec.source {
+        Source::Random { seed } => seed,
+        Source::External | Source::Replay(_) => None,
+    };
+    let (faults_tx, faults) = watch::channel(spec.faults.clone());
+    let injector = FaultInjector::new(spec.faults, seed.map(|s| derive_seed(s, "faults")));
+    let (impairment_tx, impairment) = watch::channel(spec.impairment.clone());
+    let link = Link::new(spec.impairment, seed.map(|s| derive_seed(s, "link")));
+    let (producer, feed) = Producer::new(&spec.source, &clock);
+    let (latest_tx, latest) = watch::channel(None);
+    let (paused_tx, paused) = watch::channel(false);
+    let (mailbox, commands) = mpsc::channel(MAILBOX_CAPACITY);
+    let outbox = Arc::new(Outbox::new(spec.backpressure));
+    let history = Arc::new(History::new(spec.history.unwrap_or(HISTORY_CAPACITY)));
+
+    let worker = Arc::new(Mutex::new(Worker {
+        id: id.clone(),
+        channel,
+        filters,
+        injector,
+        link,
+        producer,
+        commands,
+        paused: paused_tx,
+        faults: faults_tx,
+        impairment: impairment_tx,
+        latest: latest_tx,
+        outbox: outbox.clone(),
+        history: history.clone(),
+        bus,
+        soe,
+        soe_kind: spec.soe,
+        sequence: 0,
+        recorded: None,
+        exhausted: false,
+    }));
+
+    let supervised = supervisor::supervise(id, spec.supervision, move |token| {
+        let worker = worker.clone();
+        async move {
+            worker.lock().await.run(&token).await;
+            Ok(())
+        }
+    });
+
+    Ok(ChannelHandle {
+        control: ChannelControl::new(mailbox, paused, faults, impairment),
+        tags: spec.tags,
+        unit: spec.unit,
+        feed,
+        latest,
+        outbox,
+        history,
+        supervised,
+    })
+}
@@ -0,0 +1,36 @@
+use chrono::{DateTime, Utc};
+use tauri::State;
+
+use super::error::Result;
+use super::{ClockStatus, SimClock};
+
+#[tauri::command]
+pub async fn get_clock(state: State<'_, SimClock>) -> Result<ClockStatus> {
+    Ok(state.status())
+}
+
+#[tauri::command]
+pub async fn set_clock_speed(state: State<'_, SimClock>, speed: f64) -> Result<ClockStatus> {
+    state.set_speed(speed)?;
+    log::info!("Simulation clock speed set to {}", speed);
+    Ok(state.status())
+}
+
+#[tauri::command]
+pub async fn pause_clock(state: State<'_, SimClock>) -> Result<ClockStatus> {
+    state.pause();
+    Ok(state.status())
+}
+
+#[tauri::command]
+pub async fn resume_clock(state: State<'_, SimClock>) -> Result<ClockStatus> {
+    state.resume();
+    Ok(state.status())
+}
+
+#[tauri::command]
+pub async fn seek_clock(state: State<'_, SimClock>, time: DateTime<Utc>) -> Result<ClockStatus> {
+    state.seek(time);
+    log::info!("Simulation clock moved to {}", time);
+    Ok(state.status())
+}
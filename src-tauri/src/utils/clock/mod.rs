@@ -0,0 +1,185 @@
+//! Simulation clock, running at an adjustable speed and able to pause or
+//! jump, followed by the replayed channels.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::watch;
+use tokio::time::{sleep, Instant};
+
+pub mod commands;
+pub mod error;
+
+use error::{Error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ClockStatus {
+    pub now: DateTime<Utc>,
+    pub speed: f64,
+    pub running: bool,
+}
+
+/// Simulated time `simulated` at real instant `real`, from which the clock
+/// advances at `speed` while running.
+#[derive(Debug, Clone, Copy)]
+struct Anchor {
+    simulated: DateTime<Utc>,
+    real: Instant,
+    speed: f64,
+    running: bool,
+}
+
+impl Anchor {
+    fn now(&self) -> DateTime<Utc> {
+        if !self.running {
+            return self.simulated;
+        }
+
+        let elapsed = self.real.elapsed().mul_f64(self.speed);
+        self.simulated + chrono::Duration::from_std(elapsed).unwrap_or(chrono::TimeDelta::MAX)
+    }
+}
+
+/// Cheaply cloned handle on the simulation clock.
+#[derive(Clone)]
+pub struct SimClock {
+    anchor: Arc<watch::Sender<Anchor>>,
+}
+
+impl Default for SimClock {
+    /// Running in real time from the current instant.
+    fn default() -> Self {
+        Self::starting_at(Utc::now())
+    }
+}
+
+impl SimClock {
+    pub fn starting_at(simulated: DateTime<Utc>) -> Self {
+        let anchor = Anchor {
+            simulated,
+            real: Instant::now(),
+            speed: 1.0,
+            running: true,
+        };
+
+        Self {
+            anchor: Arc::new(watch::channel(anchor).0),
+        }
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        self.anchor.borrow().now()
+    }
+
+    pub fn status(&self) -> ClockStatus {
+        let anchor = *self.anchor.borrow();
+        ClockStatus {
+            now: anchor.now(),
+            speed: anchor.speed,
+            running: anchor.running,
+        }
+    }
+
+    /// Re-anchors at the current instant before applying `change`, so that
+    /// the time already elapsed keeps its former speed.
+    fn update(&self, change: impl FnOnce(&mut Anchor)) {
+        self.anchor.send_modify(|anchor| {
+            anchor.simulated = anchor.now();
+            anchor.real = Instant::now();
+            change(anchor);
+        });
+    }
+
+    /// Sets how many simulated seconds elapse per real second.
+    pub fn set_speed(&self, speed: f64) -> Result<()> {
+        if !speed.is_finite() || speed <= 0.0 {
+            return Err(Error::InvalidSpeed { speed });
+        }
+
+        self.update(|anchor| anchor.speed = speed);
+        Ok(())
+    }
+
+    pub fn pause(&self) {
+        self.update(|anchor| anchor.running = false);
+    }
+
+    pub fn resume(&self) {
+        self.update(|anchor| anchor.running = true);
+    }
+
+    /// Jumps to `simulated`, keeping the speed and running state.
+    pub fn seek(&self, simulated: DateTime<Utc>) {
+        self.update(|anchor| anchor.simulated = simulated);
+    }
+
+    /// Waits until the clock reaches `target`, following speed changes,
+    /// pauses and jumps meanwhile. Cancel safe.
+    pub async fn sleep_until(&self, target: DateTime<Utc>) {
+        let mut changes = self.anchor.subscribe();
+
+        loop {
+            let anchor = *changes.borrow_and_update();
+            let remaining = match (target - anchor.now()).to_std() {
+                Ok(remaining) if !remaining.is_zero() => remaining,
+                _ => return,
+            };
+
+            if anchor.running {
+                tokio::select! {
+                    _ = sleep(remaining.div_f64(anchor.speed)) => {}
+                    _ = changes.changed() => {}
+                }
+            } else if changes.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use std::time::Duration as StdDuration;
+
+    #[tokio::test]
+    async fn test_speed() {
+        let start = DateTime::from_timestamp(0, 0).unwrap();
+        let clock = SimClock::starting_at(start);
+        clock.set_speed(100.0).unwrap();
+
+        let real = Instant::now();
+        clock.sleep_until(start + Duration::seconds(2)).await;
+        assert!(real.elapsed() < StdDuration::from_millis(500));
+        assert!(clock.now() >= start + Duration::seconds(2));
+
+        assert!(clock.set_speed(0.0).is_err());
+        assert!(clock.set_speed(f64::NAN).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pause_and_seek() {
+        let start = DateTime::from_timestamp(0, 0).unwrap();
+        let clock = SimClock::starting_at(start);
+        clock.pause();
+        let now = clock.now();
+        tokio::time::sleep(StdDuration::from_millis(20)).await;
+        assert_eq!(clock.now(), now);
+        assert!(!clock.status().running);
+
+        let target = start + Duration::hours(1);
+        let waiting = clock.clone();
+        let sleeper = tokio::spawn(async move { waiting.sleep_until(target).await });
+        tokio::time::sleep(StdDuration::from_millis(20)).await;
+        assert!(!sleeper.is_finished());
+
+        // Jumping past the target releases the sleeper even while paused.
+        clock.seek(target + Duration::seconds(1));
+        tokio::time::timeout(StdDuration::from_millis(200), sleeper)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
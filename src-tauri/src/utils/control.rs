@@ -0,0 +1,72 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager};
+
+use super::soe::state::{EventKind, Recorder};
+
+/// Event carrying every operator command to the frontend.
+pub const OPERATOR_COMMAND_EVENT: &str = "operator-command";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    Open,
+    Close,
+    Set { value: f64 },
+}
+
+/// Control action requested on a piece of equipment, whether it comes from
+/// the UI or from an external SCADA through one of the protocol servers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OperatorCommand {
+    pub target: String,
+    pub action: Action,
+    /// Who issued the command, e.g. `modbus:127.0.0.1:50312`.
+    pub origin: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl OperatorCommand {
+    pub fn new(target: impl Into<String>, action: Action, origin: impl Into<String>) -> Self {
+        Self {
+            target: target.into(),
+            action,
+            origin: origin.into(),
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+impl Action {
+    /// Value recorded in the sequence of events, 1 for close and 0 for open.
+    pub fn value(&self) -> f64 {
+        match self {
+            Action::Open => 0.0,
+            Action::Close => 1.0,
+            Action::Set { value } => *value,
+        }
+    }
+}
+
+pub fn dispatch(app: &AppHandle, command: &OperatorCommand) {
+    log::info!(
+        "Operator command from {}: {:?} on '{}'",
+        command.origin,
+        command.action,
+        command.target
+    );
+
+    if let Some(soe) = app.try_state::<Recorder>() {
+        soe.record(
+            &command.target,
+            EventKind::Command,
+            command.action.value(),
+            command.timestamp,
+            &command.origin,
+        );
+    }
+
+    if let Err(e) = app.emit(OPERATOR_COMMAND_EVENT, command) {
+        log::warn!("Failed to emit operator command: {}", e);
+    }
+}
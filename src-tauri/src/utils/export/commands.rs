@@ -0,0 +1,115 @@
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::utils::channels::state::Channels;
+use crate::utils::tasks::executor::{Executor, Priority};
+use crate::utils::tasks::progress::ProgressReporter;
+
+use super::error::{Error, Result};
+use super::writer::{self, Format, Layout, Series};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExportRequest {
+    pub ids: Vec<String>,
+    #[serde(default)]
+    pub from: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub to: Option<DateTime<Utc>>,
+    pub path: PathBuf,
+    pub format: Format,
+    #[serde(default)]
+    pub layout: Layout,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportSummary {
+    path: PathBuf,
+    rows: usize,
+    samples: usize,
+}
+
+/// Kind of the writing work on the executor.
+pub const WRITE_KIND: &str = "export";
+
+/// Writes the recorded history of the requested channels, reporting the rows
+/// written to `progress`.
+pub async fn export(
+    state: &Channels,
+    executor: &Executor,
+    priority: Priority,
+    request: ExportRequest,
+    progress: Option<ProgressReporter>,
+) -> Result<ExportSummary> {
+    let ExportRequest {
+        ids,
+        from,
+        to,
+        path,
+        format,
+        layout,
+    } = request;
+
+    if ids.is_empty() {
+        return Err(Error::NoChannels);
+    }
+    if matches!((from, to), (Some(from), Some(to)) if from > to) {
+        return Err(Error::InvalidRange);
+    }
+
+    let mut series = Vec::with_capacity(ids.len());
+    for id in ids {
+        let Some((unit, records)) = state.history(&id, from, to) else {
+            return Err(Error::ChannelNotFound { id });
+        };
+        series.push(Series { id, unit, records });
+    }
+    let samples = series.iter().map(|s| s.records.len()).sum();
+
+    let target = path.clone();
+    let rows = executor
+        .run(WRITE_KIND, priority, move || {
+            writer::write(&target, format, layout, &series, progress.as_ref())
+        })
+        .await??;
+
+    log::info!(
+        "Exported {} samples of {} rows to {}",
+        samples,
+        rows,
+        path.display()
+    );
+    Ok(ExportSummary {
+        path,
+        rows,
+        samples,
+    })
+}
+
+/// Writes the recorded history of channels `ids` within `from..=to` to
+/// `path`, in the long layout unless told otherwise.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn export_channels(
+    state: State<'_, Channels>,
+    executor: State<'_, Executor>,
+    ids: Vec<String>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    path: PathBuf,
+    format: Format,
+    layout: Option<Layout>,
+) -> Result<ExportSummary> {
+    let request = ExportRequest {
+        ids,
+        from,
+        to,
+        path,
+        format,
+        layout: layout.unwrap_or_default(),
+    };
+
+    export(&state, &executor, Priority::Interactive, request, None).await
+}
@@ -0,0 +1,48 @@
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::utils::tasks::error::Error as TasksError;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Executor(#[from] TasksError),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("CSV error: {0}")]
+    Csv(#[from] csv::Error),
+
+    #[error("Arrow error: {0}")]
+    Arrow(#[from] arrow::error::ArrowError),
+
+    #[error("Parquet error: {0}")]
+    Parquet(#[from] parquet::errors::ParquetError),
+
+    #[error("Channel with id '{id}' not found")]
+    ChannelNotFound { id: String },
+
+    #[error("No channel to export")]
+    NoChannels,
+
+    #[error("Invalid time range: start is after end")]
+    InvalidRange,
+}
+
+impl Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_ref())
+    }
+}
+
+impl From<Error> for String {
+    fn from(err: Error) -> Self {
+        err.to_string()
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
@@ -0,0 +1,369 @@
+//! Tabular export of channel histories for pandas and Excel.
+//!
+//! The long layout has one row per sample. The wide layout has one row per
+//! timestamp, with a value and a quality column per channel, left empty when
+//! the channel has no sample at that instant.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use arrow::array::{ArrayRef, Float64Array, StringArray, TimestampMicrosecondArray, UInt64Array};
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow::record_batch::RecordBatch;
+use chrono::{DateTime, SecondsFormat, Utc};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use serde::Deserialize;
+
+use crate::utils::channels::history::Record;
+use crate::utils::tasks::progress::ProgressReporter;
+
+use super::error::Result;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    #[default]
+    Csv,
+    Parquet,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Layout {
+    #[default]
+    Long,
+    Wide,
+}
+
+/// Recorded samples of one channel.
+pub struct Series {
+    pub id: String,
+    pub unit: Option<String>,
+    pub records: Vec<Record>,
+}
+
+/// Column of the exported table.
+enum Column {
+    Timestamp(Vec<DateTime<Utc>>),
+    Number(Vec<Option<f64>>),
+    Text(Vec<Option<String>>),
+    Sequence(Vec<u64>),
+}
+
+struct Table {
+    columns: Vec<(String, Option<String>, Column)>,
+    rows: usize,
+}
+
+/// Rows of a Parquet row group.
+const PARQUET_ROWS: usize = 8192;
+
+/// Reports `row` rows written out of `rows`.
+fn report(progress: Option<&ProgressReporter>, row: usize, rows: usize) {
+    if let Some(progress) = progress {
+        progress.items(row, rows);
+    }
+}
+
+fn long(series: &[Series]) -> Table {
+    let mut rows: Vec<(&Series, &Record)> = series
+        .iter()
+        .flat_map(|s| s.records.iter().map(move |r| (s, r)))
+        .collect();
+    rows.sort_by_key(|(_, r)| r.timestamp);
+
+    let text = |f: &dyn Fn(&Series, &Record) -> Option<String>| {
+        Column::Text(rows.iter().map(|(s, r)| f(s, r)).collect())
+    };
+    let columns = vec![
+        (
+            "timestamp".to_string(),
+            None,
+            Column::Timestamp(rows.iter().map(|(_, r)| r.timestamp).collect()),
+        ),
+        (
+            "channel".to_string(),
+            None,
+            text(&|s, _| Some(s.id.clone())),
+        ),
+        (
+            "value".to_string(),
+            None,
+            Column::Number(rows.iter().map(|(_, r)| Some(r.value)).collect()),
+        ),
+        ("unit".to_string(), None, text(&|s, _| s.unit.clone())),
+        (
+            "quality".to_string(),
+            None,
+            text(&|_, r| Some(r.quality.as_str().to_string())),
+        ),
+        (
+            "sequence".to_string(),
+            None,
+            Column::Sequence(rows.iter().map(|(_, r)| r.sequence).collect()),
+        ),
+    ];
+
+    Table {
+        columns,
+        rows: rows.len(),
+    }
+}
+
+fn wide(series: &[Series]) -> Table {
+    // Later samples of a channel win over earlier ones at the same instant.
+    let mut rows: BTreeMap<DateTime<Utc>, Vec<Option<&Record>>> = BTreeMap::new();
+    for (i, s) in series.iter().enumerate() {
+        for record in &s.records {
+            rows.entry(record.timestamp)
+                .or_insert_with(|| vec![None; series.len()])[i] = Some(record);
+        }
+    }
+
+    let mut columns = vec![(
+        "timestamp".to_string(),
+        None,
+        Column::Timestamp(rows.keys().copied().collect()),
+    )];
+    for (i, s) in series.iter().enumerate() {
+        let name = match &s.unit {
+            Some(unit) => format!("{} [{}]", s.id, unit),
+            None => s.id.clone(),
+        };
+        let values = rows.values().map(|row| row[i].map(|r| r.value)).collect();
+        let qualities = rows
+            .values()
+            .map(|row| row[i].map(|r| r.quality.as_str().to_string()))
+            .collect();
+
+        columns.push((name, s.unit.clone(), Column::Number(values)));
+        columns.push((format!("{} quality", s.id), None, Column::Text(qualities)));
+    }
+
+    Table {
+        columns,
+        rows: rows.len(),
+    }
+}
+
+fn write_csv(path: &Path, table: &Table, progress: Option<&ProgressReporter>) -> Result<()> {
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record(table.columns.iter().map(|(name, _, _)| name))?;
+
+    for row in 0..table.rows {
+        report(progress, row, table.rows);
+        writer.write_record(table.columns.iter().map(|(_, _, column)| match column {
+            Column::Timestamp(values) => values[row].to_rfc3339_opts(SecondsFormat::Micros, true),
+            Column::Number(values) => values[row].map(|v| v.to_string()).unwrap_or_default(),
+            Column::Text(values) => values[row].clone().unwrap_or_default(),
+            Column::Sequence(values) => values[row].to_string(),
+        }))?;
+    }
+    writer.flush()?;
+    report(progress, table.rows, table.rows);
+    Ok(())
+}
+
+fn write_parquet(path: &Path, table: &Table, progress: Option<&ProgressReporter>) -> Result<()> {
+    let mut fields = Vec::with_capacity(table.columns.len());
+    let mut arrays: Vec<ArrayRef> = Vec::with_capacity(table.columns.len());
+
+    for (name, unit, column) in &table.columns {
+        let (field, array): (Field, ArrayRef) = match column {
+            Column::Timestamp(values) => (
+                Field::new(
+                    name,
+                    DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+                    false,
+                ),
+                Arc::new(
+                    TimestampMicrosecondArray::from(
+                        values
+                            .iter()
+                            .map(|t| t.timestamp_micros())
+                            .collect::<Vec<_>>(),
+                    )
+                    .with_timezone("UTC"),
+                ),
+            ),
+            Column::Number(values) => (
+                Field::new(name, DataType::Float64, true),
+                Arc::new(Float64Array::from(values.clone())),
+            ),
+            Column::Text(values) => (
+                Field::new(name, DataType::Utf8, true),
+                Arc::new(StringArray::from(values.clone())),
+            ),
+            Column::Sequence(values) => (
+                Field::new(name, DataType::UInt64, false),
+                Arc::new(UInt64Array::from(values.clone())),
+            ),
+        };
+
+        let field = match unit {
+            Some(unit) => field.with_metadata(HashMap::from([("unit".to_string(), unit.clone())])),
+            None => field,
+        };
+        fields.push(field);
+        arrays.push(array);
+    }
+
+    let schema = Arc::new(Schema::new(fields));
+    let batch = RecordBatch::try_new(schema.clone(), arrays)?;
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+
+    let mut writer = ArrowWriter::try_new(File::create(path)?, schema, Some(properties))?;
+    for offset in (0..table.rows).step_by(PARQUET_ROWS) {
+        report(progress, offset, table.rows);
+        let rows = PARQUET_ROWS.min(table.rows - offset);
+        writer.write(&batch.slice(offset, rows))?;
+        writer.flush()?;
+    }
+    writer.close()?;
+    report(progress, table.rows, table.rows);
+    Ok(())
+}
+
+/// Writes `series` to `path`, reporting the rows written to `progress`.
+/// Returns the number of rows.
+pub fn write(
+    path: &Path,
+    format: Format,
+    layout: Layout,
+    series: &[Series],
+    progress: Option<&ProgressReporter>,
+) -> Result<usize> {
+    let table = match layout {
+        Layout::Long => long(series),
+        Layout::Wide => wide(series),
+    };
+
+    match format {
+        Format::Csv => write_csv(path, &table, progress)?,
+        Format::Parquet => write_parquet(path, &table, progress)?,
+    }
+    Ok(table.rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::channels::source::Quality;
+    use arrow::array::Array;
+    use chrono::Duration;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::path::PathBuf;
+
+    fn series(start: DateTime<Utc>) -> Vec<Series> {
+        let record = |sequence, ms, value, quality| Record {
+            sequence,
+            value,
+            quality,
+            timestamp: start + Duration::milliseconds(ms),
+        };
+
+        vec![
+            Series {
+                id: "S1.V".to_string(),
+                unit: Some("kV".to_string()),
+                records: vec![
+                    record(1, 0, 225.0, Quality::Good),
+                    record(2, 20, 226.0, Quality::Suspect),
+                ],
+            },
+            Series {
+                id: "S1.CB1".to_string(),
+                unit: None,
+                records: vec![record(1, 20, 1.0, Quality::Good)],
+            },
+        ]
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("argus-export-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn test_long_csv() {
+        let start = DateTime::from_timestamp(0, 0).unwrap();
+        let path = temp_path("long.csv");
+
+        let rows = write(&path, Format::Csv, Layout::Long, &series(start), None).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(rows, 3);
+        assert_eq!(
+            content.lines().collect::<Vec<_>>(),
+            vec![
+                "timestamp,channel,value,unit,quality,sequence",
+                "1970-01-01T00:00:00.000000Z,S1.V,225,kV,good,1",
+                "1970-01-01T00:00:00.020000Z,S1.V,226,kV,suspect,2",
+                "1970-01-01T00:00:00.020000Z,S1.CB1,1,,good,1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wide_csv() {
+        let start = DateTime::from_timestamp(0, 0).unwrap();
+        let path = temp_path("wide.csv");
+
+        let rows = write(&path, Format::Csv, Layout::Wide, &series(start), None).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(rows, 2);
+        assert_eq!(
+            content.lines().collect::<Vec<_>>(),
+            vec![
+                "timestamp,S1.V [kV],S1.V quality,S1.CB1,S1.CB1 quality",
+                "1970-01-01T00:00:00.000000Z,225,good,,",
+                "1970-01-01T00:00:00.020000Z,226,suspect,1,good",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parquet() {
+        let path = temp_path("wide.parquet");
+
+        let (reporter, progress) = ProgressReporter::new();
+        let rows = write(
+            &path,
+            Format::Parquet,
+            Layout::Wide,
+            &series(Utc::now()),
+            Some(&reporter),
+        )
+        .unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches: Vec<RecordBatch> = reader.map(|b| b.unwrap()).collect();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(rows, 2);
+        assert_eq!(progress.borrow().fraction, 1.0);
+        let schema = batches[0].schema();
+        assert_eq!(schema.fields().len(), 5);
+        assert_eq!(
+            schema.field(1).metadata().get("unit").map(String::as_str),
+            Some("kV")
+        );
+        let cb = batches[0]
+            .column(3)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert!(cb.is_null(0));
+        assert_eq!(cb.value(1), 1.0);
+    }
+}
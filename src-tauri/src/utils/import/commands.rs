@@ -0,0 +1,94 @@
+use std::path::PathBuf;
+
+use tauri::{ipc::Channel, State};
+
+use crate::utils::channels::source::{Recording, Source};
+use crate::utils::channels::state::{ChannelSpec, Channels};
+use crate::utils::channels::worker::Event;
+use crate::utils::tasks::executor::{Executor, Priority};
+use crate::utils::tasks::progress::ProgressReporter;
+
+use super::error::{Error, Result};
+use super::reader::{self, Import, ImportOptions, ImportReport};
+
+/// Kind of the parsing work on the executor.
+pub const READ_KIND: &str = "import";
+
+/// Parses a CSV file on the executor, reporting the rows parsed to
+/// `progress`.
+pub async fn read(
+    executor: &Executor,
+    priority: Priority,
+    path: PathBuf,
+    options: ImportOptions,
+    progress: Option<ProgressReporter>,
+) -> Result<Import> {
+    executor
+        .run(READ_KIND, priority, move || {
+            reader::read(&path, &options, progress.as_ref())
+        })
+        .await?
+}
+
+/// Parses a CSV file without importing it, to review the validation report.
+#[tauri::command]
+pub async fn inspect_csv(
+    executor: State<'_, Executor>,
+    path: PathBuf,
+    options: ImportOptions,
+) -> Result<ImportReport> {
+    Ok(read(&executor, Priority::Interactive, path, options, None)
+        .await?
+        .report)
+}
+
+/// Registers each signal of a CSV file as a channel replaying it on the
+/// simulation clock, all delivering to `channel`. `spec` configures every
+/// imported channel, its source being replaced by the file.
+#[tauri::command]
+pub async fn import_csv(
+    state: State<'_, Channels>,
+    executor: State<'_, Executor>,
+    path: PathBuf,
+    options: ImportOptions,
+    channel: Channel<Event>,
+    spec: Option<ChannelSpec>,
+) -> Result<ImportReport> {
+    let Import { signals, report } = read(
+        &executor,
+        Priority::Interactive,
+        path.clone(),
+        options,
+        None,
+    )
+    .await?;
+    if signals.is_empty() {
+        return Err(Error::NoSignals);
+    }
+
+    let template = spec.unwrap_or_default();
+    let mut registered: Vec<String> = Vec::with_capacity(signals.len());
+    for signal in signals {
+        let spec = ChannelSpec {
+            source: Source::Replay(Recording::new(signal.samples)),
+            unit: signal.unit.or(template.unit.clone()),
+            ..template.clone()
+        };
+
+        if let Err(e) = state.register(signal.id.clone(), channel.clone(), spec) {
+            log::warn!("Import of {} failed, removing its channels", path.display());
+            for id in registered {
+                state.remove(&id);
+            }
+            return Err(e.into());
+        }
+        registered.push(signal.id);
+    }
+
+    log::info!(
+        "Imported {} channels from {}",
+        registered.len(),
+        path.display()
+    );
+    Ok(report)
+}
@@ -0,0 +1,49 @@
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::utils::channels::error::Error as ChannelsError;
+use crate::utils::tasks::error::Error as TasksError;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Executor(#[from] TasksError),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("CSV error: {0}")]
+    Csv(#[from] csv::Error),
+
+    #[error("The file has no header")]
+    EmptyFile,
+
+    #[error("Missing column '{name}'")]
+    MissingColumn { name: String },
+
+    #[error("Could not detect the format of the timestamps in column '{column}'")]
+    UnknownTimestampFormat { column: String },
+
+    #[error("No signal to import")]
+    NoSignals,
+
+    #[error(transparent)]
+    Channels(#[from] ChannelsError),
+}
+
+impl Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        serializer.serialize_str(self.to_string().as_ref())
+    }
+}
+
+impl From<Error> for String {
+    fn from(err: Error) -> Self {
+        err.to_string()
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
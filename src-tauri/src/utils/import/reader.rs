@@ -0,0 +1,709 @@
+//! Parsing of measurement CSV files into signals, with a validation report
+//! of everything that was skipped on the way.
+//!
+//! Files exported by Argus read back as they were: units come from headers
+//! such as `S1.V [kV]` and qualities from the `S1.V quality` columns, or from
+//! the `unit` and `quality` columns of the long layout.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use csv::StringRecord;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::channels::source::{Quality, Sample};
+use crate::utils::export::writer::Layout;
+use crate::utils::tasks::progress::ProgressReporter;
+
+use super::error::{Error, Result};
+
+/// Issues listed in a report, the following ones are only counted.
+const MAX_ISSUES: usize = 100;
+
+/// Timestamps looked at to detect their format.
+const DETECTION_SAMPLES: usize = 20;
+
+const TIMESTAMP_HEADERS: [&str; 4] = ["timestamp", "time", "datetime", "date"];
+const ID_HEADERS: [&str; 4] = ["channel", "id", "signal", "name"];
+
+/// Naive formats tried in order, read as UTC.
+const NAIVE_PATTERNS: [&str; 4] = [
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%d/%m/%Y %H:%M:%S%.f",
+    "%Y/%m/%d %H:%M:%S%.f",
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TimestampFormat {
+    Rfc3339,
+    /// Date and time without offset, read as UTC, e.g. `%d/%m/%Y %H:%M:%S`.
+    Naive {
+        pattern: String,
+    },
+    UnixSeconds,
+    UnixMillis,
+}
+
+impl TimestampFormat {
+    pub fn parse(&self, text: &str) -> Option<DateTime<Utc>> {
+        match self {
+            TimestampFormat::Rfc3339 => DateTime::parse_from_rfc3339(text)
+                .ok()
+                .map(|t| t.with_timezone(&Utc)),
+            TimestampFormat::Naive { pattern } => NaiveDateTime::parse_from_str(text, pattern)
+                .ok()
+                .map(|t| t.and_utc()),
+            TimestampFormat::UnixSeconds => text
+                .parse::<f64>()
+                .ok()
+                .and_then(|s| DateTime::from_timestamp_micros((s * 1e6).round() as i64)),
+            TimestampFormat::UnixMillis => text
+                .parse::<f64>()
+                .ok()
+                .and_then(|ms| DateTime::from_timestamp_micros((ms * 1e3).round() as i64)),
+        }
+    }
+
+    /// Format reading most of `samples`, and more than half of them so that
+    /// a few malformed rows do not prevent the detection. Numbers beyond
+    /// 1e11 are taken as milliseconds.
+    pub fn detect(samples: &[&str]) -> Option<Self> {
+        let numbers: Vec<f64> = samples.iter().filter_map(|s| s.parse().ok()).collect();
+        let unix = if numbers.iter().any(|n| n.abs() > 1e11) {
+            TimestampFormat::UnixMillis
+        } else {
+            TimestampFormat::UnixSeconds
+        };
+
+        let mut best: Option<(usize, Self)> = None;
+        let candidates = std::iter::once(TimestampFormat::Rfc3339)
+            .chain(NAIVE_PATTERNS.iter().map(|pattern| TimestampFormat::Naive {
+                pattern: pattern.to_string(),
+            }))
+            .chain(std::iter::once(unix));
+        for format in candidates {
+            let parsed = samples.iter().filter(|s| format.parse(s).is_some()).count();
+            if 2 * parsed > samples.len() && best.as_ref().is_none_or(|(n, _)| parsed > *n) {
+                best = Some((parsed, format));
+            }
+        }
+        best.map(|(_, format)| format)
+    }
+}
+
+/// Where a column or an id of the file goes.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ColumnMapping {
+    #[serde(default)]
+    pub channel: Option<String>,
+    #[serde(default)]
+    pub unit: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ImportOptions {
+    #[serde(default)]
+    pub layout: Layout,
+    /// Detected from the usual names, the first column otherwise.
+    #[serde(default)]
+    pub timestamp_column: Option<String>,
+    /// Detected when not set.
+    #[serde(default)]
+    pub timestamp_format: Option<TimestampFormat>,
+    /// Column of the channel ids in the long layout.
+    #[serde(default)]
+    pub id_column: Option<String>,
+    /// Column of the values in the long layout, `value` by default.
+    #[serde(default)]
+    pub value_column: Option<String>,
+    /// Signals to import by header (wide) or id (long), all when empty.
+    #[serde(default)]
+    pub columns: HashMap<String, ColumnMapping>,
+    /// Units by channel id, over those found in the file.
+    #[serde(default)]
+    pub units: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Issue {
+    pub line: u64,
+    pub column: Option<String>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SignalSummary {
+    pub id: String,
+    pub unit: Option<String>,
+    pub samples: usize,
+    pub first: Option<DateTime<Utc>>,
+    pub last: Option<DateTime<Utc>>,
+    /// Samples older than the one before them, replayed in time order.
+    pub out_of_order: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportReport {
+    pub delimiter: char,
+    pub timestamp_format: TimestampFormat,
+    pub rows: usize,
+    pub skipped_rows: usize,
+    pub invalid_cells: usize,
+    pub signals: Vec<SignalSummary>,
+    pub issues: Vec<Issue>,
+    /// Whether issues beyond `MAX_ISSUES` were left out.
+    pub truncated: bool,
+}
+
+pub struct Signal {
+    pub id: String,
+    pub unit: Option<String>,
+    pub samples: Vec<Sample>,
+}
+
+pub struct Import {
+    pub signals: Vec<Signal>,
+    pub report: ImportReport,
+}
+
+/// Signals in the order they first appear in the file, with the issues met.
+#[derive(Default)]
+struct Collector {
+    signals: Vec<Signal>,
+    index: HashMap<String, usize>,
+    issues: Vec<Issue>,
+    skipped_rows: usize,
+    invalid_cells: usize,
+    truncated: bool,
+}
+
+impl Collector {
+    fn issue(&mut self, line: u64, column: Option<&str>, message: String) {
+        if self.issues.len() < MAX_ISSUES {
+            self.issues.push(Issue {
+                line,
+                column: column.map(String::from),
+                message,
+            });
+        } else {
+            self.truncated = true;
+        }
+    }
+
+    fn signal(&mut self, id: &str, unit: Option<&str>) -> &mut Signal {
+        let i = *self.index.entry(id.to_string()).or_insert_with(|| {
+            self.signals.push(Signal {
+                id: id.to_string(),
+                unit: None,
+                samples: Vec::new(),
+            });
+            self.signals.len() - 1
+        });
+
+        let signal = &mut self.signals[i];
+        if signal.unit.is_none() {
+            signal.unit = unit.map(String::from);
+        }
+        signal
+    }
+}
+
+fn detect_delimiter(header: &str) -> u8 {
+    [b',', b';', b'\t']
+        .into_iter()
+        .max_by_key(|d| header.bytes().filter(|b| b == d).count())
+        .filter(|d| header.as_bytes().contains(d))
+        .unwrap_or(b',')
+}
+
+/// Splits `S1.V [kV]` into the id and the unit.
+fn split_header(header: &str) -> (&str, Option<&str>) {
+    match header.strip_suffix(']').and_then(|h| h.rsplit_once(" [")) {
+        Some((id, unit)) => (id, Some(unit)),
+        None => (header, None),
+    }
+}
+
+fn parse_quality(text: &str) -> Option<Quality> {
+    match text.to_ascii_lowercase().as_str() {
+        "" | "good" => Some(Quality::Good),
+        "suspect" => Some(Quality::Suspect),
+        "invalid" => Some(Quality::Invalid),
+        _ => None,
+    }
+}
+
+fn find_column(headers: &StringRecord, name: &str) -> Option<usize> {
+    headers.iter().position(|h| h.eq_ignore_ascii_case(name))
+}
+
+/// Column named `explicit`, or the first with one of the usual `names`.
+fn column(headers: &StringRecord, explicit: Option<&str>, names: &[&str]) -> Result<Option<usize>> {
+    match explicit {
+        Some(name) => find_column(headers, name)
+            .map(Some)
+            .ok_or_else(|| Error::MissingColumn {
+                name: name.to_string(),
+            }),
+        None => Ok(names.iter().find_map(|name| find_column(headers, name))),
+    }
+}
+
+struct Parser<'a> {
+    options: &'a ImportOptions,
+    headers: StringRecord,
+    timestamp: usize,
+    format: TimestampFormat,
+    /// Decimal comma, as written by Excel when fields are not comma separated.
+    decimal_comma: bool,
+    collector: Collector,
+    progress: Option<&'a ProgressReporter>,
+}
+
+impl Parser<'_> {
+    /// Reports `row` rows parsed out of `rows`.
+    fn report(&self, row: usize, rows: usize) {
+        if let Some(progress) = self.progress {
+            progress.items(row, rows);
+        }
+    }
+
+    fn line(record: &StringRecord) -> u64 {
+        record.position().map(|p| p.line()).unwrap_or_default()
+    }
+
+    /// Timestamp of a well-formed row, `None` after reporting why it is skipped.
+    fn row(&mut self, record: &StringRecord) -> Option<DateTime<Utc>> {
+        let line = Self::line(record);
+        if record.len() != self.headers.len() {
+            self.collector.issue(
+                line,
+                None,
+                format!(
+                    "expected {} fields, found {}",
+                    self.headers.len(),
+                    record.len()
+                ),
+            );
+            self.collector.skipped_rows += 1;
+            return None;
+        }
+
+        let text = &record[self.timestamp];
+        let timestamp = self.format.parse(text);
+        if timestamp.is_none() {
+            let column = self.headers[self.timestamp].to_string();
+            self.collector
+                .issue(line, Some(&column), format!("invalid timestamp '{}'", text));
+            self.collector.skipped_rows += 1;
+        }
+        timestamp
+    }
+
+    /// Value of a cell, `None` when empty or after reporting it as invalid.
+    fn value(&mut self, record: &StringRecord, column: usize) -> Option<f64> {
+        let text = &record[column];
+        if text.is_empty() {
+            return None;
+        }
+
+        let parsed = if self.decimal_comma {
+            text.replace(',', ".").parse()
+        } else {
+            text.parse()
+        };
+        match parsed {
+            Ok(value) => Some(value),
+            Err(_) => {
+                let header = self.headers[column].to_string();
+                self.collector.issue(
+                    Self::line(record),
+                    Some(&header),
+                    format!("invalid value '{}'", text),
+                );
+                self.collector.invalid_cells += 1;
+                None
+            }
+        }
+    }
+
+    fn quality(&mut self, record: &StringRecord, column: Option<usize>) -> Quality {
+        let Some(column) = column else {
+            return Quality::Good;
+        };
+
+        let text = &record[column];
+        parse_quality(text).unwrap_or_else(|| {
+            let header = self.headers[column].to_string();
+            self.collector.issue(
+                Self::line(record),
+                Some(&header),
+                format!("unknown quality '{}', read as good", text),
+            );
+            Quality::Good
+        })
+    }
+
+    fn wide(&mut self, records: &[StringRecord]) {
+        // Column, channel id, unit and quality column of each imported signal.
+        let mut plan = Vec::new();
+        for (i, header) in self.headers.iter().enumerate() {
+            if i == self.timestamp || header.ends_with(" quality") {
+                continue;
+            }
+
+            let (id, unit) = split_header(header);
+            let (id, unit) = if self.options.columns.is_empty() {
+                (id.to_string(), unit.map(String::from))
+            } else {
+                let Some(mapping) = self.options.columns.get(header) else {
+                    continue;
+                };
+                (
+                    mapping.channel.clone().unwrap_or_else(|| id.to_string()),
+                    mapping.unit.clone().or(unit.map(String::from)),
+                )
+            };
+            let quality = find_column(
+                &self.headers,
+                &format!("{} quality", split_header(header).0),
+            );
+            plan.push((i, id, unit, quality));
+        }
+
+        for (row, record) in records.iter().enumerate() {
+            self.report(row, records.len());
+            let Some(timestamp) = self.row(record) else {
+                continue;
+            };
+
+            for (column, id, unit, quality) in &plan {
+                let Some(value) = self.value(record, *column) else {
+                    continue;
+                };
+                let quality = self.quality(record, *quality);
+                self.collector
+                    .signal(id, unit.as_deref())
+                    .samples
+                    .push(Sample {
+                        value,
+                        quality,
+                        timestamp,
+                    });
+            }
+        }
+    }
+
+    fn long(&mut self, records: &[StringRecord]) -> Result<()> {
+        let id = column(
+            &self.headers,
+            self.options.id_column.as_deref(),
+            &ID_HEADERS,
+        )?
+        .ok_or_else(|| Error::MissingColumn {
+            name: "channel".to_string(),
+        })?;
+        let value = column(
+            &self.headers,
+            Some(self.options.value_column.as_deref().unwrap_or("value")),
+            &[],
+        )?
+        .unwrap_or_default();
+        let unit = find_column(&self.headers, "unit");
+        let quality = find_column(&self.headers, "quality");
+
+        for (row, record) in records.iter().enumerate() {
+            self.report(row, records.len());
+            let Some(timestamp) = self.row(record) else {
+                continue;
+            };
+
+            let (channel, mapped_unit) = if self.options.columns.is_empty() {
+                (record[id].to_string(), None)
+            } else {
+                let Some(mapping) = self.options.columns.get(&record[id]) else {
+                    continue;
+                };
+                (
+                    mapping
+                        .channel
+                        .clone()
+                        .unwrap_or_else(|| record[id].to_string()),
+                    mapping.unit.clone(),
+                )
+            };
+            let Some(value) = self.value(record, value) else {
+                continue;
+            };
+            let quality = self.quality(record, quality);
+            let unit = mapped_unit.or(unit
+                .map(|u| record[u].to_string())
+                .filter(|u| !u.is_empty()));
+
+            self.collector
+                .signal(&channel, unit.as_deref())
+                .samples
+                .push(Sample {
+                    value,
+                    quality,
+                    timestamp,
+                });
+        }
+        Ok(())
+    }
+}
+
+/// Parses the file at `path`, reporting the rows parsed to `progress`.
+pub fn read(
+    path: &Path,
+    options: &ImportOptions,
+    progress: Option<&ProgressReporter>,
+) -> Result<Import> {
+    parse(&std::fs::read_to_string(path)?, options, progress)
+}
+
+pub fn parse(
+    content: &str,
+    options: &ImportOptions,
+    progress: Option<&ProgressReporter>,
+) -> Result<Import> {
+    let header = content.lines().next().ok_or(Error::EmptyFile)?;
+    let delimiter = detect_delimiter(header);
+
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(content.as_bytes());
+    let headers = reader.headers()?.clone();
+    let records = reader
+        .records()
+        .collect::<core::result::Result<Vec<_>, _>>()?;
+
+    let timestamp = column(
+        &headers,
+        options.timestamp_column.as_deref(),
+        &TIMESTAMP_HEADERS,
+    )?
+    .unwrap_or_default();
+    let format = match &options.timestamp_format {
+        Some(format) => format.clone(),
+        None => {
+            let samples: Vec<&str> = records
+                .iter()
+                .filter_map(|r| r.get(timestamp))
+                .filter(|t| !t.is_empty())
+                .take(DETECTION_SAMPLES)
+                .collect();
+            TimestampFormat::detect(&samples).ok_or_else(|| Error::UnknownTimestampFormat {
+                column: headers.get(timestamp).unwrap_or_default().to_string(),
+            })?
+        }
+    };
+
+    let mut parser = Parser {
+        options,
+        headers,
+        timestamp,
+        format,
+        decimal_comma: delimiter != b',',
+        collector: Collector::default(),
+        progress,
+    };
+    match options.layout {
+        Layout::Wide => parser.wide(&records),
+        Layout::Long => parser.long(&records)?,
+    }
+    parser.report(records.len(), records.len());
+
+    let Parser {
+        format, collector, ..
+    } = parser;
+    let mut signals = collector.signals;
+    for signal in &mut signals {
+        if let Some(unit) = options.units.get(&signal.id) {
+            signal.unit = Some(unit.clone());
+        }
+    }
+
+    let summaries = signals
+        .iter()
+        .map(|s| SignalSummary {
+            id: s.id.clone(),
+            unit: s.unit.clone(),
+            samples: s.samples.len(),
+            first: s.samples.iter().map(|s| s.timestamp).min(),
+            last: s.samples.iter().map(|s| s.timestamp).max(),
+            out_of_order: s
+                .samples
+                .windows(2)
+                .filter(|w| w[1].timestamp < w[0].timestamp)
+                .count(),
+        })
+        .collect();
+
+    Ok(Import {
+        signals,
+        report: ImportReport {
+            delimiter: delimiter as char,
+            timestamp_format: format,
+            rows: records.len(),
+            skipped_rows: collector.skipped_rows,
+            invalid_cells: collector.invalid_cells,
+            signals: summaries,
+            issues: collector.issues,
+            truncated: collector.truncated,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(signal: &Signal) -> Vec<f64> {
+        signal.samples.iter().map(|s| s.value).collect()
+    }
+
+    #[test]
+    fn test_detect_timestamp_format() {
+        assert_eq!(
+            TimestampFormat::detect(&["2024-01-01T00:00:00.020Z"]),
+            Some(TimestampFormat::Rfc3339)
+        );
+        assert_eq!(
+            TimestampFormat::detect(&["31/01/2024 10:00:00"]),
+            Some(TimestampFormat::Naive {
+                pattern: "%d/%m/%Y %H:%M:%S%.f".to_string()
+            })
+        );
+        assert_eq!(
+            TimestampFormat::detect(&["1700000000.5"]),
+            Some(TimestampFormat::UnixSeconds)
+        );
+        assert_eq!(
+            TimestampFormat::detect(&["1700000000500"]),
+            Some(TimestampFormat::UnixMillis)
+        );
+        assert_eq!(TimestampFormat::detect(&["yesterday"]), None);
+    }
+
+    #[test]
+    fn test_wide_export_round_trip() {
+        let content = "\
+timestamp,S1.V [kV],S1.V quality,S1.CB1,S1.CB1 quality
+1970-01-01T00:00:00.000000Z,225,good,,
+1970-01-01T00:00:00.020000Z,226,suspect,1,good
+";
+        let import = parse(
+            content,
+            &ImportOptions {
+                layout: Layout::Wide,
+                ..Default::default()
+            },
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(import.signals.len(), 2);
+        assert_eq!(import.signals[0].id, "S1.V");
+        assert_eq!(import.signals[0].unit.as_deref(), Some("kV"));
+        assert_eq!(values(&import.signals[0]), vec![225.0, 226.0]);
+        assert_eq!(import.signals[0].samples[1].quality, Quality::Suspect);
+        assert_eq!(values(&import.signals[1]), vec![1.0]);
+        assert!(import.report.issues.is_empty());
+    }
+
+    #[test]
+    fn test_progress() {
+        let (reporter, progress) = ProgressReporter::new();
+        let content = "\
+timestamp,channel,value
+2024-01-01T00:00:00Z,P,1
+2024-01-01T00:00:01Z,P,2
+";
+        parse(content, &ImportOptions::default(), Some(&reporter)).unwrap();
+        assert_eq!(progress.borrow().fraction, 1.0);
+    }
+
+    #[test]
+    fn test_long_layout_and_mapping() {
+        let content = "\
+time,signal,value,unit
+1700000000,a,1.5,MW
+1700000001,b,2.5,
+1700000002,a,3.5,MW
+";
+        let options = ImportOptions {
+            layout: Layout::Long,
+            columns: HashMap::from([(
+                "a".to_string(),
+                ColumnMapping {
+                    channel: Some("S1.P".to_string()),
+                    unit: None,
+                },
+            )]),
+            ..Default::default()
+        };
+        let import = parse(content, &options, None).unwrap();
+
+        assert_eq!(import.report.timestamp_format, TimestampFormat::UnixSeconds);
+        assert_eq!(import.signals.len(), 1);
+        assert_eq!(import.signals[0].id, "S1.P");
+        assert_eq!(import.signals[0].unit.as_deref(), Some("MW"));
+        assert_eq!(values(&import.signals[0]), vec![1.5, 3.5]);
+    }
+
+    #[test]
+    fn test_validation_report() {
+        let content = "\
+Date;P;Q
+01/02/2024 10:00:00;1,5;2
+01/02/2024 10:00:01;abc;3
+not a date;4;5
+01/02/2024 10:00:03;6
+01/02/2024 09:59:59;7;8
+";
+        let options = ImportOptions {
+            layout: Layout::Wide,
+            units: HashMap::from([("P".to_string(), "MW".to_string())]),
+            ..Default::default()
+        };
+        let import = parse(content, &options, None).unwrap();
+        let report = &import.report;
+
+        assert_eq!(report.delimiter, ';');
+        assert_eq!(report.rows, 5);
+        assert_eq!(report.skipped_rows, 2);
+        assert_eq!(report.invalid_cells, 1);
+        assert_eq!(
+            report.issues.iter().map(|i| i.line).collect::<Vec<_>>(),
+            vec![3, 4, 5]
+        );
+        assert_eq!(report.issues[0].column.as_deref(), Some("P"));
+
+        assert_eq!(values(&import.signals[0]), vec![1.5, 7.0]);
+        assert_eq!(report.signals[0].unit.as_deref(), Some("MW"));
+        assert_eq!(report.signals[1].out_of_order, 1);
+    }
+
+    #[test]
+    fn test_missing_columns() {
+        let long = ImportOptions {
+            layout: Layout::Long,
+            ..Default::default()
+        };
+        assert!(matches!(
+            parse("timestamp,value\n0,1\n", &long, None),
+            Err(Error::MissingColumn { .. })
+        ));
+        assert!(matches!(parse("", &long, None), Err(Error::EmptyFile)));
+        assert!(matches!(
+            parse("timestamp,P\nnever,1\n", &ImportOptions::default(), None),
+            Err(Error::UnknownTimestampFormat { .. })
+        ));
+    }
+}
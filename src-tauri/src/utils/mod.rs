@@ -1,2 +1,7 @@
 pub mod channels;
+pub mod clock;
+pub mod control;
+pub mod export;
+pub mod import;
+pub mod soe;
 pub mod tasks;
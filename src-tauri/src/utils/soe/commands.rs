@@ -0,0 +1,16 @@
+use tauri::State;
+
+use super::error::Result;
+use super::state::{Query, Recorder, SoeEvent};
+
+/// Recorded events matching `query`, in the order they happened.
+#[tauri::command]
+pub async fn query_soe(state: State<'_, Recorder>, query: Query) -> Result<Vec<SoeEvent>> {
+    state.query(&query)
+}
+
+#[tauri::command]
+pub async fn clear_soe(state: State<'_, Recorder>) -> Result<()> {
+    state.clear();
+    Ok(())
+}
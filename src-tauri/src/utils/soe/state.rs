@@ -0,0 +1,227 @@
+//! Sequence-of-events recorder for the discrete state changes of the grid.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, SubsecRound, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::channels::selector::Pattern;
+
+use super::error::{Error, Result};
+
+/// Events kept before the oldest ones are discarded.
+pub const CAPACITY: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// Breaker or switch position.
+    Position,
+    Alarm,
+    /// Protection trip.
+    Trip,
+    /// Operator command, see `control::dispatch`.
+    Command,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SoeEvent {
+    /// Strictly increasing over the whole recorder.
+    pub sequence: u64,
+    /// Time of the change at the source, with millisecond resolution.
+    pub timestamp: DateTime<Utc>,
+    pub equipment: String,
+    pub kind: EventKind,
+    pub value: f64,
+    /// Who caused the change, e.g. `channel` or `modbus:127.0.0.1:50312`.
+    pub origin: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Query {
+    #[serde(default)]
+    pub from: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub to: Option<DateTime<Utc>>,
+    /// Glob on the equipment, e.g. `S1.CB*`.
+    #[serde(default)]
+    pub equipment: Option<String>,
+    /// Only the events recorded after this sequence number.
+    #[serde(default)]
+    pub after: Option<u64>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+struct Log {
+    events: VecDeque<SoeEvent>,
+    next_sequence: u64,
+    capacity: usize,
+}
+
+/// Bounded, cheaply cloned log of discrete events ordered by recording.
+#[derive(Clone)]
+pub struct Recorder {
+    log: Arc<Mutex<Log>>,
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self::new(CAPACITY)
+    }
+}
+
+impl Recorder {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            log: Arc::new(Mutex::new(Log {
+                events: VecDeque::new(),
+                next_sequence: 1,
+                capacity,
+            })),
+        }
+    }
+
+    /// Appends a change and returns its sequence number.
+    pub fn record(
+        &self,
+        equipment: &str,
+        kind: EventKind,
+        value: f64,
+        timestamp: DateTime<Utc>,
+        origin: &str,
+    ) -> u64 {
+        let mut log = self.log.lock().unwrap();
+        let sequence = log.next_sequence;
+        log.next_sequence += 1;
+
+        if log.events.len() == log.capacity {
+            log.events.pop_front();
+        }
+        log.events.push_back(SoeEvent {
+            sequence,
+            timestamp: timestamp.trunc_subsecs(3),
+            equipment: equipment.to_string(),
+            kind,
+            value,
+            origin: origin.to_string(),
+        });
+        sequence
+    }
+
+    /// Events matching `query` in recording order.
+    pub fn query(&self, query: &Query) -> Result<Vec<SoeEvent>> {
+        let equipment = match &query.equipment {
+            Some(pattern) => {
+                Some(Pattern::new(pattern).map_err(|e| Error::InvalidQuery(e.to_string()))?)
+            }
+            None => None,
+        };
+
+        let log = self.log.lock().unwrap();
+        Ok(log
+            .events
+            .iter()
+            .filter(|e| query.after.is_none_or(|after| e.sequence > after))
+            .filter(|e| query.from.is_none_or(|from| e.timestamp >= from))
+            .filter(|e| query.to.is_none_or(|to| e.timestamp <= to))
+            .filter(|e| equipment.as_ref().is_none_or(|p| p.is_match(&e.equipment)))
+            .take(query.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect())
+    }
+
+    pub fn clear(&self) {
+        self.log.lock().unwrap().events.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_record_and_query() {
+        let recorder = Recorder::default();
+        let start = Utc::now();
+        let at = |ms| start + Duration::milliseconds(ms);
+
+        recorder.record("S1.CB1", EventKind::Position, 0.0, at(0), "channel");
+        recorder.record("S1.PROT", EventKind::Trip, 1.0, at(2), "channel");
+        recorder.record("S2.CB1", EventKind::Position, 0.0, at(3), "channel");
+        recorder.record("S1.CB1", EventKind::Command, 1.0, at(500), "ui");
+
+        let s1 = recorder
+            .query(&Query {
+                equipment: Some("S1.*".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(
+            s1.iter().map(|e| e.sequence).collect::<Vec<_>>(),
+            vec![1, 2, 4]
+        );
+
+        let window = recorder
+            .query(&Query {
+                from: Some(at(1)),
+                to: Some(at(3)),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(
+            window
+                .iter()
+                .map(|e| e.equipment.as_str())
+                .collect::<Vec<_>>(),
+            vec!["S1.PROT", "S2.CB1"]
+        );
+
+        let next = recorder
+            .query(&Query {
+                after: Some(2),
+                limit: Some(1),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(next[0].sequence, 3);
+    }
+
+    #[test]
+    fn test_millisecond_timestamps() {
+        let recorder = Recorder::default();
+        let timestamp = DateTime::from_timestamp(0, 1_234_567).unwrap();
+        recorder.record("CB", EventKind::Alarm, 1.0, timestamp, "channel");
+
+        let events = recorder.query(&Query::default()).unwrap();
+        assert_eq!(events[0].timestamp.timestamp_subsec_nanos(), 1_000_000);
+    }
+
+    #[test]
+    fn test_capacity_keeps_sequence() {
+        let recorder = Recorder::new(2);
+        for value in 0..3 {
+            recorder.record(
+                "CB",
+                EventKind::Position,
+                value as f64,
+                Utc::now(),
+                "channel",
+            );
+        }
+
+        let events = recorder.query(&Query::default()).unwrap();
+        assert_eq!(
+            events.iter().map(|e| e.sequence).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert!(recorder
+            .query(&Query {
+                equipment: Some("[".to_string()),
+                ..Default::default()
+            })
+            .is_err());
+    }
+}
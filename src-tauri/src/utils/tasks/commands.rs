@@ -0,0 +1,355 @@
+use serde_json::Value;
+use tauri::{ipc::Channel, AppHandle, State};
+use tokio::sync::Mutex;
+use tokio::time::Duration;
+
+use crate::settings::database::state::{DatabaseState, JobPage, JobQuery, StoredJob};
+
+use super::error::{Error, Result};
+use super::executor::{Executor, ExecutorStatus};
+use super::jobs::JobSpec;
+use super::progress::{ProgressReporter, TaskContext};
+use super::scheduler::{ScheduleInfo, ScheduleSpec, Scheduler};
+use super::state::{JobInfo, Tasks};
+use super::workflow::{self, Plan, StepInfo, Steps, WorkflowSpec, Workflows};
+use super::CancelReason;
+
+/// Queues a job described by `spec`, e.g. `{"type": "inspect_csv", ...}`,
+/// and returns its id. Status and progress changes are sent to `updates`.
+/// The job is cancelled if not done within `timeout_ms`, and the job
+/// `replaces`, if still active, is cancelled as superseded.
+#[tauri::command]
+pub async fn submit_job(
+    app: AppHandle,
+    state: State<'_, Tasks>,
+    name: String,
+    spec: Value,
+    updates: Option<Channel<JobInfo>>,
+    timeout_ms: Option<u64>,
+    replaces: Option<String>,
+) -> Result<String> {
+    let job: JobSpec =
+        serde_json::from_value(spec.clone()).map_err(|e| Error::InvalidJob(e.to_string()))?;
+    if let Some(old) = &replaces {
+        match state.cancel_with(old, CancelReason::Superseded) {
+            Ok(()) | Err(Error::JobNotActive { .. }) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    let id = submit(app, &state, name, spec, job, updates);
+    if let Some(timeout) = timeout_ms {
+        state.set_deadline(&id, Duration::from_millis(timeout))?;
+    }
+    Ok(id)
+}
+
+fn submit(
+    app: AppHandle,
+    tasks: &Tasks,
+    name: String,
+    spec: Value,
+    job: JobSpec,
+    updates: Option<Channel<JobInfo>>,
+) -> String {
+    let id = tasks.submit(name.clone(), spec, updates, move |context| {
+        job.run(app, context)
+    });
+    log::info!("Submitted job '{}' as {}", name, id);
+    id
+}
+
+#[tauri::command]
+pub async fn list_jobs(state: State<'_, Tasks>) -> Result<Vec<JobInfo>> {
+    Ok(state.list())
+}
+
+#[tauri::command]
+pub async fn get_job(state: State<'_, Tasks>, id: String) -> Result<JobInfo> {
+    state.info(&id)
+}
+
+#[tauri::command]
+pub async fn cancel_job(state: State<'_, Tasks>, id: String) -> Result<()> {
+    state.cancel(&id)
+}
+
+#[tauri::command]
+pub async fn get_job_result(state: State<'_, Tasks>, id: String) -> Result<Value> {
+    state.result(&id)
+}
+
+/// Page of the stored jobs matching `query`, newest first.
+#[tauri::command]
+pub async fn list_job_history(
+    db: State<'_, Mutex<DatabaseState>>,
+    query: Option<JobQuery>,
+) -> Result<JobPage> {
+    Ok(db
+        .lock()
+        .await
+        .list_jobs(&query.unwrap_or_default())
+        .await?)
+}
+
+#[tauri::command]
+pub async fn get_job_history(db: State<'_, Mutex<DatabaseState>>, id: String) -> Result<StoredJob> {
+    db.lock()
+        .await
+        .get_job(&id)
+        .await?
+        .ok_or(Error::JobNotFound { id })
+}
+
+/// Submits again the job or workflow `id`, still in memory or stored, with
+/// the same name and parameters. Returns the id of the new job.
+#[tauri::command]
+pub async fn rerun_job(
+    app: AppHandle,
+    state: State<'_, Tasks>,
+    workflows: State<'_, Workflows>,
+    db: State<'_, Mutex<DatabaseState>>,
+    id: String,
+    updates: Option<Channel<JobInfo>>,
+    step_updates: Option<Channel<StepInfo>>,
+) -> Result<String> {
+    let (name, params) = match state.info(&id) {
+        Ok(info) => (info.name, info.params),
+        Err(_) => {
+            let job = db.lock().await.get_job(&id).await?;
+            let job = job.ok_or(Error::JobNotFound { id: id.clone() })?;
+            (job.name, job.params)
+        }
+    };
+
+    if let Ok(spec) = serde_json::from_value::<WorkflowSpec>(params.clone()) {
+        return start_workflow(app, &state, &workflows, spec, updates, step_updates);
+    }
+    let job: JobSpec =
+        serde_json::from_value(params.clone()).map_err(|e| Error::InvalidJob(e.to_string()))?;
+    log::info!("Running job {} again", id);
+    Ok(submit(app, &state, name, params, job, updates))
+}
+
+/// Forgets a job that is no longer queued or running, along with the steps
+/// of the workflow it ran.
+#[tauri::command]
+pub async fn remove_job(
+    state: State<'_, Tasks>,
+    workflows: State<'_, Workflows>,
+    id: String,
+) -> Result<JobInfo> {
+    let info = state.remove(&id)?;
+    workflows.remove(&id);
+    Ok(info)
+}
+
+#[tauri::command]
+pub async fn get_executor_status(executor: State<'_, Executor>) -> Result<ExecutorStatus> {
+    Ok(executor.status())
+}
+
+/// Allows at most `max` works of `kind`, e.g. `import` or `export`, to run
+/// at once on the executor. No `max` lifts the limit.
+#[tauri::command]
+pub async fn set_executor_limit(
+    executor: State<'_, Executor>,
+    kind: String,
+    max: Option<usize>,
+) -> Result<()> {
+    executor.set_limit(&kind, max);
+    Ok(())
+}
+
+/// Adds a schedule submitting `spec.job` on every fire and stores it in the
+/// settings database.
+#[tauri::command]
+pub async fn create_schedule(
+    state: State<'_, Scheduler>,
+    db: State<'_, Mutex<DatabaseState>>,
+    id: String,
+    spec: ScheduleSpec,
+) -> Result<ScheduleInfo> {
+    if let Err(e) = serde_json::from_value::<JobSpec>(spec.job.clone()) {
+        let reason = format!("invalid job: {}", e);
+        return Err(Error::InvalidSchedule { id, reason });
+    }
+
+    let created_at = state.now(spec.clock);
+    state.add(id.clone(), spec.clone(), created_at, None)?;
+    if let Err(e) = db
+        .lock()
+        .await
+        .insert_schedule(&id, &spec, created_at)
+        .await
+    {
+        let _ = state.remove(&id);
+        return Err(e.into());
+    }
+    log::info!("Created schedule '{}'", id);
+
+    state.info(&id)
+}
+
+#[tauri::command]
+pub async fn list_schedules(state: State<'_, Scheduler>) -> Result<Vec<ScheduleInfo>> {
+    Ok(state.list())
+}
+
+#[tauri::command]
+pub async fn get_schedule(state: State<'_, Scheduler>, id: String) -> Result<ScheduleInfo> {
+    state.info(&id)
+}
+
+/// Pauses or resumes a schedule, a resumed schedule does not catch up on the
+/// fires it missed while disabled, also after a restart.
+#[tauri::command]
+pub async fn set_schedule_enabled(
+    state: State<'_, Scheduler>,
+    db: State<'_, Mutex<DatabaseState>>,
+    id: String,
+    enabled: bool,
+) -> Result<ScheduleInfo> {
+    let resumed = enabled && !state.info(&id)?.spec.enabled;
+    let info = state.set_enabled(&id, enabled)?;
+
+    let db = db.lock().await;
+    db.save_schedule(&id, &info.spec).await?;
+    if resumed {
+        db.set_schedule_resumed(&id, state.now(info.spec.clock))
+            .await?;
+    }
+
+    Ok(info)
+}
+
+#[tauri::command]
+pub async fn delete_schedule(
+    state: State<'_, Scheduler>,
+    db: State<'_, Mutex<DatabaseState>>,
+    id: String,
+) -> Result<ScheduleInfo> {
+    let info = state.remove(&id)?;
+    db.lock().await.delete_schedule(&id).await?;
+    log::info!("Deleted schedule '{}'", id);
+
+    Ok(info)
+}
+
+fn start_workflow(
+    app: AppHandle,
+    tasks: &Tasks,
+    workflows: &Workflows,
+    spec: WorkflowSpec,
+    updates: Option<Channel<JobInfo>>,
+    step_updates: Option<Channel<StepInfo>>,
+) -> Result<String> {
+    let plan = Plan::new(&spec)?;
+    let steps = Steps::new(&plan, step_updates);
+    let params = serde_json::to_value(&spec).map_err(|e| Error::InvalidWorkflow(e.to_string()))?;
+
+    let run_steps = steps.clone();
+    let id = tasks.submit(
+        spec.name.clone(),
+        params,
+        updates,
+        move |context| async move {
+            let token = context.token.clone();
+            let run = |job: Value| {
+                let app = app.clone();
+                let token = token.child();
+                async move {
+                    let job: JobSpec =
+                        serde_json::from_value(job).map_err(|e| format!("invalid job: {}", e))?;
+                    let (progress, _) = ProgressReporter::new();
+                    job.run(app, TaskContext { token, progress }).await
+                }
+            };
+            workflow::execute(&plan, &run_steps, context, run).await
+        },
+    );
+    workflows.insert(id.clone(), steps);
+    log::info!("Started workflow '{}' as {}", spec.name, id);
+
+    Ok(id)
+}
+
+/// Runs a workflow as one job and returns its id, the job result being the
+/// outputs of the steps by step id. Step changes are sent to `step_updates`.
+#[tauri::command]
+pub async fn run_workflow(
+    app: AppHandle,
+    state: State<'_, Tasks>,
+    workflows: State<'_, Workflows>,
+    spec: WorkflowSpec,
+    updates: Option<Channel<JobInfo>>,
+    step_updates: Option<Channel<StepInfo>>,
+) -> Result<String> {
+    start_workflow(app, &state, &workflows, spec, updates, step_updates)
+}
+
+#[tauri::command]
+pub async fn run_saved_workflow(
+    app: AppHandle,
+    state: State<'_, Tasks>,
+    workflows: State<'_, Workflows>,
+    db: State<'_, Mutex<DatabaseState>>,
+    name: String,
+    updates: Option<Channel<JobInfo>>,
+    step_updates: Option<Channel<StepInfo>>,
+) -> Result<String> {
+    let spec = db
+        .lock()
+        .await
+        .get_workflow::<WorkflowSpec>(&name)
+        .await?
+        .ok_or(Error::WorkflowNotFound { name })?;
+
+    start_workflow(app, &state, &workflows, spec, updates, step_updates)
+}
+
+#[tauri::command]
+pub async fn get_workflow_steps(
+    workflows: State<'_, Workflows>,
+    id: String,
+) -> Result<Vec<StepInfo>> {
+    workflows.steps(&id)
+}
+
+/// Validates a workflow and stores it under its name, replacing any previous
+/// definition.
+#[tauri::command]
+pub async fn save_workflow(db: State<'_, Mutex<DatabaseState>>, spec: WorkflowSpec) -> Result<()> {
+    Plan::new(&spec)?;
+    db.lock().await.save_workflow(&spec.name, &spec).await?;
+    log::info!("Saved workflow '{}'", spec.name);
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_workflow(
+    db: State<'_, Mutex<DatabaseState>>,
+    name: String,
+) -> Result<WorkflowSpec> {
+    db.lock()
+        .await
+        .get_workflow(&name)
+        .await?
+        .ok_or(Error::WorkflowNotFound { name })
+}
+
+#[tauri::command]
+pub async fn list_workflows(db: State<'_, Mutex<DatabaseState>>) -> Result<Vec<WorkflowSpec>> {
+    Ok(db.lock().await.list_workflows().await?)
+}
+
+#[tauri::command]
+pub async fn delete_workflow(db: State<'_, Mutex<DatabaseState>>, name: String) -> Result<()> {
+    if !db.lock().await.delete_workflow(&name).await? {
+        return Err(Error::WorkflowNotFound { name });
+    }
+    log::info!("Deleted workflow '{}'", name);
+
+    Ok(())
+}
@@ -1,6 +1,11 @@
 use serde::Serialize;
 use thiserror::Error;
 
+use crate::settings::database::error::Error as DatabaseError;
+
+use super::panics::PanicInfo;
+use super::CancelReason;
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("{0}")]
@@ -8,6 +13,60 @@ pub enum Error {
 
     #[error("Task was already consumed")]
     AlreadyConsumed,
+
+    #[error("Task did not stop within {0:?}")]
+    StopTimeout(std::time::Duration),
+
+    #[error("Task timed out")]
+    TimedOut,
+
+    #[error("Task was cancelled ({reason})")]
+    Cancelled { reason: CancelReason },
+
+    #[error("The executor is shut down")]
+    ExecutorClosed,
+
+    #[error("Work of kind '{kind}' {panic}")]
+    WorkPanicked { kind: String, panic: PanicInfo },
+
+    #[error("Job with id '{id}' not found")]
+    JobNotFound { id: String },
+
+    #[error("Invalid job: {0}")]
+    InvalidJob(String),
+
+    #[error("Job with id '{id}' is not queued or running")]
+    JobNotActive { id: String },
+
+    #[error("Job with id '{id}' is still queued or running")]
+    JobStillActive { id: String },
+
+    #[error("Job with id '{id}' has not finished yet")]
+    JobNotFinished { id: String },
+
+    #[error("Job with id '{id}' did not succeed: {reason}")]
+    JobFailed { id: String, reason: String },
+
+    #[error("Schedule with id '{id}' not found")]
+    ScheduleNotFound { id: String },
+
+    #[error("Schedule with id '{id}' already exists")]
+    ScheduleAlreadyExists { id: String },
+
+    #[error("Invalid schedule '{id}': {reason}")]
+    InvalidSchedule { id: String, reason: String },
+
+    #[error("Invalid workflow: {0}")]
+    InvalidWorkflow(String),
+
+    #[error("Workflow '{name}' not found")]
+    WorkflowNotFound { name: String },
+
+    #[error("Workflow run with id '{id}' not found")]
+    WorkflowRunNotFound { id: String },
+
+    #[error("Settings error: {0}")]
+    Settings(#[from] DatabaseError),
 }
 
 impl Serialize for Error {
@@ -0,0 +1,381 @@
+//! Pool of worker threads for CPU-bound work, kept off the async runtime so
+//! that it does not starve the IPC.
+//!
+//! Work is queued by priority, interactive before batch, and grouped by kind,
+//! each kind having an optional limit of work running at once. Work waiting
+//! for its kind to free up does not hold back the rest of the queue.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{oneshot, watch};
+use tokio::time::Duration;
+
+use super::error::{Error, Result};
+use super::panics;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    /// Work a user is waiting on.
+    Interactive,
+    /// Background work, run when no interactive work is queued.
+    #[default]
+    Batch,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExecutorStatus {
+    pub workers: usize,
+    pub interactive: usize,
+    pub batch: usize,
+    /// Work running per kind.
+    pub in_flight: HashMap<String, usize>,
+    pub limits: HashMap<String, usize>,
+}
+
+struct Work {
+    kind: String,
+    run: Box<dyn FnOnce() + Send>,
+}
+
+#[derive(Default)]
+struct Queue {
+    interactive: VecDeque<Work>,
+    batch: VecDeque<Work>,
+    in_flight: HashMap<String, usize>,
+    limits: HashMap<String, usize>,
+    closed: bool,
+}
+
+impl Queue {
+    /// Takes the first work of the highest priority whose kind is below its
+    /// limit.
+    fn next(&mut self) -> Option<Work> {
+        let Queue {
+            interactive,
+            batch,
+            in_flight,
+            limits,
+            ..
+        } = self;
+
+        let runnable = |work: &Work| {
+            limits
+                .get(&work.kind)
+                .is_none_or(|max| in_flight.get(&work.kind).copied().unwrap_or(0) < *max)
+        };
+        let work = [interactive, batch].into_iter().find_map(|queue| {
+            let index = queue.iter().position(runnable)?;
+            queue.remove(index)
+        })?;
+
+        *in_flight.entry(work.kind.clone()).or_default() += 1;
+        Some(work)
+    }
+
+    fn done(&mut self, kind: &str) {
+        if let Some(count) = self.in_flight.get_mut(kind) {
+            *count -= 1;
+            if *count == 0 {
+                self.in_flight.remove(kind);
+            }
+        }
+    }
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    changed: Condvar,
+}
+
+/// Decrements the count of live workers when a worker thread ends.
+struct Alive(watch::Sender<usize>);
+
+impl Drop for Alive {
+    fn drop(&mut self) {
+        self.0.send_modify(|alive| *alive -= 1);
+    }
+}
+
+pub struct Executor {
+    shared: Arc<Shared>,
+    workers: usize,
+    alive: watch::Receiver<usize>,
+}
+
+impl Default for Executor {
+    /// One worker per core, leaving one to the async runtime.
+    fn default() -> Self {
+        let cores = thread::available_parallelism().map_or(1, |n| n.get());
+        Self::new(cores.saturating_sub(1).max(1))
+    }
+}
+
+impl Executor {
+    pub fn new(workers: usize) -> Self {
+        let workers = workers.max(1);
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue::default()),
+            changed: Condvar::new(),
+        });
+        let (alive_tx, alive) = watch::channel(workers);
+
+        for index in 0..workers {
+            let shared = shared.clone();
+            let alive = Alive(alive_tx.clone());
+            thread::Builder::new()
+                .name(format!("executor-{}", index))
+                .spawn(move || {
+                    let _alive = alive;
+                    work(&shared);
+                })
+                .expect("failed to spawn executor worker");
+        }
+
+        Self {
+            shared,
+            workers,
+            alive,
+        }
+    }
+
+    /// Allows at most `max` works of `kind` to run at once, `None` lifting
+    /// the limit.
+    pub fn set_limit(&self, kind: &str, max: Option<usize>) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        match max {
+            Some(max) => queue.limits.insert(kind.to_string(), max.max(1)),
+            None => queue.limits.remove(kind),
+        };
+        drop(queue);
+        self.shared.changed.notify_all();
+    }
+
+    /// Runs `function` on a worker and waits for its result. Work whose
+    /// caller stopped waiting before it started is skipped.
+    pub async fn run<F, T>(&self, kind: &str, priority: Priority, function: F) -> Result<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let run = Box::new(move || {
+            if tx.is_closed() {
+                return;
+            }
+            let _ = tx.send(panics::catch_unwind(function));
+        });
+
+        {
+            let mut queue = self.shared.queue.lock().unwrap();
+            if queue.closed {
+                return Err(Error::ExecutorClosed);
+            }
+            let work = Work {
+                kind: kind.to_string(),
+                run,
+            };
+            match priority {
+                Priority::Interactive => queue.interactive.push_back(work),
+                Priority::Batch => queue.batch.push_back(work),
+            }
+        }
+        self.shared.changed.notify_one();
+
+        match rx.await {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(panic)) => Err(Error::WorkPanicked {
+                kind: kind.to_string(),
+                panic,
+            }),
+            Err(_) => Err(Error::ExecutorClosed),
+        }
+    }
+
+    pub fn status(&self) -> ExecutorStatus {
+        let queue = self.shared.queue.lock().unwrap();
+        ExecutorStatus {
+            workers: self.workers,
+            interactive: queue.interactive.len(),
+            batch: queue.batch.len(),
+            in_flight: queue.in_flight.clone(),
+            limits: queue.limits.clone(),
+        }
+    }
+
+    /// Drops the queued work and stops the workers once their current work
+    /// is done. Returns the workers still busy after `timeout`, which are
+    /// left to end on their own.
+    pub async fn shutdown(&self, timeout: Duration) -> Vec<String> {
+        self.close();
+
+        let mut alive = self.alive.clone();
+        let stopped = tokio::time::timeout(timeout, async {
+            let _ = alive.wait_for(|alive| *alive == 0).await;
+        })
+        .await;
+        match stopped {
+            Ok(()) => Vec::new(),
+            Err(_) => vec![format!("{} executor workers", *self.alive.borrow())],
+        }
+    }
+
+    fn close(&self) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        queue.closed = true;
+        queue.interactive.clear();
+        queue.batch.clear();
+        drop(queue);
+        self.shared.changed.notify_all();
+    }
+}
+
+impl Drop for Executor {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+fn work(shared: &Shared) {
+    let mut queue = shared.queue.lock().unwrap();
+    loop {
+        if queue.closed {
+            return;
+        }
+        let Some(work) = queue.next() else {
+            queue = shared.changed.wait(queue).unwrap();
+            continue;
+        };
+        drop(queue);
+
+        (work.run)();
+
+        queue = shared.queue.lock().unwrap();
+        queue.done(&work.kind);
+        // A kind freeing up may unblock work another worker skipped.
+        shared.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_run() {
+        let executor = Executor::new(2);
+        let value = executor
+            .run("sum", Priority::Batch, || (1..=10).sum::<u32>())
+            .await
+            .unwrap();
+        assert_eq!(value, 55);
+    }
+
+    #[tokio::test]
+    async fn test_panic_keeps_worker() {
+        let executor = Executor::new(1);
+        let result = executor
+            .run("bad", Priority::Batch, || panic!("boom"))
+            .await;
+        let Err(Error::WorkPanicked { kind, panic }) = result else {
+            panic!("expected a panic");
+        };
+        assert_eq!(kind, "bad");
+        assert_eq!(panic.message, "boom");
+
+        let value = executor.run("good", Priority::Batch, || 1).await.unwrap();
+        assert_eq!(value, 1);
+    }
+
+    #[tokio::test]
+    async fn test_interactive_first() {
+        let executor = Arc::new(Executor::new(1));
+        let (block_tx, block_rx) = mpsc::channel::<()>();
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        // Occupies the only worker while the rest is queued.
+        let blocker = tokio::spawn({
+            let executor = executor.clone();
+            async move {
+                executor
+                    .run("block", Priority::Batch, move || block_rx.recv().unwrap())
+                    .await
+            }
+        });
+        while executor.status().in_flight.is_empty() {
+            tokio::task::yield_now().await;
+        }
+
+        let mut runs = Vec::new();
+        for (name, priority) in [
+            ("batch", Priority::Batch),
+            ("interactive", Priority::Interactive),
+        ] {
+            runs.push(tokio::spawn({
+                let executor = executor.clone();
+                let order = order.clone();
+                async move {
+                    executor
+                        .run(name, priority, move || order.lock().unwrap().push(name))
+                        .await
+                }
+            }));
+            while executor.status().batch + executor.status().interactive < runs.len() {
+                tokio::task::yield_now().await;
+            }
+        }
+
+        block_tx.send(()).unwrap();
+        blocker.await.unwrap().unwrap();
+        for run in runs {
+            run.await.unwrap().unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), vec!["interactive", "batch"]);
+    }
+
+    #[tokio::test]
+    async fn test_kind_limit() {
+        let executor = Arc::new(Executor::new(4));
+        executor.set_limit("heavy", Some(2));
+
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let mut runs = Vec::new();
+        for _ in 0..8 {
+            let executor = executor.clone();
+            let (running, peak) = (running.clone(), peak.clone());
+            runs.push(tokio::spawn(async move {
+                executor
+                    .run("heavy", Priority::Batch, move || {
+                        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        peak.fetch_max(now, Ordering::SeqCst);
+                        thread::sleep(std::time::Duration::from_millis(10));
+                        running.fetch_sub(1, Ordering::SeqCst);
+                    })
+                    .await
+            }));
+        }
+        // Other kinds are not held back by the limited one.
+        let light = executor.run("light", Priority::Batch, || 1).await;
+        assert_eq!(light.unwrap(), 1);
+
+        for run in runs {
+            run.await.unwrap().unwrap();
+        }
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let executor = Executor::new(2);
+        assert!(executor.shutdown(Duration::from_secs(1)).await.is_empty());
+
+        let result = executor.run("late", Priority::Batch, || ()).await;
+        assert!(matches!(result, Err(Error::ExecutorClosed)));
+    }
+}
@@ -0,0 +1,149 @@
+//! History of the jobs in the settings database, kept after they are removed
+//! from the manager or the app exits, and report of their failures.
+
+use chrono::Utc;
+use tauri::{AppHandle, Emitter, Manager};
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::Duration;
+
+use crate::settings::database::state::{DatabaseState, StoredJob};
+
+use super::error::Result;
+use super::state::{JobEntry, JobStatus, Tasks};
+use super::CancellableTask;
+
+/// Event carrying every failed job to the frontend, with its panic when it
+/// panicked.
+pub const JOB_FAILED_EVENT: &str = "job-failed";
+
+/// Error of the jobs a previous run of the app left unfinished.
+const INTERRUPTED: &str = "interrupted by the app exit";
+
+/// Writer of the job status changes to the settings database.
+pub struct History {
+    task: Mutex<Option<CancellableTask<()>>>,
+}
+
+impl History {
+    /// Writes every entry of `entries` in order, until the manager sending
+    /// them closes its history. Failures are also emitted as
+    /// `JOB_FAILED_EVENT`.
+    pub fn start(app: AppHandle, mut entries: mpsc::UnboundedReceiver<JobEntry>) -> Self {
+        let task = CancellableTask::new(move |_token| async move {
+            while let Some(entry) = entries.recv().await {
+                if entry.info.status == JobStatus::Failed {
+                    if let Err(e) = app.emit(JOB_FAILED_EVENT, &entry.info) {
+                        log::warn!("Failed to emit failure of job {}: {}", entry.info.id, e);
+                    }
+                }
+
+                let Some(db) = app.try_state::<Mutex<DatabaseState>>() else {
+                    continue;
+                };
+                let job = stored(entry);
+                let saved = db.lock().await.save_job(&job).await;
+                if let Err(e) = saved {
+                    log::warn!("Failed to record job {}: {}", job.id, e);
+                }
+            }
+        });
+
+        Self {
+            task: Mutex::new(Some(task)),
+        }
+    }
+
+    /// Waits up to `timeout` for the pending entries to be written, once the
+    /// jobs are stopped.
+    pub async fn shutdown(&self, timeout: Duration) -> Vec<String> {
+        let Some(mut task) = self.task.lock().await.take() else {
+            return Vec::new();
+        };
+        match task.join_timeout(timeout).await {
+            Ok(()) => Vec::new(),
+            Err(_) => vec!["job history".to_string()],
+        }
+    }
+}
+
+/// Marks the jobs the previous run left unfinished as failed, and numbers
+/// the next jobs after the stored ones.
+pub async fn restore(tasks: &Tasks, db: &DatabaseState) -> Result<()> {
+    let interrupted = db.interrupt_jobs(INTERRUPTED, Utc::now()).await?;
+    if interrupted > 0 {
+        log::warn!("{} jobs were interrupted by the last exit", interrupted);
+    }
+    tasks.resume_ids(db.last_job_number().await?);
+    Ok(())
+}
+
+fn stored(entry: JobEntry) -> StoredJob {
+    let JobEntry {
+        info,
+        result,
+        transitions,
+    } = entry;
+    let duration_ms = match (info.started_at, info.finished_at) {
+        (Some(started_at), Some(finished_at)) => {
+            Some((finished_at - started_at).num_milliseconds())
+        }
+        _ => None,
+    };
+
+    StoredJob {
+        id: info.id,
+        name: info.name,
+        params: info.params,
+        status: info.status.as_str().to_string(),
+        submitted_at: info.submitted_at,
+        started_at: info.started_at,
+        finished_at: info.finished_at,
+        duration_ms,
+        error: info.error,
+        cancel_reason: info.cancel_reason.map(|reason| reason.to_string()),
+        panic: info
+            .panic
+            .and_then(|panic| serde_json::to_value(panic).ok()),
+        result,
+        transitions: serde_json::to_value(transitions).unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use tokio::time::sleep;
+
+    #[tokio::test]
+    async fn test_entries() {
+        let (tasks, mut entries) = Tasks::with_history(1);
+        tasks.resume_ids(41);
+        let id = tasks.submit("sum".to_string(), json!({"n": 3}), None, |_| async {
+            sleep(Duration::from_millis(10)).await;
+            Ok(json!(6))
+        });
+        assert_eq!(id, "job-42");
+
+        let mut jobs = Vec::new();
+        while let Some(entry) = entries.recv().await {
+            let done = !entry.info.status.is_active();
+            jobs.push(stored(entry));
+            if done {
+                break;
+            }
+        }
+
+        let statuses: Vec<&str> = jobs.iter().map(|job| job.status.as_str()).collect();
+        assert_eq!(statuses, vec!["queued", "running", "finished"]);
+        let last = jobs.last().unwrap();
+        assert_eq!(last.result, Some(json!(6)));
+        assert!(last.duration_ms.unwrap() >= 10);
+        let transitions: Vec<Value> = serde_json::from_value(last.transitions.clone()).unwrap();
+        assert_eq!(transitions.len(), 3);
+        assert_eq!(transitions[2]["status"], json!("finished"));
+
+        tasks.shutdown(Duration::from_millis(100)).await;
+        assert!(entries.recv().await.is_none());
+    }
+}
@@ -0,0 +1,55 @@
+//! Kinds of jobs the frontend can submit to the task manager.
+
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use serde_json::Value;
+use tauri::{AppHandle, Manager};
+
+use crate::utils::channels::state::Channels;
+use crate::utils::export::commands::{self as export, ExportRequest};
+use crate::utils::import::{commands as import, reader::ImportOptions};
+
+use super::executor::{Executor, Priority};
+use super::progress::TaskContext;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JobSpec {
+    /// Parses a CSV file and returns its validation report.
+    InspectCsv {
+        path: PathBuf,
+        #[serde(default)]
+        options: ImportOptions,
+    },
+    /// Writes the history of channels to a file and returns a summary.
+    ExportChannels(ExportRequest),
+}
+
+impl JobSpec {
+    pub async fn run(self, app: AppHandle, context: TaskContext) -> Result<Value, String> {
+        let progress = context.progress;
+        let executor = app.state::<Executor>();
+
+        match self {
+            JobSpec::InspectCsv { path, options } => {
+                progress.stage("parsing");
+                let import =
+                    import::read(&executor, Priority::Batch, path, options, Some(progress)).await?;
+                serde_json::to_value(import.report).map_err(|e| e.to_string())
+            }
+            JobSpec::ExportChannels(request) => {
+                progress.stage("writing");
+                let summary = export::export(
+                    &app.state::<Channels>(),
+                    &executor,
+                    Priority::Batch,
+                    request,
+                    Some(progress),
+                )
+                .await?;
+                serde_json::to_value(summary).map_err(|e| e.to_string())
+            }
+        }
+    }
+}
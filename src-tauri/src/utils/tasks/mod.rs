@@ -1,24 +1,148 @@
+use std::fmt;
 use std::future::Future;
+use std::ops::Deref;
+use std::sync::{Arc, OnceLock};
+
+use serde::{Deserialize, Serialize};
+use tokio::time::{Duration, Instant};
 use tokio_util::sync::CancellationToken;
 
+pub mod commands;
 pub mod error;
+pub mod executor;
+pub mod history;
+pub mod jobs;
+pub mod panics;
+pub mod progress;
+pub mod scheduler;
 pub mod state;
+pub mod supervisor;
+pub mod workflow;
 
 use error::{Error, Result};
 
+/// Why a task was cancelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CancelReason {
+    /// Asked by the user.
+    User,
+    /// Its deadline passed.
+    Timeout,
+    /// The app is exiting.
+    Shutdown,
+    /// Replaced by a newer task.
+    Superseded,
+}
+
+impl fmt::Display for CancelReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CancelReason::User => "user",
+            CancelReason::Timeout => "timeout",
+            CancelReason::Shutdown => "shutdown",
+            CancelReason::Superseded => "superseded",
+        })
+    }
+}
+
+/// Cancellation token recording why it was cancelled. Tokens made by `child`
+/// are cancelled along with their parent and report its reason.
+#[derive(Debug, Clone, Default)]
+pub struct TaskToken {
+    token: CancellationToken,
+    reason: Arc<OnceLock<CancelReason>>,
+    parent: Option<Box<TaskToken>>,
+}
+
+impl TaskToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn child(&self) -> Self {
+        Self {
+            token: self.token.child_token(),
+            reason: Arc::default(),
+            parent: Some(Box::new(self.clone())),
+        }
+    }
+
+    /// Cancels for the user.
+    pub fn cancel(&self) {
+        self.cancel_with(CancelReason::User);
+    }
+
+    /// Cancels for `reason`, unless already cancelled.
+    pub fn cancel_with(&self, reason: CancelReason) {
+        if !self.token.is_cancelled() {
+            let _ = self.reason.set(reason);
+        }
+        self.token.cancel();
+    }
+
+    /// Why the token was cancelled, `None` while it is not.
+    pub fn reason(&self) -> Option<CancelReason> {
+        if !self.token.is_cancelled() {
+            return None;
+        }
+        self.reason
+            .get()
+            .copied()
+            .or_else(|| self.parent.as_ref().and_then(|parent| parent.reason()))
+    }
+
+    /// Cancels with `CancelReason::Timeout` once `timeout` elapsed.
+    pub fn set_deadline(&self, timeout: Duration) {
+        let token = self.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = tokio::time::sleep(timeout) => token.cancel_with(CancelReason::Timeout),
+                _ = token.cancelled() => {}
+            }
+        });
+    }
+}
+
+impl Deref for TaskToken {
+    type Target = CancellationToken;
+
+    fn deref(&self) -> &CancellationToken {
+        &self.token
+    }
+}
+
 pub struct CancellableTask<T> {
     handle: Option<tokio::task::JoinHandle<T>>,
-    token: CancellationToken,
+    token: TaskToken,
 }
 
 impl<T> CancellableTask<T> {
     pub fn new<F, Fut>(function: F) -> Self
     where
-        F: FnOnce(CancellationToken) -> Fut + Send + 'static,
+        F: FnOnce(TaskToken) -> Fut + Send + 'static,
+        Fut: Future<Output = T> + Send + 'static,
+        T: Send + 'static,
+    {
+        Self::spawn(TaskToken::new(), function)
+    }
+
+    /// Spawns a sub-task, cancelled along with this one.
+    pub fn child<U, F, Fut>(&self, function: F) -> CancellableTask<U>
+    where
+        F: FnOnce(TaskToken) -> Fut + Send + 'static,
+        Fut: Future<Output = U> + Send + 'static,
+        U: Send + 'static,
+    {
+        CancellableTask::spawn(self.token.child(), function)
+    }
+
+    fn spawn<F, Fut>(token: TaskToken, function: F) -> Self
+    where
+        F: FnOnce(TaskToken) -> Fut + Send + 'static,
         Fut: Future<Output = T> + Send + 'static,
         T: Send + 'static,
     {
-        let token = CancellationToken::new();
         let handle = Some(tokio::spawn(function(token.clone())));
         Self { handle, token }
     }
@@ -27,6 +151,23 @@ impl<T> CancellableTask<T> {
         self.token.cancel();
     }
 
+    pub fn cancel_with(&self, reason: CancelReason) {
+        self.token.cancel_with(reason);
+    }
+
+    /// Cancels the task with `CancelReason::Timeout` once `timeout` elapsed.
+    pub fn set_deadline(&self, timeout: Duration) {
+        self.token.set_deadline(timeout);
+    }
+
+    pub fn reason(&self) -> Option<CancelReason> {
+        self.token.reason()
+    }
+
+    pub fn token(&self) -> &TaskToken {
+        &self.token
+    }
+
     pub async fn join(&mut self) -> Result<T> {
         if let Some(handle) = self.handle.take() {
             Ok(handle.await?)
@@ -35,6 +176,27 @@ impl<T> CancellableTask<T> {
         }
     }
 
+    /// Like `join`, but a cancelled task fails with `Error::TimedOut` or
+    /// `Error::Cancelled` whatever it returned.
+    pub async fn outcome(&mut self) -> Result<T> {
+        let value = self.join().await?;
+        match self.reason() {
+            None => Ok(value),
+            Some(CancelReason::Timeout) => Err(Error::TimedOut),
+            Some(reason) => Err(Error::Cancelled { reason }),
+        }
+    }
+
+    /// Like `join`, but gives up after `timeout`, leaving the task running.
+    pub async fn join_timeout(&mut self, timeout: Duration) -> Result<T> {
+        let handle = self.handle.as_mut().ok_or(Error::AlreadyConsumed)?;
+        let joined = tokio::time::timeout(timeout, handle)
+            .await
+            .map_err(|_| Error::StopTimeout(timeout))?;
+        self.handle = None;
+        Ok(joined?)
+    }
+
     pub fn is_finished(&self) -> bool {
         self.handle.as_ref().map_or(true, |h| h.is_finished())
     }
@@ -44,6 +206,27 @@ impl<T> CancellableTask<T> {
     }
 }
 
+/// Joins `tasks` within `timeout` overall, the ones still running then are
+/// aborted. Returns the names of the tasks that did not end cleanly.
+///
+/// The tasks are not cancelled here, callers decide how they are asked to
+/// stop.
+pub async fn join_all<T>(
+    tasks: Vec<(String, CancellableTask<T>)>,
+    timeout: Duration,
+) -> Vec<String> {
+    let deadline = Instant::now() + timeout;
+    let mut failed = Vec::new();
+    for (name, mut task) in tasks {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if let Err(e) = task.join_timeout(remaining).await {
+            log::warn!("Task '{}' did not stop cleanly: {}", name, e);
+            failed.push(name);
+        }
+    }
+    failed
+}
+
 impl<T> Drop for CancellableTask<T> {
     fn drop(&mut self) {
         self.cancel();
@@ -158,6 +341,101 @@ mod tests {
         assert_eq!(result, 0);
     }
 
+    #[tokio::test]
+    async fn test_join_timeout() {
+        let mut task = CancellableTask::new(|token| async move {
+            token.cancelled().await;
+            7
+        });
+
+        let result = task.join_timeout(Duration::from_millis(20)).await;
+        assert!(matches!(result, Err(Error::StopTimeout(_))));
+        assert!(!task.is_finished());
+
+        task.cancel();
+        let result = task.join_timeout(Duration::from_millis(100)).await;
+        assert_eq!(result.unwrap(), 7);
+        assert!(task.is_finished());
+    }
+
+    #[tokio::test]
+    async fn test_join_all_reports_late_tasks() {
+        let quick = CancellableTask::new(|_token| async {});
+        let stuck = CancellableTask::new(|_token| sleep(Duration::from_secs(10)));
+        let panicking = CancellableTask::new(|_token| async { panic!("boom") });
+        let tasks = vec![
+            ("quick".to_string(), quick),
+            ("stuck".to_string(), stuck),
+            ("panicking".to_string(), panicking),
+        ];
+
+        let failed = timeout(
+            Duration::from_millis(500),
+            join_all(tasks, Duration::from_millis(50)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(failed, vec!["stuck".to_string(), "panicking".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_child_cancelled_with_parent() {
+        let parent = CancellableTask::new(|token| async move { token.cancelled().await });
+        let mut child = parent.child(|token| async move {
+            token.cancelled().await;
+            token.reason()
+        });
+
+        parent.cancel_with(CancelReason::Shutdown);
+        let reason = timeout(Duration::from_millis(100), child.join()).await;
+        assert_eq!(reason.unwrap().unwrap(), Some(CancelReason::Shutdown));
+    }
+
+    #[tokio::test]
+    async fn test_child_cancel_spares_parent() {
+        let parent = CancellableTask::new(|token| async move { token.cancelled().await });
+        let child = parent.child(|token| async move { token.cancelled().await });
+
+        child.cancel_with(CancelReason::Superseded);
+        assert_eq!(child.reason(), Some(CancelReason::Superseded));
+        assert!(!parent.is_cancelled());
+        assert_eq!(parent.reason(), None);
+    }
+
+    #[tokio::test]
+    async fn test_deadline() {
+        let mut task = CancellableTask::new(|token| async move {
+            tokio::select! {
+                _ = sleep(Duration::from_secs(10)) => 1,
+                _ = token.cancelled() => 0,
+            }
+        });
+        task.set_deadline(Duration::from_millis(20));
+
+        let result = timeout(Duration::from_millis(500), task.outcome()).await;
+        assert!(matches!(result.unwrap(), Err(Error::TimedOut)));
+        assert_eq!(task.reason(), Some(CancelReason::Timeout));
+    }
+
+    #[tokio::test]
+    async fn test_outcome() {
+        let mut finished = CancellableTask::new(|_token| async { 42 });
+        assert_eq!(finished.outcome().await.unwrap(), 42);
+
+        let mut cancelled = CancellableTask::new(|token| async move {
+            token.cancelled().await;
+            0
+        });
+        cancelled.cancel();
+        cancelled.cancel_with(CancelReason::Shutdown);
+        assert!(matches!(
+            cancelled.outcome().await,
+            Err(Error::Cancelled {
+                reason: CancelReason::User
+            })
+        ));
+    }
+
     #[tokio::test]
     async fn test_immediate_start() {
         let started = Arc::new(AtomicBool::new(false));
@@ -0,0 +1,161 @@
+//! Capture of the panics of tasks with their message, location and, when
+//! enabled through `RUST_BACKTRACE`, backtrace.
+//!
+//! The payload caught by `catch_unwind` only holds the message, the rest is
+//! recorded by a panic hook on the panicking thread just before unwinding.
+
+use std::any::Any;
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::cell::RefCell;
+use std::fmt;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::Once;
+use std::task::{Context, Poll};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PanicInfo {
+    pub message: String,
+    /// `file:line:column` of the panic.
+    pub location: Option<String>,
+    pub backtrace: Option<String>,
+    /// Name of the panicking thread.
+    pub thread: Option<String>,
+}
+
+impl fmt::Display for PanicInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "panicked: {}", self.message)
+    }
+}
+
+thread_local! {
+    static LAST_PANIC: RefCell<Option<PanicInfo>> = const { RefCell::new(None) };
+}
+
+static HOOK: Once = Once::new();
+
+/// Installs, once, the hook recording the panics. The hook in place before
+/// still reports them.
+pub fn install_hook() {
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let backtrace = Backtrace::capture();
+            let panic = PanicInfo {
+                message: message(info.payload()),
+                location: info.location().map(|location| location.to_string()),
+                backtrace: (backtrace.status() == BacktraceStatus::Captured)
+                    .then(|| backtrace.to_string()),
+                thread: std::thread::current().name().map(str::to_string),
+            };
+            LAST_PANIC.with(|last| *last.borrow_mut() = Some(panic));
+            previous(info);
+        }));
+    });
+}
+
+/// Message of a panic payload.
+pub fn message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown payload".to_string())
+}
+
+/// Panic caught on this thread with `payload`, along with what the hook
+/// recorded of it.
+fn caught(payload: &(dyn Any + Send)) -> PanicInfo {
+    let message = message(payload);
+    let recorded = LAST_PANIC.with(|last| last.borrow_mut().take());
+
+    // A panic caught elsewhere, e.g. by the runtime, may have been left.
+    match recorded {
+        Some(panic) if panic.message == message => panic,
+        _ => PanicInfo {
+            message,
+            location: None,
+            backtrace: None,
+            thread: None,
+        },
+    }
+}
+
+/// Calls `function`, returning its panic if it panics.
+pub fn catch_unwind<T>(function: impl FnOnce() -> T) -> Result<T, PanicInfo> {
+    install_hook();
+    panic::catch_unwind(AssertUnwindSafe(function)).map_err(|payload| caught(&*payload))
+}
+
+/// Future of `catch`.
+pub struct CatchPanic<F> {
+    future: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for CatchPanic<F> {
+    type Output = Result<F::Output, PanicInfo>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let future = self.get_mut().future.as_mut();
+        match panic::catch_unwind(AssertUnwindSafe(|| future.poll(cx))) {
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(payload) => Poll::Ready(Err(caught(&*payload))),
+        }
+    }
+}
+
+/// Runs `future`, returning its panic if it panics.
+pub fn catch<F: Future>(future: F) -> CatchPanic<F> {
+    install_hook();
+    CatchPanic {
+        future: Box::pin(future),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_catch() {
+        let output = catch(async { 42 }).await;
+        assert_eq!(output, Ok(42));
+
+        let panic = catch(async {
+            tokio::task::yield_now().await;
+            let pivot = 0.0;
+            if pivot == 0.0 {
+                panic!("singular matrix at row {}", 3);
+            }
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(panic.message, "singular matrix at row 3");
+        assert!(panic.location.as_ref().unwrap().contains("panics.rs"));
+        assert_eq!(panic.to_string(), "panicked: singular matrix at row 3");
+    }
+
+    #[test]
+    fn test_catch_unwind() {
+        let panic = catch_unwind(|| -> u32 { panic!("boom") }).unwrap_err();
+        assert_eq!(panic.message, "boom");
+        assert!(panic.location.is_some());
+        assert_eq!(catch_unwind(|| 1), Ok(1));
+    }
+
+    #[test]
+    fn test_stale_record_ignored() {
+        install_hook();
+        let _ = panic::catch_unwind(|| panic!("first"));
+
+        let payload: Box<dyn Any + Send> = Box::new("second");
+        let panic = caught(&*payload);
+        assert_eq!(panic.message, "second");
+        assert_eq!(panic.location, None);
+    }
+}
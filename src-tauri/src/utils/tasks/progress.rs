@@ -0,0 +1,137 @@
+//! Progress of long jobs, reported by the job and read by the manager.
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Serialize;
+use tokio::sync::watch;
+
+use super::TaskToken;
+
+/// Items between two updates of `ProgressReporter::items`.
+const ITEMS_STEP: usize = 1024;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Progress {
+    /// Completed part of the job, from 0 to 1.
+    pub fraction: f64,
+    pub stage: Option<String>,
+    pub message: Option<String>,
+}
+
+impl Progress {
+    /// Completion time extrapolated from the pace since `started_at`.
+    pub fn eta(&self, started_at: DateTime<Utc>, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if self.fraction <= 0.0 || self.fraction >= 1.0 {
+            return None;
+        }
+
+        let elapsed = (now - started_at).num_milliseconds() as f64;
+        let remaining = elapsed * (1.0 - self.fraction) / self.fraction;
+        Some(now + TimeDelta::milliseconds(remaining as i64))
+    }
+}
+
+/// Handle given to a job to publish its progress, cheap to clone.
+#[derive(Clone)]
+pub struct ProgressReporter {
+    progress: watch::Sender<Progress>,
+}
+
+impl ProgressReporter {
+    pub fn new() -> (Self, watch::Receiver<Progress>) {
+        let (progress, receiver) = watch::channel(Progress::default());
+        (Self { progress }, receiver)
+    }
+
+    /// Sets the completed fraction, clamped to `0..=1`.
+    pub fn set(&self, fraction: f64) {
+        self.progress
+            .send_modify(|p| p.fraction = fraction.clamp(0.0, 1.0));
+    }
+
+    /// Sets the fraction of `done` items out of `total`, only every
+    /// `ITEMS_STEP` items and at the last one so that it can be called for
+    /// each item.
+    pub fn items(&self, done: usize, total: usize) {
+        if total == 0 {
+            self.set(1.0);
+        } else if done.is_multiple_of(ITEMS_STEP) || done >= total {
+            self.set(done as f64 / total as f64);
+        }
+    }
+
+    /// Enters a new stage, e.g. `factorizing`, clearing the message.
+    pub fn stage(&self, label: impl Into<String>) {
+        self.progress.send_modify(|p| {
+            p.stage = Some(label.into());
+            p.message = None;
+        });
+    }
+
+    pub fn message(&self, message: impl Into<String>) {
+        self.progress
+            .send_modify(|p| p.message = Some(message.into()));
+    }
+}
+
+/// What a job is handed when it starts.
+#[derive(Clone)]
+pub struct TaskContext {
+    pub token: TaskToken,
+    pub progress: ProgressReporter,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eta() {
+        let start = Utc::now();
+        let now = start + TimeDelta::seconds(30);
+        let progress = |fraction| Progress {
+            fraction,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            progress(0.25).eta(start, now),
+            Some(now + TimeDelta::seconds(90))
+        );
+        assert_eq!(progress(0.0).eta(start, now), None);
+        assert_eq!(progress(1.0).eta(start, now), None);
+    }
+
+    #[test]
+    fn test_reporter() {
+        let (reporter, progress) = ProgressReporter::new();
+        reporter.stage("parsing");
+        reporter.message("line 1000");
+        reporter.set(1.5);
+        assert_eq!(
+            *progress.borrow(),
+            Progress {
+                fraction: 1.0,
+                stage: Some("parsing".to_string()),
+                message: Some("line 1000".to_string()),
+            }
+        );
+
+        reporter.stage("writing");
+        assert_eq!(progress.borrow().message, None);
+    }
+
+    #[test]
+    fn test_items() {
+        let (reporter, progress) = ProgressReporter::new();
+        reporter.items(1024, 4096);
+        assert_eq!(progress.borrow().fraction, 0.25);
+        reporter.items(1500, 4096);
+        assert_eq!(progress.borrow().fraction, 0.25);
+        reporter.items(4096, 4096);
+        assert_eq!(progress.borrow().fraction, 1.0);
+
+        reporter.set(0.0);
+        reporter.items(0, 0);
+        assert_eq!(progress.borrow().fraction, 1.0);
+    }
+}
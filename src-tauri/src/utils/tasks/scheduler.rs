@@ -0,0 +1,694 @@
+//! Jobs submitted periodically, on an interval or a cron expression, in wall
+//! time or simulated time.
+
+use std::str::FromStr;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use dashmap::{mapref::entry::Entry, DashMap};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::{AppHandle, Manager};
+use tokio::sync::{mpsc, watch, Mutex};
+use tokio::time::{sleep, Duration};
+
+use crate::settings::database::state::DatabaseState;
+use crate::utils::clock::SimClock;
+
+use super::error::{Error, Result};
+use super::jobs::JobSpec;
+use super::state::Tasks;
+use super::{join_all, CancelReason, CancellableTask};
+
+/// Most runs owed by one schedule at once, the older fires are skipped.
+pub const MAX_CATCH_UP: u64 = 100;
+
+/// Fires waiting to be submitted before the schedules wait for the dispatcher.
+const FIRES_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Trigger {
+    /// Every `every_ms`, counted from the creation of the schedule.
+    Interval { every_ms: u64 },
+    /// Cron expression in UTC, with or without the leading seconds field,
+    /// e.g. `0 2 * * *` for every night at 2:00.
+    Cron { expression: String },
+}
+
+/// Clock the fire times of a schedule are read on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleClock {
+    #[default]
+    Wall,
+    Simulation,
+}
+
+/// What to do with the fires missed while the app was closed, the clock
+/// jumped forward or the schedule was late.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MisfirePolicy {
+    /// Drops the missed fires.
+    Skip,
+    /// Runs the job once for all the missed fires.
+    #[default]
+    FireOnce,
+    /// Runs the job for every missed fire, up to `MAX_CATCH_UP`.
+    FireAll,
+}
+
+fn default_grace_ms() -> u64 {
+    1_000
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduleSpec {
+    /// Name of the submitted jobs.
+    pub name: String,
+    /// Job submitted on every fire, as given to `submit_job`.
+    pub job: Value,
+    pub trigger: Trigger,
+    #[serde(default)]
+    pub clock: ScheduleClock,
+    #[serde(default)]
+    pub misfire: MisfirePolicy,
+    /// Delay after which a fire counts as missed.
+    #[serde(default = "default_grace_ms")]
+    pub grace_ms: u64,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+/// Fire times of a trigger.
+enum Timetable {
+    Interval(TimeDelta),
+    Cron(Box<cron::Schedule>),
+}
+
+impl Timetable {
+    fn new(trigger: &Trigger) -> core::result::Result<Self, String> {
+        match trigger {
+            Trigger::Interval { every_ms } => match i64::try_from(*every_ms)
+                .ok()
+                .and_then(TimeDelta::try_milliseconds)
+            {
+                Some(every) if every > TimeDelta::zero() => Ok(Timetable::Interval(every)),
+                _ => Err(format!("invalid interval of {} ms", every_ms)),
+            },
+            Trigger::Cron { expression } => {
+                // The cron crate wants the seconds, the usual five fields
+                // fire at the start of the minute.
+                let expression = match expression.split_whitespace().count() {
+                    5 => format!("0 {}", expression),
+                    _ => expression.clone(),
+                };
+                cron::Schedule::from_str(&expression)
+                    .map(|schedule| Timetable::Cron(Box::new(schedule)))
+                    .map_err(|e| format!("invalid cron expression '{}': {}", expression, e))
+            }
+        }
+    }
+
+    fn next_after(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Timetable::Interval(every) => time.checked_add_signed(*every),
+            Timetable::Cron(schedule) => schedule.after(&time).next(),
+        }
+    }
+
+    /// Number of fires within `next..=now` and the time the following fire
+    /// is counted from. Cron fires are only counted up to `MAX_CATCH_UP`,
+    /// the next one then being counted from `now`.
+    fn due(&self, next: DateTime<Utc>, now: DateTime<Utc>) -> (u64, DateTime<Utc>) {
+        if let Timetable::Interval(every) = self {
+            let every = every.num_milliseconds();
+            let missed = ((now - next).num_milliseconds() / every).max(0);
+            return (
+                missed as u64 + 1,
+                next + TimeDelta::milliseconds(missed * every),
+            );
+        }
+
+        let mut last = next;
+        for due in 1..MAX_CATCH_UP {
+            match self.next_after(last) {
+                Some(time) if time <= now => last = time,
+                _ => return (due, last),
+            }
+        }
+        (MAX_CATCH_UP, now)
+    }
+}
+
+/// Runs owed by a schedule woken at `now` for its fire at `next`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CatchUp {
+    runs: u64,
+    skipped: u64,
+    /// Time the following fire is counted from.
+    last: DateTime<Utc>,
+}
+
+fn catch_up(
+    timetable: &Timetable,
+    next: DateTime<Utc>,
+    now: DateTime<Utc>,
+    misfire: MisfirePolicy,
+    grace: TimeDelta,
+) -> CatchUp {
+    let (due, last) = timetable.due(next, now);
+    let on_time = now - last <= grace;
+    let runs = match misfire {
+        MisfirePolicy::Skip => on_time as u64,
+        MisfirePolicy::FireOnce => 1,
+        MisfirePolicy::FireAll => due.min(MAX_CATCH_UP),
+    };
+
+    CatchUp {
+        runs,
+        skipped: due - runs,
+        last,
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ScheduleStatus {
+    pub next_fire_at: Option<DateTime<Utc>>,
+    pub last_fired_at: Option<DateTime<Utc>>,
+    /// Jobs submitted.
+    pub fired: u64,
+    /// Fires dropped by the misfire policy.
+    pub skipped: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduleInfo {
+    pub id: String,
+    pub spec: ScheduleSpec,
+    #[serde(flatten)]
+    pub status: ScheduleStatus,
+}
+
+/// Fire of a schedule, handed to the dispatcher which submits the jobs.
+#[derive(Debug, Clone)]
+pub struct Fire {
+    pub schedule: String,
+    pub name: String,
+    pub job: Value,
+    /// Time of the fire on the clock of the schedule.
+    pub at: DateTime<Utc>,
+    /// Jobs to submit, none when the misfire policy skipped the fire.
+    pub runs: u64,
+}
+
+struct Schedule {
+    spec: ScheduleSpec,
+    status: watch::Receiver<ScheduleStatus>,
+    /// `None` while disabled.
+    task: Option<CancellableTask<()>>,
+}
+
+/// Registry of the schedules, each one waiting for its next fire in its own
+/// task.
+pub struct Scheduler {
+    schedules: DashMap<String, Schedule>,
+    fires: mpsc::Sender<Fire>,
+    clock: SimClock,
+}
+
+impl Scheduler {
+    /// Returns the scheduler and the receiver of its fires, see `dispatch`.
+    pub fn new(clock: SimClock) -> (Self, mpsc::Receiver<Fire>) {
+        let (fires, receiver) = mpsc::channel(FIRES_CAPACITY);
+        let scheduler = Self {
+            schedules: DashMap::new(),
+            fires,
+            clock,
+        };
+        (scheduler, receiver)
+    }
+
+    /// Current time on `clock`.
+    pub fn now(&self, clock: ScheduleClock) -> DateTime<Utc> {
+        match clock {
+            ScheduleClock::Wall => Utc::now(),
+            ScheduleClock::Simulation => self.clock.now(),
+        }
+    }
+
+    /// Adds a schedule whose fires are counted from `since`, the fires
+    /// between `since` and now being misfires.
+    pub fn add(
+        &self,
+        id: String,
+        spec: ScheduleSpec,
+        since: DateTime<Utc>,
+        last_fired_at: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        let timetable = match Timetable::new(&spec.trigger) {
+            Ok(timetable) => timetable,
+            Err(reason) => return Err(Error::InvalidSchedule { id, reason }),
+        };
+
+        match self.schedules.entry(id) {
+            Entry::Occupied(entry) => Err(Error::ScheduleAlreadyExists {
+                id: entry.key().clone(),
+            }),
+            Entry::Vacant(entry) => {
+                let (status_tx, status) = watch::channel(ScheduleStatus {
+                    last_fired_at,
+                    ..Default::default()
+                });
+                let task = spec
+                    .enabled
+                    .then(|| self.spawn(entry.key().clone(), &spec, timetable, since, status_tx));
+                entry.insert(Schedule { spec, status, task });
+                Ok(())
+            }
+        }
+    }
+
+    /// Enables or disables a schedule, an enabled schedule counts its fires
+    /// from now.
+    pub fn set_enabled(&self, id: &str, enabled: bool) -> Result<ScheduleInfo> {
+        let mut schedule = self
+            .schedules
+            .get_mut(id)
+            .ok_or_else(|| Error::ScheduleNotFound { id: id.to_string() })?;
+        schedule.spec.enabled = enabled;
+
+        if !enabled {
+            schedule.task = None;
+            let status = ScheduleStatus {
+                next_fire_at: None,
+                ..schedule.status.borrow().clone()
+            };
+            schedule.status = watch::channel(status).1;
+        } else if schedule.task.is_none() {
+            let timetable = Timetable::new(&schedule.spec.trigger).map_err(|reason| {
+                Error::InvalidSchedule {
+                    id: id.to_string(),
+                    reason,
+                }
+            })?;
+            let (status_tx, status) = watch::channel(schedule.status.borrow().clone());
+            let since = self.now(schedule.spec.clock);
+            schedule.task =
+                Some(self.spawn(id.to_string(), &schedule.spec, timetable, since, status_tx));
+            schedule.status = status;
+        }
+
+        let status = schedule.status.borrow().clone();
+        Ok(ScheduleInfo {
+            id: id.to_string(),
+            spec: schedule.spec.clone(),
+            status,
+        })
+    }
+
+    pub fn remove(&self, id: &str) -> Result<ScheduleInfo> {
+        let (id, schedule) = self
+            .schedules
+            .remove(id)
+            .ok_or_else(|| Error::ScheduleNotFound { id: id.to_string() })?;
+        let status = schedule.status.borrow().clone();
+
+        Ok(ScheduleInfo {
+            id,
+            spec: schedule.spec,
+            status,
+        })
+    }
+
+    pub fn info(&self, id: &str) -> Result<ScheduleInfo> {
+        self.schedules
+            .get(id)
+            .map(|schedule| ScheduleInfo {
+                id: id.to_string(),
+                spec: schedule.spec.clone(),
+                status: schedule.status.borrow().clone(),
+            })
+            .ok_or_else(|| Error::ScheduleNotFound { id: id.to_string() })
+    }
+
+    /// Every schedule, sorted by id.
+    pub fn list(&self) -> Vec<ScheduleInfo> {
+        let mut schedules: Vec<ScheduleInfo> = self
+            .schedules
+            .iter()
+            .map(|schedule| ScheduleInfo {
+                id: schedule.key().clone(),
+                spec: schedule.spec.clone(),
+                status: schedule.status.borrow().clone(),
+            })
+            .collect();
+        schedules.sort_by(|a, b| a.id.cmp(&b.id));
+        schedules
+    }
+
+    /// Stops every schedule for the app exit. Returns the ids of the ones
+    /// that did not end within `timeout`.
+    pub async fn shutdown(&self, timeout: Duration) -> Vec<String> {
+        let ids: Vec<String> = self.schedules.iter().map(|s| s.key().clone()).collect();
+        let tasks: Vec<(String, CancellableTask<()>)> = ids
+            .iter()
+            .filter_map(|id| self.schedules.remove(id))
+            .filter_map(|(id, schedule)| schedule.task.map(|task| (id, task)))
+            .inspect(|(_, task)| task.cancel_with(CancelReason::Shutdown))
+            .collect();
+
+        join_all(tasks, timeout).await
+    }
+
+    fn spawn(
+        &self,
+        id: String,
+        spec: &ScheduleSpec,
+        timetable: Timetable,
+        since: DateTime<Utc>,
+        status: watch::Sender<ScheduleStatus>,
+    ) -> CancellableTask<()> {
+        let spec = spec.clone();
+        let fires = self.fires.clone();
+        let clock = self.clock.clone();
+        let grace = i64::try_from(spec.grace_ms)
+            .ok()
+            .and_then(TimeDelta::try_milliseconds)
+            .unwrap_or(TimeDelta::MAX);
+
+        CancellableTask::new(move |token| async move {
+            let now = || match spec.clock {
+                ScheduleClock::Wall => Utc::now(),
+                ScheduleClock::Simulation => clock.now(),
+            };
+            let mut last = since;
+
+            loop {
+                let Some(next) = timetable.next_after(last) else {
+                    log::info!("Schedule '{}' has no fire left", id);
+                    break;
+                };
+                status.send_modify(|s| s.next_fire_at = Some(next));
+
+                tokio::select! {
+                    _ = wait_until(spec.clock, &clock, next) => {}
+                    _ = token.cancelled() => break,
+                }
+
+                let catch_up = catch_up(&timetable, next, now(), spec.misfire, grace);
+                if catch_up.skipped > 0 {
+                    log::warn!(
+                        "Schedule '{}' skipped {} missed fires",
+                        id,
+                        catch_up.skipped
+                    );
+                }
+                status.send_modify(|s| {
+                    s.fired += catch_up.runs;
+                    s.skipped += catch_up.skipped;
+                    s.last_fired_at = Some(catch_up.last);
+                });
+
+                let fire = Fire {
+                    schedule: id.clone(),
+                    name: spec.name.clone(),
+                    job: spec.job.clone(),
+                    at: catch_up.last,
+                    runs: catch_up.runs,
+                };
+                if fires.send(fire).await.is_err() {
+                    break;
+                }
+                last = catch_up.last;
+            }
+            status.send_modify(|s| s.next_fire_at = None);
+        })
+    }
+}
+
+async fn wait_until(clock: ScheduleClock, simulation: &SimClock, time: DateTime<Utc>) {
+    match clock {
+        ScheduleClock::Wall => sleep((time - Utc::now()).to_std().unwrap_or_default()).await,
+        ScheduleClock::Simulation => simulation.sleep_until(time).await,
+    }
+}
+
+/// Submits the jobs of the fires to the task manager and records the fires
+/// in the settings database, until the scheduler is dropped.
+pub async fn dispatch(app: AppHandle, mut fires: mpsc::Receiver<Fire>) {
+    while let Some(fire) = fires.recv().await {
+        let tasks = app.state::<Tasks>();
+        for _ in 0..fire.runs {
+            let job: JobSpec = match serde_json::from_value(fire.job.clone()) {
+                Ok(job) => job,
+                Err(e) => {
+                    log::error!("Invalid job in schedule '{}': {}", fire.schedule, e);
+                    break;
+                }
+            };
+            let app = app.clone();
+            let id = tasks.submit(fire.name.clone(), fire.job.clone(), None, move |context| {
+                job.run(app, context)
+            });
+            log::info!("Schedule '{}' submitted job {}", fire.schedule, id);
+        }
+
+        if let Some(db) = app.try_state::<Mutex<DatabaseState>>() {
+            if let Err(e) = db
+                .lock()
+                .await
+                .set_schedule_fired(&fire.schedule, fire.at)
+                .await
+            {
+                log::warn!(
+                    "Failed to record fire of schedule '{}': {}",
+                    fire.schedule,
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// Adds the schedules stored in the settings database, counting their fires
+/// from the last one or from their last resume.
+pub async fn restore(scheduler: &Scheduler, db: &DatabaseState) -> Result<()> {
+    for stored in db.list_schedules::<ScheduleSpec>().await? {
+        let since = stored
+            .last_fired_at
+            .max(stored.resumed_at)
+            .unwrap_or(stored.created_at);
+        if let Err(e) = scheduler.add(stored.id, stored.spec, since, stored.last_fired_at) {
+            log::warn!("Failed to restore schedule: {}", e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn time(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    fn spec(trigger: Trigger, clock: ScheduleClock, misfire: MisfirePolicy) -> ScheduleSpec {
+        ScheduleSpec {
+            name: "snapshot".to_string(),
+            job: json!({"type": "inspect_csv", "path": "state.csv"}),
+            trigger,
+            clock,
+            misfire,
+            grace_ms: default_grace_ms(),
+            enabled: true,
+        }
+    }
+
+    #[test]
+    fn test_cron_accepts_five_fields() {
+        let nightly = Timetable::new(&Trigger::Cron {
+            expression: "0 2 * * *".to_string(),
+        })
+        .unwrap();
+        assert_eq!(
+            nightly.next_after(time("2025-06-01T12:00:00Z")),
+            Some(time("2025-06-02T02:00:00Z"))
+        );
+
+        assert!(Timetable::new(&Trigger::Cron {
+            expression: "every night".to_string()
+        })
+        .is_err());
+        assert!(Timetable::new(&Trigger::Interval { every_ms: 0 }).is_err());
+    }
+
+    #[test]
+    fn test_misfire_policies() {
+        let every_minute = Timetable::new(&Trigger::Interval { every_ms: 60_000 }).unwrap();
+        let next = time("2025-06-01T12:01:00Z");
+        let grace = TimeDelta::seconds(1);
+
+        let on_time = catch_up(
+            &every_minute,
+            next,
+            time("2025-06-01T12:01:00.200Z"),
+            MisfirePolicy::Skip,
+            grace,
+        );
+        assert_eq!(
+            on_time,
+            CatchUp {
+                runs: 1,
+                skipped: 0,
+                last: next
+            }
+        );
+
+        // Fires at 12:01, 12:02 and 12:03 are due at 12:03:30.
+        let late = time("2025-06-01T12:03:30Z");
+        let last = time("2025-06-01T12:03:00Z");
+        let runs = |misfire| catch_up(&every_minute, next, late, misfire, grace);
+        assert_eq!(
+            runs(MisfirePolicy::Skip),
+            CatchUp {
+                runs: 0,
+                skipped: 3,
+                last
+            }
+        );
+        assert_eq!(
+            runs(MisfirePolicy::FireOnce),
+            CatchUp {
+                runs: 1,
+                skipped: 2,
+                last
+            }
+        );
+        assert_eq!(
+            runs(MisfirePolicy::FireAll),
+            CatchUp {
+                runs: 3,
+                skipped: 0,
+                last
+            }
+        );
+    }
+
+    #[test]
+    fn test_cron_catch_up_is_bounded() {
+        let every_second = Timetable::new(&Trigger::Cron {
+            expression: "* * * * * *".to_string(),
+        })
+        .unwrap();
+        let now = time("2025-06-02T00:00:00Z");
+
+        let (due, last) = every_second.due(time("2025-06-01T00:00:00Z"), now);
+        assert_eq!((due, last), (MAX_CATCH_UP, now));
+    }
+
+    #[tokio::test]
+    async fn test_simulation_schedule_fires() {
+        let clock = SimClock::starting_at(time("2025-06-01T12:00:00Z"));
+        clock.set_speed(1000.0).unwrap();
+        let (scheduler, mut fires) = Scheduler::new(clock.clone());
+
+        let spec = spec(
+            Trigger::Interval { every_ms: 60_000 },
+            ScheduleClock::Simulation,
+            MisfirePolicy::FireOnce,
+        );
+        scheduler
+            .add(
+                "snapshot".to_string(),
+                spec,
+                time("2025-06-01T12:00:00Z"),
+                None,
+            )
+            .unwrap();
+
+        let fire = tokio::time::timeout(Duration::from_secs(1), fires.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(fire.schedule, "snapshot");
+        assert_eq!(fire.runs, 1);
+        assert_eq!(fire.at, time("2025-06-01T12:01:00Z"));
+
+        let info = scheduler.info("snapshot").unwrap();
+        assert_eq!(info.status.fired, 1);
+        assert_eq!(info.status.last_fired_at, Some(fire.at));
+    }
+
+    #[tokio::test]
+    async fn test_seek_forward_misfires() {
+        let clock = SimClock::starting_at(time("2025-06-01T12:00:00Z"));
+        clock.pause();
+        let (scheduler, mut fires) = Scheduler::new(clock.clone());
+
+        let spec = spec(
+            Trigger::Interval { every_ms: 60_000 },
+            ScheduleClock::Simulation,
+            MisfirePolicy::Skip,
+        );
+        scheduler
+            .add(
+                "snapshot".to_string(),
+                spec,
+                time("2025-06-01T12:00:00Z"),
+                None,
+            )
+            .unwrap();
+
+        clock.seek(time("2025-06-01T12:10:30Z"));
+        let fire = tokio::time::timeout(Duration::from_secs(1), fires.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(fire.runs, 0);
+        assert_eq!(fire.at, time("2025-06-01T12:10:00Z"));
+        assert_eq!(scheduler.info("snapshot").unwrap().status.skipped, 10);
+        assert_eq!(
+            scheduler.info("snapshot").unwrap().status.next_fire_at,
+            Some(time("2025-06-01T12:11:00Z"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_disable_and_remove() {
+        let clock = SimClock::default();
+        let (scheduler, _fires) = Scheduler::new(clock.clone());
+        let spec = spec(
+            Trigger::Cron {
+                expression: "0 * * * *".to_string(),
+            },
+            ScheduleClock::Wall,
+            MisfirePolicy::default(),
+        );
+
+        scheduler
+            .add("hourly".to_string(), spec.clone(), Utc::now(), None)
+            .unwrap();
+        assert!(matches!(
+            scheduler.add("hourly".to_string(), spec, Utc::now(), None),
+            Err(Error::ScheduleAlreadyExists { .. })
+        ));
+
+        let info = scheduler.set_enabled("hourly", false).unwrap();
+        assert!(!info.spec.enabled);
+        assert!(scheduler.set_enabled("hourly", true).unwrap().spec.enabled);
+
+        scheduler.remove("hourly").unwrap();
+        assert!(scheduler.list().is_empty());
+        assert!(matches!(
+            scheduler.remove("hourly"),
+            Err(Error::ScheduleNotFound { .. })
+        ));
+    }
+}
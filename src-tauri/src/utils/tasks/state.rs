@@ -1,11 +1,666 @@
-use std::collections::HashMap;
-use tokio::sync::Mutex;
+//! Background jobs, queued until a slot is free and tracked until removed or
+//! evicted once ended.
 
-use crate::utils::tasks::CancellableTask;
+use std::future::Future;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
 
-#[derive(Default)]
-pub struct TasksInner {
-    pub tasks: HashMap<String, CancellableTask<()>>,
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use serde_json::Value;
+use tauri::ipc::Channel;
+use tokio::sync::{mpsc, watch, Semaphore};
+use tokio::time::{sleep, Duration};
+
+use crate::utils::tasks::{join_all, CancelReason, CancellableTask, TaskToken};
+
+use super::error::{Error, Result};
+use super::panics::{self, PanicInfo};
+use super::progress::{Progress, ProgressReporter, TaskContext};
+use super::supervisor::join_failure;
+
+/// Jobs running at the same time, the others wait in the queue.
+pub const MAX_RUNNING_JOBS: usize = 4;
+
+/// Ended jobs kept in memory, the oldest ones being evicted past it. The
+/// history in the settings database keeps them all.
+pub const MAX_ENDED_JOBS: usize = 256;
+
+/// Minimum delay between two progress updates sent to the frontend.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Finished,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn is_active(self) -> bool {
+        matches!(self, JobStatus::Queued | JobStatus::Running)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Finished => "finished",
+            JobStatus::Failed => "failed",
+            JobStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Transition {
+    pub status: JobStatus,
+    pub at: DateTime<Utc>,
+}
+
+/// State of a job after a status change, sent to the history.
+#[derive(Debug, Clone)]
+pub struct JobEntry {
+    pub info: JobInfo,
+    pub result: Option<Value>,
+    pub transitions: Vec<Transition>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobInfo {
+    pub id: String,
+    pub name: String,
+    /// Parameters the job was submitted with.
+    pub params: Value,
+    pub status: JobStatus,
+    pub submitted_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+    /// Why a cancelled job was cancelled.
+    pub cancel_reason: Option<CancelReason>,
+    /// Panic of a job that failed by panicking.
+    pub panic: Option<PanicInfo>,
+    pub progress: Progress,
+    /// Estimated completion time of a running job.
+    pub eta: Option<DateTime<Utc>>,
+}
+
+struct JobRecord {
+    info: JobInfo,
+    progress: watch::Receiver<Progress>,
+    result: Option<Value>,
+    transitions: Vec<Transition>,
+}
+
+impl JobRecord {
+    fn snapshot(&self) -> JobInfo {
+        let progress = self.progress.borrow().clone();
+        let eta = match (self.info.status, self.info.started_at) {
+            (JobStatus::Running, Some(started_at)) => progress.eta(started_at, Utc::now()),
+            _ => None,
+        };
+
+        JobInfo {
+            progress,
+            eta,
+            ..self.info.clone()
+        }
+    }
+
+    fn entry(&self) -> JobEntry {
+        JobEntry {
+            info: self.info.clone(),
+            result: self.result.clone(),
+            transitions: self.transitions.clone(),
+        }
+    }
+
+    fn set_status(&mut self, status: JobStatus) -> DateTime<Utc> {
+        let at = Utc::now();
+        self.info.status = status;
+        self.transitions.push(Transition { status, at });
+        at
+    }
+
+    fn finish(&mut self, status: JobStatus, outcome: core::result::Result<Value, String>) {
+        self.info.finished_at = Some(self.set_status(status));
+        match outcome {
+            Ok(value) => self.result = Some(value),
+            Err(error) => self.info.error = Some(error),
+        }
+    }
+}
+
+struct Job {
+    record: Arc<Mutex<JobRecord>>,
+    task: CancellableTask<()>,
+}
+
+/// Manager of the background jobs.
+pub struct Tasks {
+    jobs: DashMap<String, Job>,
+    next_job: AtomicU64,
+    slots: Arc<Semaphore>,
+    history: Mutex<Option<mpsc::UnboundedSender<JobEntry>>>,
+    max_ended: usize,
+}
+
+impl Default for Tasks {
+    fn default() -> Self {
+        Self::new(MAX_RUNNING_JOBS)
+    }
+}
+
+impl Tasks {
+    pub fn new(max_running: usize) -> Self {
+        Self {
+            jobs: DashMap::new(),
+            next_job: AtomicU64::new(1),
+            slots: Arc::new(Semaphore::new(max_running)),
+            history: Mutex::new(None),
+            max_ended: MAX_ENDED_JOBS,
+        }
+    }
+
+    /// Like `new`, every status change of a job being also sent to the
+    /// returned receiver.
+    pub fn with_history(max_running: usize) -> (Self, mpsc::UnboundedReceiver<JobEntry>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let tasks = Self::new(max_running);
+        *tasks.history.lock().unwrap() = Some(tx);
+        (tasks, rx)
+    }
+
+    /// Numbers the next jobs after `last`, so that their ids differ from the
+    /// ones of a previous run of the app.
+    pub fn resume_ids(&self, last: u64) {
+        self.next_job.fetch_max(last + 1, Ordering::Relaxed);
+    }
+
+    /// Queues `job` and returns its id. The job is handed the token cancelled
+    /// by `cancel`, after which it is aborted at its next await point, and a
+    /// reporter whose updates are forwarded to `updates` at a bounded rate.
+    pub fn submit<F, Fut>(
+        &self,
+        name: String,
+        params: Value,
+        updates: Option<Channel<JobInfo>>,
+        job: F,
+    ) -> String
+    where
+        F: FnOnce(TaskContext) -> Fut + Send + 'static,
+        Fut: Future<Output = core::result::Result<Value, String>> + Send + 'static,
+    {
+        let id = format!("job-{}", self.next_job.fetch_add(1, Ordering::Relaxed));
+        let (reporter, mut progress) = ProgressReporter::new();
+        let mut record = JobRecord {
+            info: JobInfo {
+                id: id.clone(),
+                name,
+                params,
+                status: JobStatus::Queued,
+                submitted_at: Utc::now(),
+                started_at: None,
+                finished_at: None,
+                error: None,
+                cancel_reason: None,
+                panic: None,
+                progress: Progress::default(),
+                eta: None,
+            },
+            progress: progress.clone(),
+            result: None,
+            transitions: Vec::new(),
+        };
+        record.set_status(JobStatus::Queued);
+        let history = self.history.lock().unwrap().clone();
+        if let Some(history) = &history {
+            let _ = history.send(record.entry());
+        }
+        let record = Arc::new(Mutex::new(record));
+
+        let slots = self.slots.clone();
+        let job_record = record.clone();
+        let task = CancellableTask::new(move |token| async move {
+            let record = job_record;
+            let notify = |record: &JobRecord| {
+                if let Some(updates) = &updates {
+                    let _ = updates.send(record.snapshot());
+                }
+            };
+            let changed = |record: &JobRecord| {
+                notify(record);
+                if let Some(history) = &history {
+                    let _ = history.send(record.entry());
+                }
+            };
+
+            let permit = tokio::select! {
+                permit = slots.acquire_owned() => permit,
+                _ = token.cancelled() => {
+                    let mut record = record.lock().unwrap();
+                    record.info.cancel_reason = token.reason();
+                    record.finish(JobStatus::Cancelled, Err(cancelled(&token, " while queued")));
+                    changed(&record);
+                    return;
+                }
+            };
+            {
+                let mut record = record.lock().unwrap();
+                record.info.started_at = Some(record.set_status(JobStatus::Running));
+                changed(&record);
+            }
+
+            // Progress is sent at most every `PROGRESS_INTERVAL`, the watch
+            // only keeping the latest update meanwhile.
+            let forward = async {
+                while progress.changed().await.is_ok() {
+                    notify(&record.lock().unwrap());
+                    sleep(PROGRESS_INTERVAL).await;
+                }
+            };
+            tokio::pin!(forward);
+            let mut forwarding = updates.is_some();
+
+            // Spawned apart so that a panic fails the job instead of the manager.
+            let context = TaskContext {
+                token: token.clone(),
+                progress: reporter,
+            };
+            let mut handle = tokio::spawn(panics::catch(job(context)));
+            let (status, outcome) = loop {
+                tokio::select! {
+                    joined = &mut handle => break match joined {
+                        Ok(Ok(Ok(value))) => (JobStatus::Finished, Ok(value)),
+                        Ok(Ok(Err(error))) => (JobStatus::Failed, Err(error)),
+                        Ok(Err(panic)) => {
+                            let error = panic.to_string();
+                            record.lock().unwrap().info.panic = Some(panic);
+                            (JobStatus::Failed, Err(error))
+                        }
+                        Err(e) => (JobStatus::Failed, Err(join_failure(e))),
+                    },
+                    _ = token.cancelled() => {
+                        handle.abort();
+                        record.lock().unwrap().info.cancel_reason = token.reason();
+                        break (JobStatus::Cancelled, Err(cancelled(&token, "")));
+                    }
+                    // Ends once the job dropped its reporter.
+                    _ = &mut forward, if forwarding => forwarding = false,
+                }
+            };
+            drop(permit);
+
+            let mut record = record.lock().unwrap();
+            log::info!("Job '{}' ended as {:?}", record.info.id, status);
+            record.finish(status, outcome);
+            changed(&record);
+        });
+
+        self.evict();
+        self.jobs.insert(id.clone(), Job { record, task });
+        id
+    }
+
+    /// Forgets the jobs that ended first past `max_ended` ended jobs.
+    fn evict(&self) {
+        let mut ended: Vec<(DateTime<Utc>, String)> = self
+            .jobs
+            .iter()
+            .filter_map(|job| {
+                let record = job.record.lock().unwrap();
+                let ended_at = record.info.finished_at?;
+                (!record.info.status.is_active()).then(|| (ended_at, job.key().clone()))
+            })
+            .collect();
+        if ended.len() <= self.max_ended {
+            return;
+        }
+
+        ended.sort();
+        for (_, id) in &ended[..ended.len() - self.max_ended] {
+            let _ = self.remove(id);
+        }
+    }
+
+    pub fn info(&self, id: &str) -> Result<JobInfo> {
+        self.jobs
+            .get(id)
+            .map(|job| job.record.lock().unwrap().snapshot())
+            .ok_or_else(|| Error::JobNotFound { id: id.to_string() })
+    }
+
+    /// Every job, oldest first.
+    pub fn list(&self) -> Vec<JobInfo> {
+        let mut jobs: Vec<JobInfo> = self
+            .jobs
+            .iter()
+            .map(|job| job.record.lock().unwrap().snapshot())
+            .collect();
+        jobs.sort_by_key(|job| job.submitted_at);
+        jobs
+    }
+
+    pub fn cancel(&self, id: &str) -> Result<()> {
+        self.cancel_with(id, CancelReason::User)
+    }
+
+    pub fn cancel_with(&self, id: &str, reason: CancelReason) -> Result<()> {
+        let job = self
+            .jobs
+            .get(id)
+            .ok_or_else(|| Error::JobNotFound { id: id.to_string() })?;
+        if !job.record.lock().unwrap().info.status.is_active() {
+            return Err(Error::JobNotActive { id: id.to_string() });
+        }
+
+        job.task.cancel_with(reason);
+        Ok(())
+    }
+
+    /// Cancels the job with `CancelReason::Timeout` if it is still queued or
+    /// running after `timeout`.
+    pub fn set_deadline(&self, id: &str, timeout: Duration) -> Result<()> {
+        let job = self
+            .jobs
+            .get(id)
+            .ok_or_else(|| Error::JobNotFound { id: id.to_string() })?;
+        job.task.set_deadline(timeout);
+        Ok(())
+    }
+
+    /// Value returned by a finished job.
+    pub fn result(&self, id: &str) -> Result<Value> {
+        let job = self
+            .jobs
+            .get(id)
+            .ok_or_else(|| Error::JobNotFound { id: id.to_string() })?;
+        let record = job.record.lock().unwrap();
+
+        match (record.info.status, &record.result) {
+            (JobStatus::Finished, Some(result)) => Ok(result.clone()),
+            (status, _) if status.is_active() => Err(Error::JobNotFinished { id: id.to_string() }),
+            _ => Err(Error::JobFailed {
+                id: id.to_string(),
+                reason: record.info.error.clone().unwrap_or_default(),
+            }),
+        }
+    }
+
+    /// Forgets a job that is no longer queued or running.
+    pub fn remove(&self, id: &str) -> Result<JobInfo> {
+        let removed = self.jobs.remove_if(id, |_, job| {
+            !job.record.lock().unwrap().info.status.is_active()
+        });
+
+        match removed {
+            Some((_, job)) => Ok(job.record.lock().unwrap().snapshot()),
+            None if self.jobs.contains_key(id) => Err(Error::JobStillActive { id: id.to_string() }),
+            None => Err(Error::JobNotFound { id: id.to_string() }),
+        }
+    }
+
+    /// Cancels every job for the app exit and waits up to `timeout` for
+    /// them to end, then closes the history. Returns the ids of the jobs that
+    /// did not end in time.
+    pub async fn shutdown(&self, timeout: Duration) -> Vec<String> {
+        let ids: Vec<String> = self.jobs.iter().map(|job| job.key().clone()).collect();
+        let jobs: Vec<(String, CancellableTask<()>)> = ids
+            .iter()
+            .filter_map(|id| self.jobs.remove(id))
+            .map(|(id, job)| {
+                job.task.cancel_with(CancelReason::Shutdown);
+                (id, job.task)
+            })
+            .collect();
+
+        let late = join_all(jobs, timeout).await;
+        self.history.lock().unwrap().take();
+        late
+    }
 }
 
-pub type Tasks = Mutex<TasksInner>;
+/// Error of a job cancelled for the reason held by `token`.
+fn cancelled(token: &TaskToken, when: &str) -> String {
+    match token.reason() {
+        Some(reason) => format!("cancelled{} ({})", when, reason),
+        None => format!("cancelled{}", when),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tauri::ipc::InvokeResponseBody;
+    use tokio::time::{sleep, Duration};
+
+    async fn wait_for(tasks: &Tasks, id: &str, status: JobStatus) {
+        for _ in 0..100 {
+            if tasks.info(id).unwrap().status == status {
+                return;
+            }
+            sleep(Duration::from_millis(5)).await;
+        }
+        panic!("job {} never reached {:?}", id, status);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_cancels_jobs() {
+        let tasks = Tasks::new(1);
+        let running = tasks.submit(
+            "forever".to_string(),
+            Value::Null,
+            None,
+            |context| async move {
+                context.token.cancelled().await;
+                Ok(Value::Null)
+            },
+        );
+        let queued = tasks.submit("queued".to_string(), Value::Null, None, |_| async {
+            Ok(Value::Null)
+        });
+        wait_for(&tasks, &running, JobStatus::Running).await;
+        assert_eq!(tasks.info(&queued).unwrap().status, JobStatus::Queued);
+
+        let late = tasks.shutdown(Duration::from_millis(200)).await;
+        assert!(late.is_empty());
+        assert!(tasks.list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_ended_jobs_evicted() {
+        let tasks = Tasks {
+            max_ended: 2,
+            ..Tasks::new(1)
+        };
+        let mut ids = Vec::new();
+        for n in 0..3 {
+            let id = tasks.submit("n".to_string(), json!(n), None, move |_| async move {
+                Ok(json!(n))
+            });
+            wait_for(&tasks, &id, JobStatus::Finished).await;
+            ids.push(id);
+        }
+        let running = tasks.submit(
+            "forever".to_string(),
+            Value::Null,
+            None,
+            |context| async move {
+                context.token.cancelled().await;
+                Ok(Value::Null)
+            },
+        );
+
+        assert!(matches!(
+            tasks.info(&ids[0]),
+            Err(Error::JobNotFound { .. })
+        ));
+        assert_eq!(tasks.result(&ids[2]).unwrap(), json!(2));
+        assert_eq!(tasks.list().len(), 3);
+        tasks.cancel(&running).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_job_result() {
+        let tasks = Tasks::default();
+        let id = tasks.submit("sum".to_string(), json!([1, 2]), None, |_| async {
+            Ok(json!(3))
+        });
+
+        wait_for(&tasks, &id, JobStatus::Finished).await;
+        assert_eq!(tasks.result(&id).unwrap(), json!(3));
+        assert!(tasks.info(&id).unwrap().finished_at.is_some());
+        assert!(matches!(tasks.cancel(&id), Err(Error::JobNotActive { .. })));
+
+        tasks.remove(&id).unwrap();
+        assert!(matches!(tasks.info(&id), Err(Error::JobNotFound { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_failure_and_panic() {
+        let tasks = Tasks::default();
+        let failing = tasks.submit("failing".to_string(), Value::Null, None, |_| async {
+            Err("diverged".to_string())
+        });
+        let panicking = tasks.submit("panicking".to_string(), Value::Null, None, |_| async {
+            panic!("singular matrix")
+        });
+
+        wait_for(&tasks, &failing, JobStatus::Failed).await;
+        wait_for(&tasks, &panicking, JobStatus::Failed).await;
+        assert_eq!(
+            tasks.info(&failing).unwrap().error.as_deref(),
+            Some("diverged")
+        );
+        assert!(matches!(
+            tasks.result(&panicking),
+            Err(Error::JobFailed { .. })
+        ));
+        assert_eq!(
+            tasks.info(&panicking).unwrap().error.as_deref(),
+            Some("panicked: singular matrix")
+        );
+        assert_eq!(tasks.info(&failing).unwrap().panic, None);
+        let panic = tasks.info(&panicking).unwrap().panic.unwrap();
+        assert_eq!(panic.message, "singular matrix");
+        assert!(panic.location.unwrap().contains("state.rs"));
+    }
+
+    #[tokio::test]
+    async fn test_queue_and_cancel() {
+        let tasks = Tasks::new(1);
+        let forever = |_| async {
+            sleep(Duration::from_secs(60)).await;
+            Ok(Value::Null)
+        };
+        let running = tasks.submit("running".to_string(), Value::Null, None, forever);
+        let queued = tasks.submit("queued".to_string(), Value::Null, None, forever);
+
+        wait_for(&tasks, &running, JobStatus::Running).await;
+        assert_eq!(tasks.info(&queued).unwrap().status, JobStatus::Queued);
+        assert!(matches!(
+            tasks.result(&running),
+            Err(Error::JobNotFinished { .. })
+        ));
+        assert!(matches!(
+            tasks.remove(&running),
+            Err(Error::JobStillActive { .. })
+        ));
+
+        tasks.cancel(&running).unwrap();
+        wait_for(&tasks, &running, JobStatus::Cancelled).await;
+        wait_for(&tasks, &queued, JobStatus::Running).await;
+
+        tasks.cancel(&queued).unwrap();
+        wait_for(&tasks, &queued, JobStatus::Cancelled).await;
+        assert_eq!(
+            tasks
+                .list()
+                .iter()
+                .map(|j| j.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["running", "queued"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancel_reasons() {
+        let tasks = Tasks::new(1);
+        let forever = |_| async {
+            sleep(Duration::from_secs(60)).await;
+            Ok(Value::Null)
+        };
+        let slow = tasks.submit("slow".to_string(), Value::Null, None, forever);
+        let queued = tasks.submit("queued".to_string(), Value::Null, None, forever);
+        tasks
+            .set_deadline(&slow, Duration::from_millis(20))
+            .unwrap();
+
+        wait_for(&tasks, &slow, JobStatus::Cancelled).await;
+        let info = tasks.info(&slow).unwrap();
+        assert_eq!(info.cancel_reason, Some(CancelReason::Timeout));
+        assert_eq!(info.error.as_deref(), Some("cancelled (timeout)"));
+
+        wait_for(&tasks, &queued, JobStatus::Running).await;
+        tasks
+            .cancel_with(&queued, CancelReason::Superseded)
+            .unwrap();
+        wait_for(&tasks, &queued, JobStatus::Cancelled).await;
+        let info = tasks.info(&queued).unwrap();
+        assert_eq!(info.cancel_reason, Some(CancelReason::Superseded));
+        assert_eq!(info.error.as_deref(), Some("cancelled (superseded)"));
+    }
+
+    #[tokio::test]
+    async fn test_progress_updates() {
+        let tasks = Tasks::default();
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let updates_clone = updates.clone();
+        let channel = Channel::new(move |body| {
+            let InvokeResponseBody::Json(json) = body else {
+                panic!("expected a JSON job");
+            };
+            let info: Value = serde_json::from_str(&json).unwrap();
+            updates_clone.lock().unwrap().push((
+                info["status"].as_str().unwrap().to_string(),
+                info["progress"]["fraction"].as_f64().unwrap(),
+            ));
+            Ok(())
+        });
+
+        let id = tasks.submit(
+            "steps".to_string(),
+            Value::Null,
+            Some(channel),
+            |context| async move {
+                context.progress.stage("solving");
+                for step in 1..=10 {
+                    context.progress.set(step as f64 / 10.0);
+                    sleep(Duration::from_millis(10)).await;
+                }
+                Ok(Value::Null)
+            },
+        );
+
+        sleep(Duration::from_millis(50)).await;
+        let running = tasks.info(&id).unwrap();
+        assert_eq!(running.progress.stage.as_deref(), Some("solving"));
+        assert!(running.eta.is_some());
+        wait_for(&tasks, &id, JobStatus::Finished).await;
+
+        // Throttled to a couple of updates between the start and the end.
+        let updates = updates.lock().unwrap();
+        assert_eq!(updates.first().unwrap().0, "running");
+        assert_eq!(*updates.last().unwrap(), ("finished".to_string(), 1.0));
+        assert!(updates.len() < 6, "{:?}", updates);
+        assert_eq!(tasks.info(&id).unwrap().eta, None);
+    }
+}
@@ -0,0 +1,323 @@
+//! Restarts of long-running tasks such as network bridges, so that a
+//! transient error does not permanently kill a feed.
+
+use std::collections::VecDeque;
+use std::future::Future;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tokio::task::JoinError;
+use tokio::time::{sleep, timeout, Duration, Instant};
+
+use super::panics::{self, PanicInfo};
+use super::{CancellableTask, TaskToken};
+
+/// Time given to a child to end on cancellation before it is aborted.
+const STOP_GRACE: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestartPolicy {
+    Never,
+    #[default]
+    OnFailure,
+    /// Also restarts a child that ended on its own.
+    Always,
+}
+
+fn default_max_restarts() -> usize {
+    5
+}
+
+fn default_window_ms() -> u64 {
+    60_000
+}
+
+fn default_initial_backoff_ms() -> u64 {
+    100
+}
+
+fn default_max_backoff_ms() -> u64 {
+    30_000
+}
+
+/// Gives up after `max_restarts` within `window_ms`, waiting between restarts
+/// twice as long as before each time, from `initial_backoff_ms` up to
+/// `max_backoff_ms`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Supervision {
+    #[serde(default)]
+    pub policy: RestartPolicy,
+    #[serde(default = "default_max_restarts")]
+    pub max_restarts: usize,
+    #[serde(default = "default_window_ms")]
+    pub window_ms: u64,
+    #[serde(default = "default_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+}
+
+impl Default for Supervision {
+    fn default() -> Self {
+        Self {
+            policy: RestartPolicy::default(),
+            max_restarts: default_max_restarts(),
+            window_ms: default_window_ms(),
+            initial_backoff_ms: default_initial_backoff_ms(),
+            max_backoff_ms: default_max_backoff_ms(),
+        }
+    }
+}
+
+impl Supervision {
+    /// Wait before the restart following `recent` ones.
+    fn backoff(&self, recent: usize) -> Duration {
+        let factor = 2u64.saturating_pow(recent.min(63) as u32);
+        Duration::from_millis(
+            self.initial_backoff_ms
+                .saturating_mul(factor)
+                .min(self.max_backoff_ms),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SupervisorState {
+    Running,
+    BackingOff,
+    Stopped,
+    /// Too many restarts within the window.
+    GaveUp,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SupervisorStatus {
+    pub state: SupervisorState,
+    pub restarts: u64,
+    pub last_failure: Option<String>,
+    pub last_failure_at: Option<DateTime<Utc>>,
+    /// Panic of the last failure, if it was one.
+    pub last_panic: Option<PanicInfo>,
+}
+
+/// Failure reason of a child that did not return, with the panic message
+/// when there is one.
+pub fn join_failure(error: JoinError) -> String {
+    if !error.is_panic() {
+        return error.to_string();
+    }
+
+    format!("panicked: {}", panics::message(&*error.into_panic()))
+}
+
+pub struct Supervised {
+    pub task: CancellableTask<()>,
+    status: watch::Receiver<SupervisorStatus>,
+}
+
+impl Supervised {
+    pub fn status(&self) -> SupervisorStatus {
+        self.status.borrow().clone()
+    }
+
+    pub fn cancel(&self) {
+        self.task.cancel();
+    }
+}
+
+/// Runs the children built by `factory` one after the other, restarting
+/// them according to `supervision`. Each child is handed a token cancelled
+/// with the supervisor.
+pub fn supervise<F, Fut>(name: String, supervision: Supervision, mut factory: F) -> Supervised
+where
+    F: FnMut(TaskToken) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<(), String>> + Send + 'static,
+{
+    let (status_tx, status) = watch::channel(SupervisorStatus {
+        state: SupervisorState::Running,
+        restarts: 0,
+        last_failure: None,
+        last_failure_at: None,
+        last_panic: None,
+    });
+    let task = CancellableTask::new(move |token| async move {
+        let set_state = |state| status_tx.send_modify(|s| s.state = state);
+        let window = Duration::from_millis(supervision.window_ms);
+        let mut recent: VecDeque<Instant> = VecDeque::new();
+
+        loop {
+            set_state(SupervisorState::Running);
+            let child = token.child();
+            let mut handle = tokio::spawn(panics::catch(factory(child.clone())));
+
+            let mut panic = None;
+            let exit = tokio::select! {
+                joined = &mut handle => match joined {
+                    Ok(Ok(result)) => result,
+                    Ok(Err(caught)) => Err(panic.insert(caught).to_string()),
+                    Err(e) => Err(join_failure(e)),
+                },
+                _ = token.cancelled() => {
+                    child.cancel();
+                    if timeout(STOP_GRACE, &mut handle).await.is_err() {
+                        handle.abort();
+                    }
+                    set_state(SupervisorState::Stopped);
+                    return;
+                }
+            };
+
+            let restart = match (supervision.policy, &exit) {
+                (RestartPolicy::Never, _) => false,
+                (RestartPolicy::OnFailure, exit) => exit.is_err(),
+                (RestartPolicy::Always, _) => true,
+            };
+            if let Err(reason) = exit {
+                log::warn!("Supervised task '{}' failed: {}", name, reason);
+                status_tx.send_modify(|s| {
+                    s.last_failure = Some(reason);
+                    s.last_failure_at = Some(Utc::now());
+                    s.last_panic = panic;
+                });
+            }
+            if !restart {
+                set_state(SupervisorState::Stopped);
+                return;
+            }
+
+            let now = Instant::now();
+            recent.retain(|at| now.duration_since(*at) < window);
+            if recent.len() >= supervision.max_restarts {
+                log::error!(
+                    "Supervised task '{}' restarted {} times within {:?}, giving up",
+                    name,
+                    recent.len(),
+                    window
+                );
+                set_state(SupervisorState::GaveUp);
+                return;
+            }
+
+            let backoff = supervision.backoff(recent.len());
+            recent.push_back(now);
+            set_state(SupervisorState::BackingOff);
+            tokio::select! {
+                _ = sleep(backoff) => {}
+                _ = token.cancelled() => {
+                    set_state(SupervisorState::Stopped);
+                    return;
+                }
+            }
+            log::info!("Restarting supervised task '{}' after {:?}", name, backoff);
+            status_tx.send_modify(|s| s.restarts += 1);
+        }
+    });
+
+    Supervised { task, status }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    fn fast(policy: RestartPolicy, max_restarts: usize) -> Supervision {
+        Supervision {
+            policy,
+            max_restarts,
+            initial_backoff_ms: 1,
+            max_backoff_ms: 4,
+            ..Default::default()
+        }
+    }
+
+    async fn wait_for(supervised: &Supervised, state: SupervisorState) {
+        for _ in 0..200 {
+            if supervised.status().state == state {
+                return;
+            }
+            sleep(Duration::from_millis(5)).await;
+        }
+        panic!("supervisor never reached {:?}", state);
+    }
+
+    #[test]
+    fn test_backoff() {
+        let supervision = Supervision::default();
+        assert_eq!(supervision.backoff(0), Duration::from_millis(100));
+        assert_eq!(supervision.backoff(3), Duration::from_millis(800));
+        assert_eq!(supervision.backoff(100), Duration::from_secs(30));
+    }
+
+    #[tokio::test]
+    async fn test_restart_until_success() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let runs_clone = runs.clone();
+        let supervised = supervise(
+            "flaky".to_string(),
+            fast(RestartPolicy::OnFailure, 5),
+            move |_| {
+                let run = runs_clone.fetch_add(1, Ordering::Relaxed);
+                async move {
+                    match run {
+                        0 => Err("connection refused".to_string()),
+                        1 => panic!("bad frame"),
+                        _ => Ok(()),
+                    }
+                }
+            },
+        );
+
+        wait_for(&supervised, SupervisorState::Stopped).await;
+        let status = supervised.status();
+        assert_eq!(runs.load(Ordering::Relaxed), 3);
+        assert_eq!(status.restarts, 2);
+        assert_eq!(status.last_failure.as_deref(), Some("panicked: bad frame"));
+        let panic = status.last_panic.unwrap();
+        assert!(panic.location.unwrap().contains("supervisor.rs"));
+    }
+
+    #[tokio::test]
+    async fn test_give_up() {
+        let supervised = supervise(
+            "broken".to_string(),
+            fast(RestartPolicy::Always, 3),
+            |_| async { Ok(()) },
+        );
+
+        wait_for(&supervised, SupervisorState::GaveUp).await;
+        assert_eq!(supervised.status().restarts, 3);
+    }
+
+    #[tokio::test]
+    async fn test_never_and_cancel() {
+        let never = supervise(
+            "once".to_string(),
+            fast(RestartPolicy::Never, 5),
+            |_| async { Err("failed".to_string()) },
+        );
+        wait_for(&never, SupervisorState::Stopped).await;
+        assert_eq!(never.status().restarts, 0);
+
+        let cancelled = Arc::new(AtomicUsize::new(0));
+        let cancelled_clone = cancelled.clone();
+        let running = supervise("feed".to_string(), Supervision::default(), move |token| {
+            let cancelled = cancelled_clone.clone();
+            async move {
+                token.cancelled().await;
+                cancelled.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+        });
+        sleep(Duration::from_millis(10)).await;
+        running.cancel();
+        wait_for(&running, SupervisorState::Stopped).await;
+        assert_eq!(cancelled.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,548 @@
+//! Workflows of jobs run in the order of their dependencies, the outputs of
+//! the steps being passed on to the steps after them.
+
+use std::collections::{BTreeSet, HashMap};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tauri::ipc::Channel;
+use tokio::task::{self, JoinSet};
+
+use super::error::{Error, Result};
+use super::panics::{self, PanicInfo};
+use super::progress::TaskContext;
+use super::state::JobStatus;
+use super::supervisor::join_failure;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StepSpec {
+    pub id: String,
+    /// Job run by the step, as given to `submit_job`. Strings of the form
+    /// `${step}` or `${step/json/pointer}` are replaced by the output of that
+    /// step, or the part of it at the pointer, which makes it a dependency.
+    pub job: Value,
+    /// Steps to finish before this one, besides the referenced ones.
+    #[serde(default)]
+    pub after: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkflowSpec {
+    pub name: String,
+    pub steps: Vec<StepSpec>,
+}
+
+/// Validated workflow, with the dependencies of every step resolved.
+pub struct Plan {
+    steps: Vec<StepSpec>,
+    dependencies: Vec<Vec<usize>>,
+    dependents: Vec<Vec<usize>>,
+}
+
+impl Plan {
+    pub fn new(spec: &WorkflowSpec) -> Result<Self> {
+        let invalid = |reason: String| Err(Error::InvalidWorkflow(reason));
+        if spec.steps.is_empty() {
+            return invalid("a workflow needs at least one step".to_string());
+        }
+
+        let mut index = HashMap::new();
+        for (i, step) in spec.steps.iter().enumerate() {
+            if step.id.is_empty() || step.id.contains('/') {
+                return invalid(format!("invalid step id '{}'", step.id));
+            }
+            if index.insert(step.id.as_str(), i).is_some() {
+                return invalid(format!("duplicate step '{}'", step.id));
+            }
+        }
+
+        let mut dependencies = Vec::with_capacity(spec.steps.len());
+        let mut dependents = vec![Vec::new(); spec.steps.len()];
+        for (i, step) in spec.steps.iter().enumerate() {
+            let mut names: BTreeSet<&str> = step.after.iter().map(String::as_str).collect();
+            references(&step.job, &mut names);
+
+            let mut step_dependencies = Vec::with_capacity(names.len());
+            for name in names {
+                let Some(&j) = index.get(name) else {
+                    return invalid(format!(
+                        "step '{}' depends on unknown step '{}'",
+                        step.id, name
+                    ));
+                };
+                if j == i {
+                    return invalid(format!("step '{}' depends on itself", step.id));
+                }
+                step_dependencies.push(j);
+                dependents[j].push(i);
+            }
+            dependencies.push(step_dependencies);
+        }
+
+        // The steps of a cycle never get all their dependencies done.
+        let mut pending: Vec<usize> = dependencies.iter().map(Vec::len).collect();
+        let mut ready: Vec<usize> = (0..pending.len()).filter(|&i| pending[i] == 0).collect();
+        while let Some(i) = ready.pop() {
+            for &j in &dependents[i] {
+                pending[j] -= 1;
+                if pending[j] == 0 {
+                    ready.push(j);
+                }
+            }
+        }
+        let cycle: Vec<&str> = (0..pending.len())
+            .filter(|&i| pending[i] > 0)
+            .map(|i| spec.steps[i].id.as_str())
+            .collect();
+        if !cycle.is_empty() {
+            return invalid(format!("dependency cycle between {}", cycle.join(", ")));
+        }
+
+        Ok(Self {
+            steps: spec.steps.clone(),
+            dependencies,
+            dependents,
+        })
+    }
+}
+
+/// Step and JSON pointer of a `${step/pointer}` reference.
+fn reference(s: &str) -> Option<(&str, &str)> {
+    let inner = s.strip_prefix("${")?.strip_suffix('}')?;
+    Some(match inner.find('/') {
+        Some(i) => inner.split_at(i),
+        None => (inner, ""),
+    })
+}
+
+fn references<'a>(value: &'a Value, names: &mut BTreeSet<&'a str>) {
+    match value {
+        Value::String(s) => {
+            if let Some((step, _)) = reference(s) {
+                names.insert(step);
+            }
+        }
+        Value::Array(items) => items.iter().for_each(|item| references(item, names)),
+        Value::Object(map) => map.values().for_each(|item| references(item, names)),
+        _ => {}
+    }
+}
+
+/// `value` with its references replaced by the outputs they point to.
+fn resolve(value: &Value, outputs: &HashMap<String, Value>) -> core::result::Result<Value, String> {
+    match value {
+        Value::String(s) => match reference(s) {
+            Some((step, pointer)) => outputs
+                .get(step)
+                .and_then(|output| output.pointer(pointer))
+                .cloned()
+                .ok_or_else(|| format!("output of step '{}' has nothing at '{}'", step, pointer)),
+            None => Ok(value.clone()),
+        },
+        Value::Array(items) => items
+            .iter()
+            .map(|item| resolve(item, outputs))
+            .collect::<core::result::Result<_, _>>()
+            .map(Value::Array),
+        Value::Object(map) => map
+            .iter()
+            .map(|(key, item)| Ok((key.clone(), resolve(item, outputs)?)))
+            .collect::<core::result::Result<Map<_, _>, String>>()
+            .map(Value::Object),
+        _ => Ok(value.clone()),
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StepInfo {
+    pub id: String,
+    /// `queued` while waiting for its dependencies.
+    pub status: JobStatus,
+    /// Steps this one waits for.
+    pub after: Vec<String>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+    /// Panic of a step that failed by panicking.
+    pub panic: Option<PanicInfo>,
+}
+
+/// Status of the steps of a run, shared by the run and the registry. Every
+/// change is sent to `updates`.
+#[derive(Clone)]
+pub struct Steps {
+    steps: Arc<Mutex<Vec<StepInfo>>>,
+    updates: Option<Channel<StepInfo>>,
+}
+
+impl Steps {
+    pub fn new(plan: &Plan, updates: Option<Channel<StepInfo>>) -> Self {
+        let steps = plan
+            .steps
+            .iter()
+            .zip(&plan.dependencies)
+            .map(|(step, dependencies)| StepInfo {
+                id: step.id.clone(),
+                status: JobStatus::Queued,
+                after: dependencies
+                    .iter()
+                    .map(|&j| plan.steps[j].id.clone())
+                    .collect(),
+                started_at: None,
+                finished_at: None,
+                error: None,
+                panic: None,
+            })
+            .collect();
+
+        Self {
+            steps: Arc::new(Mutex::new(steps)),
+            updates,
+        }
+    }
+
+    pub fn snapshot(&self) -> Vec<StepInfo> {
+        self.steps.lock().unwrap().clone()
+    }
+
+    fn update(&self, i: usize, change: impl FnOnce(&mut StepInfo)) {
+        let mut steps = self.steps.lock().unwrap();
+        change(&mut steps[i]);
+        if let Some(updates) = &self.updates {
+            let _ = updates.send(steps[i].clone());
+        }
+    }
+
+    fn finish(&self, i: usize, status: JobStatus, error: Option<String>) {
+        self.update(i, |step| {
+            step.status = status;
+            step.finished_at = Some(Utc::now());
+            step.error = error;
+        });
+    }
+
+    /// Marks the steps still queued or running as cancelled.
+    fn cancel_active(&self) {
+        let active: Vec<usize> = self
+            .steps
+            .lock()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .filter(|(_, step)| step.status.is_active())
+            .map(|(i, _)| i)
+            .collect();
+        for i in active {
+            self.finish(i, JobStatus::Cancelled, None);
+        }
+    }
+}
+
+/// Cancels the steps left when a run ends early, including when its job is
+/// aborted.
+struct CancelOnDrop<'a>(&'a Steps);
+
+impl Drop for CancelOnDrop<'_> {
+    fn drop(&mut self) {
+        self.0.cancel_active();
+    }
+}
+
+/// Runs each step of `plan` through `run` as soon as its dependencies are
+/// finished, independent branches in parallel. Returns the outputs of the
+/// steps by id, or the failure of the first failing step after which the
+/// other ones are cancelled.
+pub async fn execute<F, Fut>(
+    plan: &Plan,
+    steps: &Steps,
+    context: TaskContext,
+    run: F,
+) -> core::result::Result<Value, String>
+where
+    F: Fn(Value) -> Fut,
+    Fut: Future<Output = core::result::Result<Value, String>> + Send + 'static,
+{
+    let _cancel = CancelOnDrop(steps);
+    let mut pending: Vec<usize> = plan.dependencies.iter().map(Vec::len).collect();
+    let mut ready: Vec<usize> = (0..pending.len()).filter(|&i| pending[i] == 0).collect();
+    let mut outputs = HashMap::new();
+    let mut running = JoinSet::new();
+    let mut tasks: HashMap<task::Id, usize> = HashMap::new();
+
+    loop {
+        for i in ready.drain(..) {
+            let job = match resolve(&plan.steps[i].job, &outputs) {
+                Ok(job) => job,
+                Err(error) => {
+                    steps.finish(i, JobStatus::Failed, Some(error.clone()));
+                    return Err(format!("step '{}' failed: {}", plan.steps[i].id, error));
+                }
+            };
+            steps.update(i, |step| {
+                step.status = JobStatus::Running;
+                step.started_at = Some(Utc::now());
+            });
+            tasks.insert(running.spawn(panics::catch(run(job))).id(), i);
+        }
+
+        let running_ids: Vec<&str> = tasks.values().map(|&i| plan.steps[i].id.as_str()).collect();
+        context
+            .progress
+            .set(outputs.len() as f64 / plan.steps.len() as f64);
+        if !running_ids.is_empty() {
+            context
+                .progress
+                .message(format!("running {}", running_ids.join(", ")));
+        }
+
+        let joined = tokio::select! {
+            joined = running.join_next_with_id() => joined,
+            _ = context.token.cancelled() => return Err("cancelled".to_string()),
+        };
+        let Some(joined) = joined else {
+            break;
+        };
+        let (i, outcome) = match joined {
+            Ok((id, Ok(outcome))) => (tasks.remove(&id), outcome),
+            Ok((id, Err(panic))) => {
+                let i = tasks.remove(&id);
+                let error = panic.to_string();
+                if let Some(i) = i {
+                    steps.update(i, |step| step.panic = Some(panic));
+                }
+                (i, Err(error))
+            }
+            Err(e) => (tasks.remove(&e.id()), Err(join_failure(e))),
+        };
+        let Some(i) = i else {
+            continue;
+        };
+
+        match outcome {
+            Ok(output) => {
+                steps.finish(i, JobStatus::Finished, None);
+                outputs.insert(plan.steps[i].id.clone(), output);
+                for &j in &plan.dependents[i] {
+                    pending[j] -= 1;
+                    if pending[j] == 0 {
+                        ready.push(j);
+                    }
+                }
+            }
+            Err(error) => {
+                steps.finish(i, JobStatus::Failed, Some(error.clone()));
+                return Err(format!("step '{}' failed: {}", plan.steps[i].id, error));
+            }
+        }
+    }
+
+    Ok(Value::Object(outputs.into_iter().collect()))
+}
+
+/// Steps of the workflow runs, by id of the job running them.
+#[derive(Default)]
+pub struct Workflows {
+    runs: DashMap<String, Steps>,
+}
+
+impl Workflows {
+    pub fn insert(&self, id: String, steps: Steps) {
+        self.runs.insert(id, steps);
+    }
+
+    pub fn steps(&self, id: &str) -> Result<Vec<StepInfo>> {
+        self.runs
+            .get(id)
+            .map(|steps| steps.snapshot())
+            .ok_or_else(|| Error::WorkflowRunNotFound { id: id.to_string() })
+    }
+
+    pub fn remove(&self, id: &str) {
+        self.runs.remove(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::tasks::{progress::ProgressReporter, TaskToken};
+    use serde_json::json;
+    use tokio::time::{sleep, Duration};
+
+    fn step(id: &str, job: Value, after: &[&str]) -> StepSpec {
+        StepSpec {
+            id: id.to_string(),
+            job,
+            after: after.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    fn workflow(steps: Vec<StepSpec>) -> WorkflowSpec {
+        WorkflowSpec {
+            name: "study".to_string(),
+            steps,
+        }
+    }
+
+    fn context() -> TaskContext {
+        TaskContext {
+            token: TaskToken::new(),
+            progress: ProgressReporter::new().0,
+        }
+    }
+
+    fn statuses(steps: &Steps) -> Vec<JobStatus> {
+        steps.snapshot().iter().map(|step| step.status).collect()
+    }
+
+    /// Waits `delay_ms` then returns `output`, or fails with `fail`.
+    async fn fake(job: Value) -> core::result::Result<Value, String> {
+        sleep(Duration::from_millis(job["delay_ms"].as_u64().unwrap_or(0))).await;
+        if let Some(message) = job["panic"].as_str() {
+            panic!("{}", message);
+        }
+        match job.get("fail") {
+            Some(reason) => Err(reason.to_string()),
+            None => Ok(job["output"].clone()),
+        }
+    }
+
+    #[test]
+    fn test_plan_validation() {
+        let invalid = |steps| matches!(Plan::new(&workflow(steps)), Err(Error::InvalidWorkflow(_)));
+
+        assert!(invalid(vec![]));
+        assert!(invalid(vec![
+            step("a", json!({}), &[]),
+            step("a", json!({}), &[])
+        ]));
+        assert!(invalid(vec![step("a", json!({}), &["b"])]));
+        assert!(invalid(vec![step("a", json!("${a}"), &[])]));
+        assert!(invalid(vec![
+            step("a", json!({}), &["c"]),
+            step("b", json!({}), &["a"]),
+            step("c", json!({"input": "${b/path}"}), &[]),
+        ]));
+
+        let plan = Plan::new(&workflow(vec![
+            step("import", json!({}), &[]),
+            step("validate", json!({"network": "${import/path}"}), &[]),
+        ]))
+        .unwrap();
+        assert_eq!(plan.dependencies, vec![vec![], vec![0]]);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let outputs = HashMap::from([(
+            "import".to_string(),
+            json!({"path": "net.xiidm", "buses": 14}),
+        )]);
+
+        let job = json!({"path": "${import/path}", "all": ["${import}"], "literal": "$import"});
+        assert_eq!(
+            resolve(&job, &outputs).unwrap(),
+            json!({"path": "net.xiidm", "all": [{"path": "net.xiidm", "buses": 14}], "literal": "$import"})
+        );
+        assert!(resolve(&json!("${import/lines}"), &outputs).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_branches_run_in_parallel() {
+        let plan = Plan::new(&workflow(vec![
+            step("import", json!({"output": {"path": "net.xiidm"}}), &[]),
+            step(
+                "load_flow",
+                json!({"delay_ms": 50, "output": "${import/path}"}),
+                &[],
+            ),
+            step(
+                "contingencies",
+                json!({"delay_ms": 50, "output": 3}),
+                &["import"],
+            ),
+            step(
+                "report",
+                json!({"output": ["${load_flow}", "${contingencies}"]}),
+                &[],
+            ),
+        ]))
+        .unwrap();
+        let steps = Steps::new(&plan, None);
+
+        let outputs = execute(&plan, &steps, context(), fake).await.unwrap();
+        assert_eq!(outputs["report"], json!(["net.xiidm", 3]));
+        assert_eq!(statuses(&steps), vec![JobStatus::Finished; 4]);
+        let snapshot = steps.snapshot();
+        assert_eq!(snapshot[3].after, vec!["contingencies", "load_flow"]);
+
+        // Each branch started before the other one finished.
+        let (load_flow, contingencies) = (&snapshot[1], &snapshot[2]);
+        assert!(load_flow.started_at.unwrap() < contingencies.finished_at.unwrap());
+        assert!(contingencies.started_at.unwrap() < load_flow.finished_at.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_failure_cancels_the_run() {
+        let plan = Plan::new(&workflow(vec![
+            step("validate", json!({"fail": "islanded bus"}), &[]),
+            step("slow", json!({"delay_ms": 1000}), &[]),
+            step("report", json!({}), &["validate"]),
+        ]))
+        .unwrap();
+        let steps = Steps::new(&plan, None);
+
+        let error = execute(&plan, &steps, context(), fake).await.unwrap_err();
+        assert!(error.contains("validate"));
+        assert_eq!(
+            statuses(&steps),
+            vec![
+                JobStatus::Failed,
+                JobStatus::Cancelled,
+                JobStatus::Cancelled
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_panicking_step() {
+        let plan = Plan::new(&workflow(vec![step(
+            "solve",
+            json!({"panic": "singular matrix"}),
+            &[],
+        )]))
+        .unwrap();
+        let steps = Steps::new(&plan, None);
+
+        let error = execute(&plan, &steps, context(), fake).await.unwrap_err();
+        assert_eq!(error, "step 'solve' failed: panicked: singular matrix");
+        let step = &steps.snapshot()[0];
+        assert_eq!(step.status, JobStatus::Failed);
+        assert_eq!(step.panic.as_ref().unwrap().message, "singular matrix");
+    }
+
+    #[tokio::test]
+    async fn test_cancel() {
+        let plan = Plan::new(&workflow(vec![step(
+            "slow",
+            json!({"delay_ms": 1000}),
+            &[],
+        )]))
+        .unwrap();
+        let steps = Steps::new(&plan, None);
+        let context = context();
+        let token = context.token.clone();
+
+        tokio::spawn(async move {
+            sleep(Duration::from_millis(20)).await;
+            token.cancel();
+        });
+        let error = execute(&plan, &steps, context, fake).await.unwrap_err();
+        assert_eq!(error, "cancelled");
+        assert_eq!(statuses(&steps), vec![JobStatus::Cancelled]);
+    }
+}